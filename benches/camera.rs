@@ -0,0 +1,75 @@
+//! Camera rays through the pixels of a small image, for each camera model.
+
+use criterion::{black_box, criterion_group, criterion_main, Criterion, Throughput};
+use nalgebra::Vector2;
+use rtow::camera::{CameraSettings, Projection};
+use rtow::float::Float;
+use rtow::sampler::SamplerKind;
+use rtow::sphere::Sphere;
+
+const WIDTH: u32 = 32;
+const HEIGHT: u32 = 18;
+const SEED: u64 = 7;
+
+fn cameras() -> Vec<(&'static str, CameraSettings)> {
+    let projection = |projection| CameraSettings {
+        projection: Some(projection),
+        ..CameraSettings::default()
+    };
+    vec![
+        (
+            "pinhole",
+            CameraSettings {
+                aperture: Some(0.),
+                ..CameraSettings::default()
+            },
+        ),
+        ("thin lens", CameraSettings::default()),
+        (
+            "polygonal aperture",
+            CameraSettings {
+                blades: Some(6),
+                cat_eye: Some(0.5),
+                ..CameraSettings::default()
+            },
+        ),
+        ("orthographic", projection(Projection::Orthographic)),
+        ("fisheye", projection(Projection::FisheyeEquisolid)),
+        ("equirectangular", projection(Projection::Equirectangular)),
+        (
+            "tilt-shift",
+            CameraSettings {
+                tilt: Some(5.),
+                shift: Some(Vector2::new(0.1, 0.)),
+                ..projection(Projection::TiltShift)
+            },
+        ),
+    ]
+}
+
+fn get_ray(c: &mut Criterion) {
+    let mut group = c.benchmark_group("camera");
+    group.throughput(Throughput::Elements((WIDTH * HEIGHT) as u64));
+    for (name, settings) in cameras() {
+        let camera = settings
+            .build(&Vec::<Sphere>::new(), WIDTH as Float / HEIGHT as Float)
+            .unwrap();
+        let mut sampler = SamplerKind::Independent.create(1, SEED);
+        group.bench_function(name, |b| {
+            b.iter(|| {
+                for y in 0..HEIGHT {
+                    for x in 0..WIDTH {
+                        sampler.start_pixel_sample(x, y, 0);
+                        let u = (x as Float + 0.5) / WIDTH as Float;
+                        let v = (y as Float + 0.5) / HEIGHT as Float;
+                        black_box(camera.get_ray(u, v, sampler.as_mut()));
+                    }
+                }
+            })
+        });
+    }
+    group.finish();
+}
+
+criterion_group!(benches, get_ray);
+criterion_main!(benches);
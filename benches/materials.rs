@@ -0,0 +1,196 @@
+//! Sampling the scattered direction off each variant of `Material`, seen from a few directions.
+
+use criterion::{black_box, criterion_group, criterion_main, Criterion, Throughput};
+use nalgebra::{Point3, Unit, Vector2, Vector3};
+use rtow::color::{ColorSpace, Encoding};
+use rtow::float::Float;
+use rtow::hitable::HitPoint;
+use rtow::material::{Bsdf, Material};
+use rtow::sampler::SamplerKind;
+use rtow::spectrum::{Conductor, ThinFilm, Wavelengths};
+use rtow::texture::{ImageTexture, Pattern, Texture};
+use std::path::Path;
+
+const SEED: u64 = 7;
+const SAMPLES: u32 = 256;
+
+fn gray() -> Vector3<Float> {
+    Vector3::new(0.5, 0.5, 0.5)
+}
+
+fn lambertian() -> Box<Material> {
+    Box::new(Material::Lambertian {
+        attenuation: gray(),
+    })
+}
+
+/// A normal map which tilts the normal vector the same way everywhere.
+fn normal_map() -> ImageTexture {
+    let path = Path::new(env!("CARGO_TARGET_TMPDIR")).join("bench_normal_map.png");
+    image::RgbImage::from_pixel(1, 1, image::Rgb([160, 110, 240]))
+        .save(&path)
+        .expect("the target directory is writable");
+    ImageTexture::load(&path).expect("the normal map was just written")
+}
+
+fn label() -> ImageTexture {
+    let path = Path::new(env!("CARGO_TARGET_TMPDIR")).join("bench_label.png");
+    image::RgbImage::from_fn(4, 4, |x, y| image::Rgb([60 * x as u8, 60 * y as u8, 200]))
+        .save(&path)
+        .expect("the target directory is writable");
+    ImageTexture::load(&path).expect("the label was just written")
+}
+
+/// One material of each variant.
+fn materials() -> Vec<(&'static str, Material)> {
+    vec![
+        ("lambertian", *lambertian()),
+        (
+            "metal",
+            Material::Metal {
+                attenuation: gray(),
+                fuzziness: 0.3,
+                conductor: None,
+            },
+        ),
+        (
+            "microfacet",
+            Material::Microfacet {
+                attenuation: gray(),
+                roughness: 0.3,
+                anisotropy: 0.5,
+                conductor: Some(Conductor::Gold),
+                thin_film: Some(ThinFilm {
+                    thickness: 300.,
+                    refractive_index: 1.4,
+                }),
+            },
+        ),
+        (
+            "dielectric",
+            Material::Dielectric {
+                refractive_index: 1.5,
+                abbe_number: None,
+                thin_film: None,
+            },
+        ),
+        (
+            "subsurface",
+            Material::Subsurface {
+                refractive_index: 1.4,
+                mean_free_path: Vector3::new(0.1, 0.1, 0.1),
+                albedo: gray(),
+                anisotropy: 0.,
+            },
+        ),
+        (
+            "mix",
+            Material::Mix {
+                first: lambertian(),
+                second: Box::new(Material::Metal {
+                    attenuation: gray(),
+                    fuzziness: 0.,
+                    conductor: None,
+                }),
+                weight: Texture::Constant(0.3),
+            },
+        ),
+        (
+            "clear coat",
+            Material::ClearCoat {
+                base: lambertian(),
+                refractive_index: 1.5,
+            },
+        ),
+        (
+            "two sided",
+            Material::TwoSided {
+                front: lambertian(),
+                back: lambertian(),
+            },
+        ),
+        (
+            "bump",
+            Material::Bump {
+                base: lambertian(),
+                height: Texture::Pattern(Pattern::Checker { scale: 8. }),
+                scale: 0.1,
+            },
+        ),
+        (
+            "normal map",
+            Material::NormalMap {
+                base: lambertian(),
+                map: normal_map(),
+            },
+        ),
+        (
+            "cutout",
+            Material::Cutout {
+                base: lambertian(),
+                opacity: Texture::Constant(0.5),
+            },
+        ),
+        (
+            "textured",
+            Material::Textured {
+                base: lambertian(),
+                image: label(),
+                encoding: Encoding::Srgb,
+            },
+        ),
+    ]
+}
+
+/// A hit point on the plane `z = 0`.
+fn hitpoint(material: &Material) -> HitPoint<'_> {
+    HitPoint {
+        t: 1.,
+        p: Point3::origin(),
+        p_error: Vector3::zeros(),
+        normal: Unit::new_unchecked(Vector3::z()),
+        geometric_normal: Unit::new_unchecked(Vector3::z()),
+        uv: Vector2::new(0.3, 0.6),
+        dpdu: Vector3::x(),
+        dpdv: Vector3::y(),
+        material,
+    }
+}
+
+fn sample(c: &mut Criterion) {
+    let directions: Vec<Vector3<Float>> = [0.1 as Float, 0.7, 1.3]
+        .iter()
+        .map(|theta| Vector3::new(theta.sin(), 0.3 * theta.sin(), theta.cos()).normalize())
+        .collect();
+
+    let mut group = c.benchmark_group("material");
+    group.throughput(Throughput::Elements(
+        SAMPLES as u64 * directions.len() as u64,
+    ));
+    for (name, material) in materials() {
+        let hitpoint = hitpoint(&material);
+        let mut sampler = SamplerKind::Independent.create(SAMPLES, SEED);
+        group.bench_function(name, |b| {
+            b.iter(|| {
+                for wo in &directions {
+                    for index in 0..SAMPLES {
+                        sampler.start_pixel_sample(0, 0, index);
+                        let mut wavelengths = Wavelengths::Rgb {
+                            space: ColorSpace::default(),
+                        };
+                        black_box(material.sample(
+                            wo,
+                            &hitpoint,
+                            &mut wavelengths,
+                            sampler.as_mut(),
+                        ));
+                    }
+                }
+            })
+        });
+    }
+    group.finish();
+}
+
+criterion_group!(benches, sample);
+criterion_main!(benches);
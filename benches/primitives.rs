@@ -0,0 +1,108 @@
+//! Intersection tests: a single sphere, and the spheres of the default scene searched one by one
+//! (`Hitable for Vec<T>`) and through the bounding volume hierarchy of the `Scene`.
+
+use criterion::{black_box, criterion_group, criterion_main, BenchmarkId, Criterion, Throughput};
+use nalgebra::{Point3, Vector3};
+use rand::rngs::StdRng;
+use rand::{Rng, SeedableRng};
+use rtow::camera::CameraSettings;
+use rtow::float::Float;
+use rtow::hitable::Hitable;
+use rtow::material::Material;
+use rtow::ray::Ray;
+use rtow::sampler::SamplerKind;
+use rtow::sphere::Sphere;
+use rtow::Scene;
+
+const SEED: u64 = 7;
+const RAYS: usize = 1024;
+
+/// Rays from around the unit sphere at the origin towards points near it, about half of which
+/// hit it.
+fn rays_around_unit_sphere() -> Vec<Ray> {
+    let mut rng = StdRng::seed_from_u64(SEED);
+    let mut direction = || -> Vector3<Float> {
+        Vector3::new(
+            rng.gen_range(-1.0..1.0),
+            rng.gen_range(-1.0..1.0),
+            rng.gen_range(-1.0..1.0),
+        )
+        .normalize()
+    };
+    (0..RAYS)
+        .map(|_| {
+            let origin = Point3::from(4. * direction());
+            let target = 1.4 * direction();
+            Ray::new(origin, target - origin.coords)
+        })
+        .collect()
+}
+
+/// The rays through the pixels of a `RAYS` pixel image of the scene.
+fn camera_rays(scene: &Scene) -> Vec<Ray> {
+    let mut sampler = SamplerKind::Independent.create(1, SEED);
+    let width = 32;
+    (0..RAYS)
+        .filter_map(|i| {
+            let (x, y) = ((i % width) as u32, (i / width) as u32);
+            sampler.start_pixel_sample(x, y, 0);
+            let u = (x as Float + 0.5) / width as Float;
+            let v = (y as Float + 0.5) / (RAYS / width) as Float;
+            scene.camera().get_ray(u, v, sampler.as_mut())
+        })
+        .collect()
+}
+
+fn sphere_hit(c: &mut Criterion) {
+    let sphere = Sphere::new(
+        Point3::origin(),
+        1.,
+        Material::Lambertian {
+            attenuation: Vector3::new(0.5, 0.5, 0.5),
+        },
+    );
+    let rays = rays_around_unit_sphere();
+
+    let mut group = c.benchmark_group("sphere");
+    group.throughput(Throughput::Elements(rays.len() as u64));
+    group.bench_function("hit", |b| {
+        b.iter(|| {
+            for ray in &rays {
+                black_box(sphere.hit(black_box(ray), 0., Float::INFINITY));
+            }
+        })
+    });
+    group.finish();
+}
+
+/// The spheres of the default scene, searched by the camera rays.
+fn list_and_bvh(c: &mut Criterion) {
+    let mut group = c.benchmark_group("world");
+    for object_count in [10, 100, 500] {
+        let scene =
+            Scene::random(object_count, SEED, &CameraSettings::default(), 16. / 9.).unwrap();
+        scene.prepare();
+        let rays = camera_rays(&scene);
+        group.throughput(Throughput::Elements(rays.len() as u64));
+
+        let list = scene.objects();
+        group.bench_with_input(BenchmarkId::new("list", object_count), &rays, |b, rays| {
+            b.iter(|| {
+                for ray in rays {
+                    black_box(list.hit(ray, 0., Float::INFINITY));
+                }
+            })
+        });
+        group.bench_with_input(BenchmarkId::new("bvh", object_count), &rays, |b, rays| {
+            b.iter(|| {
+                for ray in rays {
+                    black_box(scene.hit(ray, 0., Float::INFINITY));
+                }
+            })
+        });
+    }
+    group.finish();
+}
+
+criterion_group!(benches, sphere_hit, list_and_bvh);
+criterion_main!(benches);
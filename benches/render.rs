@@ -0,0 +1,54 @@
+//! Renders of the default scene at a low resolution, with camera rays traced in packets and one
+//! by one.
+//!
+//! The precision is chosen at compile time, so compare single with double precision by saving a
+//! baseline with one and comparing the other against it (the ground of the random scene is
+//! smaller in single precision, see `Scene::random`):
+//!
+//! ```text
+//! cargo bench --bench render -- --save-baseline f64
+//! cargo bench --bench render --features f32 -- --baseline f64
+//! ```
+
+use criterion::{criterion_group, criterion_main, Criterion};
+use rtow::camera::CameraSettings;
+use rtow::float::Float;
+use rtow::{Renderer, Resolution, Scene};
+
+const WIDTH: u32 = 160;
+const HEIGHT: u32 = 90;
+const SEED: u64 = 7;
+
+fn default_scene(c: &mut Criterion) {
+    let scene = Scene::random(
+        500,
+        SEED,
+        &CameraSettings::default(),
+        WIDTH as Float / HEIGHT as Float,
+    )
+    .unwrap();
+    let renderer = |packets: bool| {
+        Renderer::new(Resolution {
+            width: WIDTH,
+            height: HEIGHT,
+        })
+        .samples(4)
+        .seed(SEED)
+        .packets(packets)
+    };
+
+    let mut group = c.benchmark_group("render");
+    group.sample_size(10);
+    let mut packets = renderer(true);
+    group.bench_function("default scene", |b| {
+        b.iter(|| packets.render(&scene).unwrap())
+    });
+    let mut scalar = renderer(false);
+    group.bench_function("default scene, scalar", |b| {
+        b.iter(|| scalar.render(&scene).unwrap())
+    });
+    group.finish();
+}
+
+criterion_group!(benches, default_scene);
+criterion_main!(benches);
@@ -0,0 +1,427 @@
+use crate::camera::{CameraSettings, Focus};
+use crate::error::{Error, Result};
+use crate::float::Float;
+use nalgebra::{Point3, UnitQuaternion, Vector3};
+use serde::Deserialize;
+use std::ops::{Add, Mul, Sub};
+
+/// How a value changes between a keyframe and the next one.
+///
+/// * `Step`: keeps the value of the keyframe until the next one.
+/// * `Linear`: moves at constant speed.
+/// * `Bezier`: follows a smooth cubic Bezier curve, whose control points are chosen so that the
+///   motion does not change speed abruptly at the keyframes (a Catmull-Rom spline).
+/// * `Slerp`: for camera orientations, rotates at constant angular speed (spherical linear
+///   interpolation). Behaves like `Linear` for everything else.
+///
+/// The rotations of objects are always slerped, unless the interpolation is `Step`.
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Interpolation {
+    Step,
+    #[default]
+    Linear,
+    Bezier,
+    Slerp,
+}
+
+/// Anything that can be interpolated between keyframes.
+pub trait Animatable:
+    Copy + Add<Output = Self> + Sub<Output = Self> + Mul<Float, Output = Self>
+{
+}
+
+impl<T> Animatable for T where T: Copy + Add<Output = T> + Sub<Output = T> + Mul<Float, Output = T> {}
+
+/// A value at a given frame, along with the interpolation used up to the next keyframe.
+#[derive(Copy, Clone, Debug)]
+pub struct Keyframe<T> {
+    pub frame: Float,
+    pub value: T,
+    pub interpolation: Interpolation,
+}
+
+/// A sequence of keyframes of a single animated value, sorted by frame. Before the first keyframe
+/// and after the last one, the value stays constant.
+#[derive(Clone, Debug)]
+pub struct Track<T> {
+    keyframes: Vec<Keyframe<T>>,
+}
+
+/// The position of a frame on a track: between keyframes `index` and `index + 1`, at a fraction
+/// `t` of the way.
+struct Segment {
+    index: usize,
+    t: Float,
+}
+
+impl<T> Track<T> {
+    pub fn new(mut keyframes: Vec<Keyframe<T>>) -> Track<T> {
+        keyframes.sort_by(|a, b| a.frame.total_cmp(&b.frame));
+        Track { keyframes }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.keyframes.is_empty()
+    }
+
+    fn segment(&self, frame: Float) -> Option<Segment> {
+        let first = self.keyframes.first()?;
+        let last = self.keyframes.last()?;
+
+        if frame <= first.frame {
+            return Some(Segment { index: 0, t: 0. });
+        }
+        if frame >= last.frame {
+            return Some(Segment {
+                index: self.keyframes.len() - 1,
+                t: 0.,
+            });
+        }
+
+        let index = self.keyframes.partition_point(|key| key.frame <= frame) - 1;
+        let (start, end) = (self.keyframes[index].frame, self.keyframes[index + 1].frame);
+        Some(Segment {
+            index,
+            t: (frame - start) / (end - start),
+        })
+    }
+}
+
+impl<T: Animatable> Track<T> {
+    /// The value at the given frame, or `None` if the track has no keyframes.
+    pub fn sample(&self, frame: Float) -> Option<T> {
+        let Segment { index, t } = self.segment(frame)?;
+        let current = &self.keyframes[index];
+        if t == 0. {
+            return Some(current.value);
+        }
+        let next = &self.keyframes[index + 1];
+
+        Some(match current.interpolation {
+            Interpolation::Step => current.value,
+            Interpolation::Linear | Interpolation::Slerp => {
+                current.value + (next.value - current.value) * t
+            }
+            Interpolation::Bezier => {
+                let duration = next.frame - current.frame;
+                let c1 = current.value + self.tangent(index) * (duration / 3.);
+                let c2 = next.value - self.tangent(index + 1) * (duration / 3.);
+                let s = 1. - t;
+
+                current.value * (s * s * s)
+                    + c1 * (3. * s * s * t)
+                    + c2 * (3. * s * t * t)
+                    + next.value * (t * t * t)
+            }
+        })
+    }
+
+    /// The rate of change (per frame) at a keyframe, estimated from its neighbours.
+    fn tangent(&self, index: usize) -> T {
+        let previous = &self.keyframes[index.saturating_sub(1)];
+        let next = &self.keyframes[(index + 1).min(self.keyframes.len() - 1)];
+        let duration = next.frame - previous.frame;
+
+        if duration > 0. {
+            (next.value - previous.value) * (1. / duration)
+        } else {
+            self.keyframes[index].value * 0.
+        }
+    }
+}
+
+impl Track<UnitQuaternion<Float>> {
+    /// The rotation at the given frame, turning at constant angular speed along the shortest
+    /// way between the keyframes. Segments of half a turn, which have no shortest way, keep the
+    /// rotation of their first keyframe.
+    pub fn sample_rotation(&self, frame: Float) -> Option<UnitQuaternion<Float>> {
+        let Segment { index, t } = self.segment(frame)?;
+        let current = &self.keyframes[index];
+        if t == 0. || current.interpolation == Interpolation::Step {
+            return Some(current.value);
+        }
+        let next = &self.keyframes[index + 1];
+        Some(
+            current
+                .value
+                .try_slerp(&next.value, t, 1e-9)
+                .unwrap_or(current.value),
+        )
+    }
+}
+
+/// A keyframe of the camera. Every value is optional: each one is animated separately, using
+/// only the keyframes that mention it. Orientations need both `look_from` and `look_at`.
+#[derive(Clone, Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct CameraKey {
+    pub frame: Float,
+    pub look_from: Option<Point3<Float>>,
+    pub look_at: Option<Point3<Float>>,
+    pub up: Option<Vector3<Float>>,
+    pub aperture: Option<Float>,
+    pub focus_distance: Option<Float>,
+    pub fov: Option<Float>,
+    #[serde(default)]
+    pub interpolation: Interpolation,
+}
+
+/// A keyframe of the transform of an object: its offset from the position given in the scene,
+/// the factor by which its size is multiplied and its `rotation`, given as the angles (in
+/// degrees) around the x, y and z axes, applied in that order. Objects are scaled and rotated
+/// around their position (for meshes, the origin of the model). Spheres cannot be seen to
+/// rotate, so only meshes are rotated.
+///
+/// Rotations take the shortest way between keyframes, so a full turn needs at least three
+/// segments, e.g. keyframes at `0`, `120`, `240` and `360` degrees.
+#[derive(Clone, Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct TransformKey {
+    pub frame: Float,
+    pub translation: Option<Vector3<Float>>,
+    pub scale: Option<Float>,
+    pub rotation: Option<Vector3<Float>>,
+    #[serde(default)]
+    pub interpolation: Interpolation,
+}
+
+/// The keyframes of a single object (a sphere or a mesh), identified by its name in the scene
+/// file.
+#[derive(Clone, Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct ObjectAnimation {
+    pub object: String,
+    pub keys: Vec<TransformKey>,
+}
+
+/// The `[animation]` section of a scene file:
+///
+/// ```toml
+/// [[animation.camera]]
+/// frame = 0
+/// look_from = [-4, 2, 0]
+/// look_at = [0, 1, -4]
+/// interpolation = "slerp"
+///
+/// [[animation.camera]]
+/// frame = 48
+/// look_from = [4, 2, 0]
+/// look_at = [0, 1, -4]
+///
+/// [[animation.objects]]
+/// object = "ball"
+/// keys = [
+///     { frame = 0, translation = [0, 0, 0], interpolation = "bezier" },
+///     { frame = 24, translation = [0, 2, 0], interpolation = "bezier" },
+///     { frame = 48, translation = [0, 0, 0] },
+/// ]
+///
+/// [[animation.objects]]
+/// object = "teapot"
+/// keys = [
+///     { frame = 0, rotation = [0, 0, 0] },
+///     { frame = 16, rotation = [0, 120, 0] },
+///     { frame = 32, rotation = [0, 240, 0] },
+///     { frame = 48, rotation = [0, 360, 0] },
+/// ]
+/// ```
+#[derive(Clone, Debug, Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Animation {
+    #[serde(default)]
+    pub camera: Vec<CameraKey>,
+    #[serde(default)]
+    pub objects: Vec<ObjectAnimation>,
+}
+
+/// The animated transform of an object at a given frame.
+#[derive(Copy, Clone, Debug)]
+pub struct Transform {
+    pub translation: Vector3<Float>,
+    pub scale: Float,
+    pub rotation: UnitQuaternion<Float>,
+}
+
+impl Animation {
+    /// The animated camera values at the given frame. Values which are not animated are left
+    /// empty, so that they fall back to the static camera of the scene.
+    pub fn camera_at(&self, frame: Float) -> CameraSettings {
+        let track = |value: fn(&CameraKey) -> Option<Float>| {
+            Track::new(
+                self.camera
+                    .iter()
+                    .filter_map(|key| {
+                        value(key).map(|value| Keyframe {
+                            frame: key.frame,
+                            value,
+                            interpolation: key.interpolation,
+                        })
+                    })
+                    .collect(),
+            )
+            .sample(frame)
+        };
+
+        let (look_from, look_at, up) = match self.orientation_at(frame) {
+            Some((look_from, look_at, up)) => (Some(look_from), Some(look_at), Some(up)),
+            None => (None, None, None),
+        };
+
+        CameraSettings {
+            look_from,
+            look_at,
+            up,
+            aperture: track(|key| key.aperture),
+            focus: track(|key| key.focus_distance).map(Focus::Distance),
+            fov: track(|key| key.fov),
+            ..CameraSettings::default()
+        }
+    }
+
+    /// The transform of the object called `name` at the given frame, if it is animated.
+    pub fn transform_at(&self, name: &str, frame: Float) -> Option<Transform> {
+        let animation = self.objects.iter().find(|a| a.object == name)?;
+
+        let translation = Track::new(
+            animation
+                .keys
+                .iter()
+                .filter_map(|key| {
+                    key.translation.map(|value| Keyframe {
+                        frame: key.frame,
+                        value,
+                        interpolation: key.interpolation,
+                    })
+                })
+                .collect(),
+        );
+        let scale = Track::new(
+            animation
+                .keys
+                .iter()
+                .filter_map(|key| {
+                    key.scale.map(|value| Keyframe {
+                        frame: key.frame,
+                        value,
+                        interpolation: key.interpolation,
+                    })
+                })
+                .collect(),
+        );
+
+        let rotation = Track::new(
+            animation
+                .keys
+                .iter()
+                .filter_map(|key| {
+                    key.rotation.map(|angles| Keyframe {
+                        frame: key.frame,
+                        value: euler_rotation(&angles),
+                        interpolation: key.interpolation,
+                    })
+                })
+                .collect(),
+        );
+
+        Some(Transform {
+            translation: translation.sample(frame).unwrap_or_else(Vector3::zeros),
+            scale: scale.sample(frame).unwrap_or(1.),
+            rotation: rotation
+                .sample_rotation(frame)
+                .unwrap_or_else(UnitQuaternion::identity),
+        })
+    }
+
+    /// Checks that every animated object exists, given the names of the objects of the scene.
+    pub fn check_objects<'a, I>(&self, names: I) -> Result<()>
+    where
+        I: IntoIterator<Item = &'a str> + Clone,
+    {
+        for animation in &self.objects {
+            if !names
+                .clone()
+                .into_iter()
+                .any(|name| name == animation.object)
+            {
+                return Err(Error::Scene(format!(
+                    "the animation refers to `{}`, but there is no object with that name",
+                    animation.object
+                )));
+            }
+        }
+        Ok(())
+    }
+
+    /// Interpolates the `look_from`, `look_at` and `up` vectors of the keyframes which contain
+    /// an orientation. Slerp segments rotate the viewing direction and the upwards vector at a
+    /// constant angular speed, while the camera position and the distance to the target change
+    /// linearly.
+    fn orientation_at(
+        &self,
+        frame: Float,
+    ) -> Option<(Point3<Float>, Point3<Float>, Vector3<Float>)> {
+        let mut keys: Vec<&CameraKey> = self
+            .camera
+            .iter()
+            .filter(|key| key.look_from.is_some() && key.look_at.is_some())
+            .collect();
+        keys.sort_by(|a, b| a.frame.total_cmp(&b.frame));
+        let up = |key: &CameraKey| key.up.unwrap_or_else(|| Vector3::new(0., 1., 0.));
+        let track = |value: &dyn Fn(&CameraKey) -> Vector3<Float>| {
+            Track::new(
+                keys.iter()
+                    .map(|key| Keyframe {
+                        frame: key.frame,
+                        value: value(key),
+                        interpolation: key.interpolation,
+                    })
+                    .collect(),
+            )
+        };
+
+        let look_from = track(&|key| key.look_from.unwrap().coords);
+        let look_at = track(&|key| key.look_at.unwrap().coords);
+        let upwards = track(&|key| up(key));
+
+        let segment = look_from.segment(frame)?;
+        let current = look_from.keyframes[segment.index];
+        if current.interpolation == Interpolation::Slerp && segment.t > 0. {
+            let (from, to) = (keys[segment.index], keys[segment.index + 1]);
+            let t = segment.t;
+
+            let rotation = |key: &CameraKey| {
+                UnitQuaternion::face_towards(
+                    &(key.look_at.unwrap() - key.look_from.unwrap()),
+                    &up(key),
+                )
+            };
+            let distance = |key: &CameraKey| (key.look_at.unwrap() - key.look_from.unwrap()).norm();
+
+            let rotation = rotation(from)
+                .try_slerp(&rotation(to), t, 1e-9)
+                .unwrap_or_else(|| rotation(from));
+            let position =
+                from.look_from.unwrap() + (to.look_from.unwrap() - from.look_from.unwrap()) * t;
+            let distance = distance(from) + (distance(to) - distance(from)) * t;
+
+            return Some((
+                position,
+                position + rotation * Vector3::z() * distance,
+                rotation * Vector3::y(),
+            ));
+        }
+
+        Some((
+            Point3::from(look_from.sample(frame)?),
+            Point3::from(look_at.sample(frame)?),
+            upwards.sample(frame)?,
+        ))
+    }
+}
+
+/// The rotation by the given angles (in degrees) around the x, y and z axes, in that order.
+fn euler_rotation(angles: &Vector3<Float>) -> UnitQuaternion<Float> {
+    let angles = angles.map(Float::to_radians);
+    UnitQuaternion::from_euler_angles(angles.x, angles.y, angles.z)
+}
@@ -0,0 +1,173 @@
+use crate::error::{Error, Result};
+use crate::float::{consts, Float};
+use crate::util;
+use nalgebra::Vector2;
+use std::fmt;
+use std::path::Path;
+use std::sync::Arc;
+
+/// The shape of the opening through which light enters the lens. Out of focus highlights
+/// ("bokeh") take this shape.
+#[derive(Clone, Debug)]
+pub enum ApertureShape {
+    /// A perfect circle.
+    Circular,
+    /// A regular polygon formed by `blades` straight diaphragm blades, rotated by `rotation`
+    /// degrees.
+    Polygonal { blades: u32, rotation: Float },
+    /// An arbitrary shape, given by a grayscale image.
+    Mask(Arc<ApertureMask>),
+}
+
+/// The aperture of a lens: its shape and the amount of optical ("cat's eye") vignetting.
+///
+/// Away from the center of the image, light that passes through the aperture can be blocked by
+/// the rim of the lens barrel. This is modelled as a second opening of the same size as the
+/// aperture, displaced towards the edge of the image by `cat_eye` aperture radii at the corners.
+/// Only the overlap of the two openings lets light through, which turns out of focus highlights
+/// into cat's eyes and darkens the corners of the image. `0` disables the effect.
+#[derive(Clone, Debug)]
+pub struct Diaphragm {
+    pub shape: ApertureShape,
+    pub cat_eye: Float,
+}
+
+impl Default for Diaphragm {
+    fn default() -> Self {
+        Diaphragm {
+            shape: ApertureShape::Circular,
+            cat_eye: 0.,
+        }
+    }
+}
+
+impl Diaphragm {
+    /// Returns a random point of the aperture, relative to its center and measured in aperture
+    /// radii, through which light reaches the sensor point `(u, v)`. The point is chosen by
+    /// `sample`, a point of the unit square. Returns `None` if the light is blocked by the lens
+    /// barrel.
+    pub fn sample(
+        &self,
+        u: Float,
+        v: Float,
+        aspect_ratio: Float,
+        sample: Vector2<Float>,
+    ) -> Option<Vector2<Float>> {
+        let point = self.shape.sample(sample);
+
+        if self.cat_eye > 0. {
+            // Position on the sensor, normalized so that the corners are at a distance of 1.
+            let diagonal = (aspect_ratio.powf(2.) + 1.).sqrt();
+            let position = Vector2::new((2. * u - 1.) * aspect_ratio, 2. * v - 1.) / diagonal;
+            let barrel_center = self.cat_eye * position;
+
+            if (point - barrel_center).norm_squared() > 1. {
+                return None;
+            }
+        }
+
+        Some(point)
+    }
+}
+
+impl ApertureShape {
+    /// Maps a point of the unit square onto a uniformly distributed (or, for masks, distributed
+    /// proportionally to the brightness of the mask) point of the aperture, measured in aperture
+    /// radii.
+    pub fn sample(&self, sample: Vector2<Float>) -> Vector2<Float> {
+        match self {
+            ApertureShape::Circular => util::concentric_disk(sample),
+            ApertureShape::Polygonal { blades, rotation } => {
+                let blades = (*blades).max(3);
+
+                // All the triangles between the center and two consecutive corners have the same
+                // area, so one of them is chosen uniformly and then sampled uniformly.
+                let scaled = sample.x * blades as Float;
+                let sector = scaled.floor().min(blades as Float - 1.);
+                let angle =
+                    |i: Float| rotation.to_radians() + i * 2. * consts::PI / blades as Float;
+                let a = Vector2::new(angle(sector).cos(), angle(sector).sin());
+                let b = Vector2::new(angle(sector + 1.).cos(), angle(sector + 1.).sin());
+
+                let distance = (scaled - sector).sqrt();
+                distance * ((1. - sample.y) * a + sample.y * b)
+            }
+            ApertureShape::Mask(mask) => mask.sample(sample),
+        }
+    }
+}
+
+/// An aperture shape given by an image, centered on the lens. The longer side of the image spans
+/// the diameter of the aperture; brighter pixels let more light through.
+pub struct ApertureMask {
+    width: u32,
+    height: u32,
+    /// Cumulative brightness of the pixels, row by row, used for importance sampling.
+    cdf: Vec<Float>,
+}
+
+impl ApertureMask {
+    pub fn load<P: AsRef<Path>>(path: P) -> Result<ApertureMask> {
+        let path = path.as_ref();
+        let img = image::open(path)
+            .map_err(|err| Error::from(err).with_path(path))?
+            .into_luma8();
+
+        ApertureMask::from_image(&img).ok_or_else(|| {
+            Error::Scene(format!(
+                "{}: the aperture mask is completely black",
+                path.display()
+            ))
+        })
+    }
+
+    /// Builds a mask out of a grayscale image. Returns `None` if no light passes through it.
+    pub fn from_image(img: &image::GrayImage) -> Option<ApertureMask> {
+        let cdf: Vec<Float> = img
+            .pixels()
+            .scan(0., |total, pixel| {
+                *total += pixel[0] as Float;
+                Some(*total)
+            })
+            .collect();
+
+        match cdf.last() {
+            Some(total) if *total > 0. => Some(ApertureMask {
+                width: img.width(),
+                height: img.height(),
+                cdf,
+            }),
+            _ => None,
+        }
+    }
+
+    fn sample(&self, sample: Vector2<Float>) -> Vector2<Float> {
+        let total = self.cdf[self.cdf.len() - 1];
+        let target = sample.x * total;
+        let index = self
+            .cdf
+            .partition_point(|&cumulative| cumulative <= target)
+            .min(self.cdf.len() - 1);
+
+        // The position of the target inside the pixel's share of the total is reused as the
+        // horizontal position inside the pixel.
+        let start = if index == 0 { 0. } else { self.cdf[index - 1] };
+        let fraction = ((target - start) / (self.cdf[index] - start)).clamp(0., 1.);
+        let index = index as u32;
+        let x = (index % self.width) as Float + fraction;
+        let y = (index / self.width) as Float + sample.y;
+
+        // Keep the aspect ratio of the image: its longer side spans the diameter of the aperture.
+        let size = self.width.max(self.height) as Float;
+        Vector2::new(
+            (2. * x - self.width as Float) / size,
+            (self.height as Float - 2. * y) / size,
+        )
+    }
+}
+
+impl fmt::Debug for ApertureMask {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "ApertureMask({}x{})", self.width, self.height)
+    }
+}
@@ -0,0 +1,341 @@
+use crate::float::Float;
+use crate::packet::{self, Lanes, RayPacket, Vectors, WIDTH};
+use crate::ray::Ray;
+use crate::stats;
+use crate::util;
+use nalgebra::Point3;
+
+/// An axis-aligned bounding box.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct Aabb {
+    pub min: Point3<Float>,
+    pub max: Point3<Float>,
+}
+
+impl Aabb {
+    /// The box which contains nothing; growing it by anything gives the bounds of that thing.
+    pub fn empty() -> Aabb {
+        let infinity = Float::INFINITY;
+        Aabb {
+            min: Point3::new(infinity, infinity, infinity),
+            max: Point3::new(-infinity, -infinity, -infinity),
+        }
+    }
+
+    /// The bounds of a set of points.
+    pub fn from_points<'a, I: IntoIterator<Item = &'a Point3<Float>>>(points: I) -> Aabb {
+        points
+            .into_iter()
+            .fold(Aabb::empty(), |bounds, point| Aabb {
+                min: bounds.min.inf(point),
+                max: bounds.max.sup(point),
+            })
+    }
+
+    pub fn union(&self, other: &Aabb) -> Aabb {
+        Aabb {
+            min: self.min.inf(&other.min),
+            max: self.max.sup(&other.max),
+        }
+    }
+
+    pub fn centroid(&self) -> Point3<Float> {
+        nalgebra::center(&self.min, &self.max)
+    }
+
+    /// Half of the surface area, which is proportional to the probability that a random ray
+    /// passing through an enclosing box hits this one.
+    fn half_area(&self) -> Float {
+        let d = self.max - self.min;
+        if d.x < 0. {
+            return 0.;
+        }
+        d.x * d.y + d.y * d.z + d.z * d.x
+    }
+
+    /// Whether the ray passes through the box at a `t` in `[t_min, t_max]` (the slab test).
+    pub fn hit(&self, ray: &Ray, t_min: Float, t_max: Float) -> bool {
+        let (mut t_min, mut t_max) = (t_min, t_max);
+        for axis in 0..3 {
+            let inverse = 1. / ray.direction()[axis];
+            let mut t0 = (self.min[axis] - ray.origin()[axis]) * inverse;
+            let mut t1 = (self.max[axis] - ray.origin()[axis]) * inverse;
+            if inverse < 0. {
+                std::mem::swap(&mut t0, &mut t1);
+            }
+            // Widen the slab by the rounding errors, so that rays grazing a primitive on the
+            // boundary of the box do not miss the box.
+            t1 *= 1. + 2. * util::gamma(3);
+            t_min = t0.max(t_min);
+            t_max = t1.min(t_max);
+            if t_max < t_min {
+                return false;
+            }
+        }
+        true
+    }
+
+    /// The slab test for each lane of a packet (see `hit`), given the inverses of the
+    /// coordinates of the directions of the rays.
+    pub fn hit_lanes<const N: usize>(
+        &self,
+        origin: &Vectors<N>,
+        inverse: &Vectors<N>,
+        t_min: Float,
+        t_max: &Lanes<N>,
+    ) -> [bool; N] {
+        let (mut t_min, mut t_max) = ([t_min; N], *t_max);
+        for axis in 0..3 {
+            for i in 0..N {
+                let t0 = (self.min[axis] - origin[axis][i]) * inverse[axis][i];
+                let t1 = (self.max[axis] - origin[axis][i]) * inverse[axis][i];
+                let (t0, t1) = if inverse[axis][i] < 0. {
+                    (t1, t0)
+                } else {
+                    (t0, t1)
+                };
+                t_min[i] = t0.max(t_min[i]);
+                t_max[i] = (t1 * (1. + 2. * util::gamma(3))).min(t_max[i]);
+            }
+        }
+        std::array::from_fn(|i| t_min[i] <= t_max[i])
+    }
+}
+
+/// Primitives which can be put into a `Bvh`.
+pub trait Bounded {
+    fn bounds(&self) -> Aabb;
+}
+
+/// The most primitives in a leaf of a `Bvh`.
+const MAX_LEAF_SIZE: usize = 4;
+
+/// The number of candidate split positions per axis, when building a `Bvh`.
+const BUCKETS: usize = 12;
+
+/// A bounding volume hierarchy: a binary tree of bounding boxes over a list of primitives, which
+/// lets a ray skip the primitives of all the boxes it misses.
+///
+/// The hierarchy only stores the indices of the primitives; the owner of the primitives does
+/// the actual intersection tests (see `Bvh::traverse`), so that e.g. alpha cutouts keep working
+/// inside it.
+#[derive(Clone, Debug, Default)]
+pub struct Bvh {
+    nodes: Vec<Node>,
+    /// The indices of the primitives, ordered so that every leaf covers a contiguous range.
+    indices: Vec<usize>,
+}
+
+#[derive(Clone, Debug)]
+struct Node {
+    bounds: Aabb,
+    kind: NodeKind,
+}
+
+#[derive(Clone, Debug)]
+enum NodeKind {
+    /// A range of `Bvh::indices`.
+    Leaf { start: usize, count: usize },
+    /// The first child directly follows its parent; the second child is at index `second`.
+    /// `axis` is the axis along which the children were split.
+    Interior { second: usize, axis: usize },
+}
+
+impl Bvh {
+    /// Builds the hierarchy over primitives with the given bounds, splitting the nodes where the
+    /// surface area heuristic expects the fewest intersection tests.
+    pub fn build(bounds: &[Aabb]) -> Bvh {
+        let mut bvh = Bvh {
+            nodes: Vec::with_capacity(2 * bounds.len()),
+            indices: (0..bounds.len()).collect(),
+        };
+        if !bounds.is_empty() {
+            bvh.build_node(bounds, 0, bounds.len());
+        }
+        bvh
+    }
+
+    fn build_node(&mut self, bounds: &[Aabb], start: usize, end: usize) -> usize {
+        let node_bounds = self.indices[start..end]
+            .iter()
+            .fold(Aabb::empty(), |acc, &i| acc.union(&bounds[i]));
+        let index = self.nodes.len();
+        self.nodes.push(Node {
+            bounds: node_bounds,
+            kind: NodeKind::Leaf {
+                start,
+                count: end - start,
+            },
+        });
+
+        let count = end - start;
+        if count <= MAX_LEAF_SIZE {
+            return index;
+        }
+
+        let centroids = self.indices[start..end]
+            .iter()
+            .map(|&i| bounds[i].centroid())
+            .fold(Aabb::empty(), |acc, centroid| Aabb {
+                min: acc.min.inf(&centroid),
+                max: acc.max.sup(&centroid),
+            });
+        let extent = centroids.max - centroids.min;
+        let axis = extent.imax();
+        if extent[axis] <= 0. {
+            // All the centroids coincide; no split can separate them.
+            return index;
+        }
+
+        let bucket_of = |aabb: &Aabb| {
+            let offset = (aabb.centroid()[axis] - centroids.min[axis]) / extent[axis];
+            ((offset * BUCKETS as Float) as usize).min(BUCKETS - 1)
+        };
+        let mut buckets = [(0, Aabb::empty()); BUCKETS];
+        for &i in &self.indices[start..end] {
+            let bucket = &mut buckets[bucket_of(&bounds[i])];
+            bucket.0 += 1;
+            bucket.1 = bucket.1.union(&bounds[i]);
+        }
+
+        // The cost of splitting after each bucket, relative to the cost of a leaf.
+        let (best, cost) = (0..BUCKETS - 1)
+            .map(|split| {
+                let (below, above) = buckets.split_at(split + 1);
+                let side = |buckets: &[(usize, Aabb)]| {
+                    buckets
+                        .iter()
+                        .fold((0, Aabb::empty()), |(n, b), (count, aabb)| {
+                            (n + count, b.union(aabb))
+                        })
+                };
+                let ((n0, b0), (n1, b1)) = (side(below), side(above));
+                let cost = 0.125
+                    + (n0 as Float * b0.half_area() + n1 as Float * b1.half_area())
+                        / node_bounds.half_area().max(Float::MIN_POSITIVE);
+                (split, cost)
+            })
+            .min_by(|a, b| a.1.total_cmp(&b.1))
+            .expect("there are several buckets");
+
+        let middle = if cost < count as Float {
+            let (mut i, mut j) = (start, end);
+            while i < j {
+                if bucket_of(&bounds[self.indices[i]]) <= best {
+                    i += 1;
+                } else {
+                    j -= 1;
+                    self.indices.swap(i, j);
+                }
+            }
+            i
+        } else {
+            return index;
+        };
+        if middle == start || middle == end {
+            return index;
+        }
+
+        self.build_node(bounds, start, middle);
+        let second = self.build_node(bounds, middle, end);
+        self.nodes[index].kind = NodeKind::Interior { second, axis };
+        index
+    }
+
+    /// Visits the primitives whose boxes the ray passes through, nearest boxes first. `hit` is
+    /// called with the index of a primitive and the current `t_max`; it returns the distance of
+    /// its hit point, if the primitive is hit closer than `t_max`. The distances shrink the
+    /// search, so that boxes behind the closest hit point so far are skipped.
+    pub fn traverse<F>(&self, ray: &Ray, t_min: Float, t_max: Float, mut hit: F)
+    where
+        F: FnMut(usize, Float) -> Option<Float>,
+    {
+        if self.nodes.is_empty() {
+            return;
+        }
+
+        let mut t_max = t_max;
+        let mut stack = vec![0];
+        while let Some(index) = stack.pop() {
+            let node = &self.nodes[index];
+            stats::count(|c| c.box_tests += 1);
+            if !node.bounds.hit(ray, t_min, t_max) {
+                continue;
+            }
+            match node.kind {
+                NodeKind::Leaf { start, count } => {
+                    for &primitive in &self.indices[start..start + count] {
+                        if let Some(t) = hit(primitive, t_max) {
+                            t_max = t;
+                        }
+                    }
+                }
+                NodeKind::Interior { second, axis } => {
+                    // Visit the child on the side the ray comes from first.
+                    if ray.direction()[axis] < 0. {
+                        stack.push(index + 1);
+                        stack.push(second);
+                    } else {
+                        stack.push(second);
+                        stack.push(index + 1);
+                    }
+                }
+            }
+        }
+    }
+
+    /// Like `traverse`, for the active rays of a packet, each with its own `t_max`. `hit` is
+    /// called with the index of a primitive, the packet restricted to the rays which pass
+    /// through its box, and the current `t_max` of each ray; it returns the distances of the hit
+    /// points closer than `t_max`. The boxes are visited in the order of the first active ray.
+    pub fn traverse_packet<F>(
+        &self,
+        packet: &RayPacket,
+        t_min: Float,
+        t_max: &Lanes<WIDTH>,
+        mut hit: F,
+    ) where
+        F: FnMut(usize, &RayPacket, &Lanes<WIDTH>) -> [Option<Float>; WIDTH],
+    {
+        let first = match packet.active().iter().position(|&active| active) {
+            Some(lane) if !self.nodes.is_empty() => lane,
+            _ => return,
+        };
+
+        let inverse = packet.direction().map(|axis| axis.map(|d| 1. / d));
+        let mut t_max = *t_max;
+        let mut stack = vec![0];
+        while let Some(index) = stack.pop() {
+            let node = &self.nodes[index];
+            stats::count(|c| c.box_tests += 1);
+            let mask = node
+                .bounds
+                .hit_lanes(packet.origin(), &inverse, t_min, &t_max);
+            let inside = packet.restrict(&mask);
+            if !packet::any(inside.active()) {
+                continue;
+            }
+            match node.kind {
+                NodeKind::Leaf { start, count } => {
+                    for &primitive in &self.indices[start..start + count] {
+                        let distances = hit(primitive, &inside, &t_max);
+                        for (t_max, t) in t_max.iter_mut().zip(distances) {
+                            if let Some(t) = t {
+                                *t_max = t;
+                            }
+                        }
+                    }
+                }
+                NodeKind::Interior { second, axis } => {
+                    if packet.direction()[axis][first] < 0. {
+                        stack.push(index + 1);
+                        stack.push(second);
+                    } else {
+                        stack.push(second);
+                        stack.push(index + 1);
+                    }
+                }
+            }
+        }
+    }
+}
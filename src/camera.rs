@@ -1,37 +1,84 @@
+use crate::aperture::{ApertureMask, ApertureShape, Diaphragm};
+use crate::config;
+use crate::error::{Error, Result};
+use crate::float::{consts, Float};
+use crate::hitable::Hitable;
 use crate::ray::Ray;
-use crate::sphere::Sphere;
-use nalgebra::{Point3, Unit, Vector3};
-use std::f64::consts;
+use crate::sampler::Sampler;
+use crate::util;
+use nalgebra::{Point3, Rotation3, Unit, Vector2, Vector3};
+use serde::{Deserialize, Deserializer};
+use std::cmp::Ordering;
+use std::path::PathBuf;
+use std::str::FromStr;
+use std::sync::Arc;
 
-/// A camera represented as a single 3D point and a rectangular sensor.
-/// The sensor is identified by its lower left corner and two directional vectors (one horizontal
-/// and one vertical).
+/// Anything that turns points of the sensor into rays. Every projection (perspective,
+/// orthographic, fisheye, ...) implements this trait.
+pub trait CameraModel: Send + Sync {
+    /// Creates a ray which runs from the camera towards the world. The values `u` and `v` provide
+    /// the point on the sensor through which the ray passes:
+    ///
+    /// * Use `(0.0, 0.0)` for obtaining a ray that passes through the lower left corner of the
+    ///   sensor.
+    /// * Use `(1.0, 1.0)` for obtaining a ray that passes through the upper right corner of the
+    ///   sensor.
+    /// * Use anything in between for obtaining an arbitrary ray.
+    ///
+    /// Cameras with a lens take the point of the lens through which the ray passes from the
+    /// `sampler`.
+    ///
+    /// Returns `None` if no light reaches that point of the sensor (e.g. outside the image circle
+    /// of a fisheye lens).
+    fn get_ray(&self, u: Float, v: Float, sampler: &mut dyn Sampler) -> Option<Ray>;
+}
+
+/// The names of the available camera models.
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum Projection {
+    Perspective,
+    Orthographic,
+    FisheyeEquidistant,
+    FisheyeEquisolid,
+    Equirectangular,
+    TiltShift,
+}
+
+/// A perspective camera with a thin lens, represented as a single 3D point and a rectangular
+/// sensor. The sensor is identified by its lower left corner and two directional vectors (one
+/// horizontal and one vertical).
 ///
 /// The sensor is split into a grid of pixels.
-#[derive(Copy, Clone)]
+#[derive(Clone)]
 pub struct Camera {
     lens: Lens,
-    origin: Point3<f64>,
-    lower_left_corner: Vector3<f64>,
-    horizontal: Vector3<f64>,
-    vertical: Vector3<f64>,
-    u: Unit<Vector3<f64>>,
-    v: Unit<Vector3<f64>>,
-    w: Unit<Vector3<f64>>,
+    origin: Point3<Float>,
+    lower_left_corner: Vector3<Float>,
+    horizontal: Vector3<Float>,
+    vertical: Vector3<Float>,
+    u: Unit<Vector3<Float>>,
+    v: Unit<Vector3<Float>>,
 }
 
+/// Where the camera is placed and where it looks.
+#[derive(Copy, Clone, Debug)]
 pub struct Orientation {
-    pub look_from: Point3<f64>,
-    pub look_at: Point3<f64>,
-    pub upwards: Vector3<f64>,
+    pub look_from: Point3<Float>,
+    pub look_at: Point3<Float>,
+    pub upwards: Vector3<Float>,
 }
 
-#[derive(Copy, Clone)]
+/// The optical properties of the camera. `aperture` is the diameter of the lens opening,
+/// `diaphragm` its shape and `focal_length` is the distance from the camera to the plane which is
+/// in perfect focus.
+#[derive(Clone, Debug)]
 pub struct Lens {
-    pub aperture: f64,
-    pub focal_length: f64,
-    pub vertical_fov: f64,
-    pub aspect_ratio: f64,
+    pub aperture: Float,
+    pub diaphragm: Diaphragm,
+    pub focal_length: Float,
+    pub vertical_fov: Float,
+    pub aspect_ratio: Float,
 }
 
 impl Camera {
@@ -42,12 +89,9 @@ impl Camera {
         let half_height = (theta / 2.).tan();
         let half_width = lens.aspect_ratio * half_height;
 
-        let w = (orientation.look_from - orientation.look_at).normalize();
-        let u = orientation.upwards.cross(&w).normalize();
-        let v = w.cross(&u);
+        let (u, v, w) = orientation.basis();
 
         Camera {
-            lens,
             origin: orientation.look_from,
             lower_left_corner: (orientation.look_from
                 - lens.focal_length * half_width * u
@@ -58,27 +102,521 @@ impl Camera {
             vertical: 2. * half_height * lens.focal_length * v,
             u: Unit::new_normalize(u),
             v: Unit::new_normalize(v),
-            w: Unit::new_normalize(w),
+            lens,
         }
     }
 
-    /// Creates a ray which runs from the camera center towards (and through) the screen.
-    /// The values `u` and `v` provide the exact direction:
-    ///
-    /// * Use `(0.0, 0.0)` for obtaining a ray that passes through the lower left corner of the sensor.
-    /// * Use `(1.0, 1.0)` for obtaining a ray that passes through the upper right corner of the sensor.
-    /// * Use anything in between for obtaining an arbitrary ray.
-    pub fn get_ray(&self, u: f64, v: f64) -> Ray {
-        let rd = self.lens_radius() * Sphere::random_point_in_unit_disk();
+    pub fn lens_radius(&self) -> Float {
+        self.lens.aperture / 2.
+    }
+}
+
+impl Lens {
+    /// A random point of the lens (relative to its center, in the plane of the sensor) through
+    /// which light reaches the sensor point `(u, v)`, or `None` if the light is vignetted.
+    fn sample(&self, u: Float, v: Float, sampler: &mut dyn Sampler) -> Option<Vector2<Float>> {
+        if self.aperture == 0. {
+            return Some(Vector2::zeros());
+        }
+        self.diaphragm
+            .sample(u, v, self.aspect_ratio, sampler.get_2d())
+            .map(|point| self.aperture / 2. * point)
+    }
+}
+
+impl CameraModel for Camera {
+    /// Creates a ray which runs from a random point of the lens towards (and through) the
+    /// screen.
+    fn get_ray(&self, u: Float, v: Float, sampler: &mut dyn Sampler) -> Option<Ray> {
+        let rd = self.lens.sample(u, v, sampler)?;
 
         let offset = self.u.into_inner() * rd.x + self.v.into_inner() * rd.y;
         let direction = self.lower_left_corner + u * self.horizontal + v * self.vertical
             - self.origin.coords
             - offset;
-        Ray::new(self.origin + offset, direction)
+        Some(Ray::new(self.origin + offset, direction))
     }
+}
 
-    pub fn lens_radius(&self) -> f64 {
-        self.lens.aperture / 2.
+impl Orientation {
+    /// The orthonormal basis of the camera: `u` points to the right, `v` upwards and `w`
+    /// backwards (the camera looks towards `-w`).
+    fn basis(&self) -> (Vector3<Float>, Vector3<Float>, Vector3<Float>) {
+        let w = (self.look_from - self.look_at).normalize();
+        let u = self.upwards.cross(&w).normalize();
+        let v = w.cross(&u);
+        (u, v, w)
+    }
+}
+
+/// A camera whose rays are all parallel to the viewing direction, so that objects keep their size
+/// regardless of their distance. The rays start on a rectangle of height `view_height` which is
+/// centered in the `look_from` point.
+#[derive(Copy, Clone)]
+pub struct Orthographic {
+    lower_left_corner: Point3<Float>,
+    horizontal: Vector3<Float>,
+    vertical: Vector3<Float>,
+    direction: Vector3<Float>,
+}
+
+impl Orthographic {
+    pub fn new(orientation: Orientation, view_height: Float, aspect_ratio: Float) -> Orthographic {
+        let (u, v, w) = orientation.basis();
+        let horizontal = view_height * aspect_ratio * u;
+        let vertical = view_height * v;
+
+        Orthographic {
+            lower_left_corner: orientation.look_from - horizontal / 2. - vertical / 2.,
+            horizontal,
+            vertical,
+            direction: -w,
+        }
+    }
+}
+
+impl CameraModel for Orthographic {
+    fn get_ray(&self, u: Float, v: Float, _sampler: &mut dyn Sampler) -> Option<Ray> {
+        let origin = self.lower_left_corner + u * self.horizontal + v * self.vertical;
+        Some(Ray::new(origin, self.direction))
+    }
+}
+
+/// How a fisheye lens maps the angle `theta` between a ray and the viewing direction to the
+/// distance `r` from the center of the image.
+///
+/// * `Equidistant`: `r` is proportional to `theta`.
+/// * `Equisolid`: `r` is proportional to `sin(theta / 2)`, which preserves areas.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum FisheyeMapping {
+    Equidistant,
+    Equisolid,
+}
+
+/// A fisheye camera with a circular image, inscribed in the shorter side of the sensor. The
+/// diameter of the circle covers `fov` degrees; the sensor outside the circle receives no light.
+#[derive(Copy, Clone)]
+pub struct Fisheye {
+    origin: Point3<Float>,
+    u: Vector3<Float>,
+    v: Vector3<Float>,
+    w: Vector3<Float>,
+    mapping: FisheyeMapping,
+    half_fov: Float,
+    aspect_ratio: Float,
+}
+
+impl Fisheye {
+    pub fn new(
+        orientation: Orientation,
+        mapping: FisheyeMapping,
+        fov: Float,
+        aspect_ratio: Float,
+    ) -> Fisheye {
+        let (u, v, w) = orientation.basis();
+        Fisheye {
+            origin: orientation.look_from,
+            u,
+            v,
+            w,
+            mapping,
+            half_fov: fov.to_radians() / 2.,
+            aspect_ratio,
+        }
+    }
+}
+
+impl CameraModel for Fisheye {
+    fn get_ray(&self, u: Float, v: Float, _sampler: &mut dyn Sampler) -> Option<Ray> {
+        // Coordinates relative to the image circle, which has a radius of 1.
+        let (x, y) = if self.aspect_ratio >= 1. {
+            ((2. * u - 1.) * self.aspect_ratio, 2. * v - 1.)
+        } else {
+            (2. * u - 1., (2. * v - 1.) / self.aspect_ratio)
+        };
+        let r = x.hypot(y);
+        if r > 1. {
+            return None;
+        }
+
+        let theta = match self.mapping {
+            FisheyeMapping::Equidistant => r * self.half_fov,
+            FisheyeMapping::Equisolid => 2. * (r * (self.half_fov / 2.).sin()).asin(),
+        };
+        let phi = y.atan2(x);
+
+        let direction =
+            theta.sin() * (phi.cos() * self.u + phi.sin() * self.v) - theta.cos() * self.w;
+        Some(Ray::new(self.origin, direction))
+    }
+}
+
+/// A 360 degree panoramic camera. The horizontal axis of the image covers the longitude (the
+/// center of the image is the viewing direction) and the vertical axis covers the latitude, from
+/// straight down to straight up.
+#[derive(Copy, Clone)]
+pub struct Equirectangular {
+    origin: Point3<Float>,
+    u: Vector3<Float>,
+    v: Vector3<Float>,
+    w: Vector3<Float>,
+}
+
+impl Equirectangular {
+    pub fn new(orientation: Orientation) -> Equirectangular {
+        let (u, v, w) = orientation.basis();
+        Equirectangular {
+            origin: orientation.look_from,
+            u,
+            v,
+            w,
+        }
+    }
+}
+
+impl CameraModel for Equirectangular {
+    fn get_ray(&self, u: Float, v: Float, _sampler: &mut dyn Sampler) -> Option<Ray> {
+        let longitude = (u - 0.5) * 2. * consts::PI;
+        let latitude = (v - 0.5) * consts::PI;
+
+        let direction = latitude.cos() * (longitude.sin() * self.u - longitude.cos() * self.w)
+            + latitude.sin() * self.v;
+        Some(Ray::new(self.origin, direction))
+    }
+}
+
+/// A thin lens perspective camera whose lens can be tilted and shifted relative to the sensor.
+///
+/// * Shifting moves the sensor within its plane by a fraction of its width and height, which
+///   reframes the image without converging vertical lines.
+/// * Tilting rotates the plane of focus around the horizontal axis of the camera by `tilt`
+///   degrees (the [Scheimpflug principle](https://en.wikipedia.org/wiki/Scheimpflug_principle)).
+///   Positive values bring the bottom of the plane closer, e.g. for keeping a whole table top in
+///   focus, while negative values create the "miniature" look.
+#[derive(Clone)]
+pub struct TiltShift {
+    lens: Lens,
+    origin: Point3<Float>,
+    lower_left_corner: Point3<Float>,
+    horizontal: Vector3<Float>,
+    vertical: Vector3<Float>,
+    u: Vector3<Float>,
+    v: Vector3<Float>,
+    focus_point: Point3<Float>,
+    focus_normal: Vector3<Float>,
+}
+
+impl TiltShift {
+    pub fn new(
+        orientation: Orientation,
+        lens: Lens,
+        tilt: Float,
+        shift: Vector2<Float>,
+    ) -> TiltShift {
+        let (u, v, w) = orientation.basis();
+        let half_height = (lens.vertical_fov.to_radians() / 2.).tan();
+        let half_width = lens.aspect_ratio * half_height;
+
+        // The sensor is placed at a distance of 1 in front of the pinhole.
+        let horizontal = 2. * half_width * u;
+        let vertical = 2. * half_height * v;
+        let lower_left_corner = orientation.look_from - horizontal / 2. - vertical / 2. - w
+            + shift.x * horizontal
+            + shift.y * vertical;
+
+        let tilt = Rotation3::from_axis_angle(&Unit::new_normalize(u), -tilt.to_radians());
+
+        TiltShift {
+            origin: orientation.look_from,
+            lower_left_corner,
+            horizontal,
+            vertical,
+            u,
+            v,
+            focus_point: orientation.look_from - lens.focal_length * w,
+            focus_normal: tilt * w,
+            lens,
+        }
+    }
+}
+
+impl CameraModel for TiltShift {
+    /// The pinhole ray through the sensor point is intersected with the (tilted) plane of focus;
+    /// the actual ray runs from a random point of the lens towards that intersection.
+    fn get_ray(&self, u: Float, v: Float, sampler: &mut dyn Sampler) -> Option<Ray> {
+        let pinhole_direction =
+            self.lower_left_corner + u * self.horizontal + v * self.vertical - self.origin;
+
+        let rd = self.lens.sample(u, v, sampler)?;
+        let offset = self.u * rd.x + self.v * rd.y;
+
+        let denominator = pinhole_direction.dot(&self.focus_normal);
+        let t = (self.focus_point - self.origin).dot(&self.focus_normal) / denominator;
+
+        let direction = if t > 0. && t.is_finite() {
+            t * pinhole_direction - offset
+        } else {
+            // The ray never meets the plane of focus, so it is focused at infinity.
+            pinhole_direction
+        };
+        Some(Ray::new(self.origin + offset, direction))
+    }
+}
+
+/// How the distance to the plane of perfect focus is chosen.
+///
+/// * `Distance`: an explicit distance, measured along the viewing direction.
+/// * `Point`: the focus plane passes through the given point.
+/// * `Auto`: the focus plane passes through whatever the ray through the center of the sensor
+///   hits first; if it hits nothing, the camera focuses on the `look_at` point.
+///
+/// On the command line, these are written as `8.5`, `0,1,-8` and `auto` respectively.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum Focus {
+    Distance(Float),
+    Point(Point3<Float>),
+    Auto,
+}
+
+/// A partial camera description, as given on the command line or stored in a scene file. Missing
+/// values fall back to the next layer and ultimately to the defaults of `CameraSettings::build`.
+///
+/// In scene files, named cameras ("bookmarks") are stored as `[cameras.<name>]` tables:
+///
+/// ```toml
+/// [cameras.closeup]
+/// look_from = [0, 1.5, -1]
+/// look_at = [0, 1, -4]
+/// aperture = 0.3
+/// focus = "auto"
+/// fov = 35
+///
+/// [cameras.panorama]
+/// projection = "equirectangular"
+/// ```
+///
+/// * `fov`: the vertical field of view in degrees; for fisheye cameras, the field of view of the
+///   image circle.
+/// * `view_height`: the height of the area seen by orthographic cameras.
+/// * `tilt` and `shift`: the lens movements of tilt-shift cameras (see `TiltShift`).
+/// * `blades` and `blade_rotation` (in degrees): turn the aperture into a regular polygon.
+/// * `aperture_mask`: an image which gives the shape of the aperture; overrides `blades`.
+/// * `cat_eye`: the strength of the optical vignetting (see `Diaphragm`).
+#[derive(Clone, Debug, Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct CameraSettings {
+    pub projection: Option<Projection>,
+    pub look_from: Option<Point3<Float>>,
+    pub look_at: Option<Point3<Float>>,
+    pub up: Option<Vector3<Float>>,
+    pub aperture: Option<Float>,
+    pub focus: Option<Focus>,
+    pub fov: Option<Float>,
+    pub view_height: Option<Float>,
+    pub tilt: Option<Float>,
+    pub shift: Option<Vector2<Float>>,
+    pub blades: Option<u32>,
+    pub blade_rotation: Option<Float>,
+    pub aperture_mask: Option<PathBuf>,
+    pub cat_eye: Option<Float>,
+}
+
+impl CameraSettings {
+    /// Fills in the values missing from `self` with the ones from `fallback`.
+    pub fn or(self, fallback: CameraSettings) -> CameraSettings {
+        CameraSettings {
+            projection: self.projection.or(fallback.projection),
+            look_from: self.look_from.or(fallback.look_from),
+            look_at: self.look_at.or(fallback.look_at),
+            up: self.up.or(fallback.up),
+            aperture: self.aperture.or(fallback.aperture),
+            focus: self.focus.or(fallback.focus),
+            fov: self.fov.or(fallback.fov),
+            view_height: self.view_height.or(fallback.view_height),
+            tilt: self.tilt.or(fallback.tilt),
+            shift: self.shift.or(fallback.shift),
+            blades: self.blades.or(fallback.blades),
+            blade_rotation: self.blade_rotation.or(fallback.blade_rotation),
+            aperture_mask: self.aperture_mask.or(fallback.aperture_mask),
+            cat_eye: self.cat_eye.or(fallback.cat_eye),
+        }
+    }
+
+    /// Builds the camera, using the following defaults for the missing values: a perspective
+    /// projection looking from `(-2, 1.7, 0)` at `(0, 1, -8)` with `(0, 1, 0)` upwards, an
+    /// aperture of `0.1`, a vertical field of view of 60 degrees (180 degrees for fisheye
+    /// cameras), the focus on the `look_at` point and a view height which matches the field of
+    /// view at the focus distance. The `world` is needed for autofocus.
+    pub fn build(&self, world: &dyn Hitable, aspect_ratio: Float) -> Result<Arc<dyn CameraModel>> {
+        let orientation = Orientation {
+            look_from: self.look_from.unwrap_or_else(|| Point3::new(-2., 1.7, 0.)),
+            look_at: self.look_at.unwrap_or_else(|| Point3::new(0., 1., -8.)),
+            upwards: self.up.unwrap_or_else(|| Vector3::new(0., 1., 0.)),
+        };
+
+        let view = orientation.look_at - orientation.look_from;
+        if util::length(&view) == 0. {
+            return Err(Error::Scene(String::from(
+                "the camera cannot look at the point it is looking from",
+            )));
+        }
+        if util::length(&orientation.upwards.cross(&view)) == 0. {
+            return Err(Error::Scene(String::from(
+                "the upwards vector of the camera is parallel to the viewing direction",
+            )));
+        }
+        let forward = view.normalize();
+
+        let focal_length = match self.focus {
+            None => util::length(&view),
+            Some(Focus::Distance(distance)) => distance,
+            Some(Focus::Point(point)) => (point - orientation.look_from).dot(&forward),
+            Some(Focus::Auto) => {
+                let center_ray = Ray::new(orientation.look_from, forward);
+                match world.hit(&center_ray, 0., Float::INFINITY) {
+                    Some(hitpoint) => hitpoint.t,
+                    None => util::length(&view),
+                }
+            }
+        };
+        if focal_length.partial_cmp(&0.) != Some(Ordering::Greater) {
+            return Err(Error::Scene(format!(
+                "the focus distance must be positive, got {}",
+                focal_length
+            )));
+        }
+        debug!("focus distance: {}", focal_length);
+
+        let aperture = self.aperture.unwrap_or(0.1);
+        if aperture.partial_cmp(&0.).is_none() || aperture < 0. {
+            return Err(Error::Scene(format!(
+                "the aperture cannot be negative, got {}",
+                aperture
+            )));
+        }
+
+        let shape = match (&self.aperture_mask, self.blades) {
+            (Some(path), _) => ApertureShape::Mask(Arc::new(ApertureMask::load(path)?)),
+            (None, Some(blades)) if blades < 3 => {
+                return Err(Error::Scene(format!(
+                    "an aperture needs at least 3 blades, got {}",
+                    blades
+                )));
+            }
+            (None, Some(blades)) => ApertureShape::Polygonal {
+                blades,
+                rotation: self.blade_rotation.unwrap_or(0.),
+            },
+            (None, None) => ApertureShape::Circular,
+        };
+        let diaphragm = Diaphragm {
+            shape,
+            cat_eye: self.cat_eye.unwrap_or(0.),
+        };
+
+        let projection = self.projection.unwrap_or(Projection::Perspective);
+        let default_fov = match projection {
+            Projection::FisheyeEquidistant | Projection::FisheyeEquisolid => 180.,
+            _ => 60.,
+        };
+        let lens = Lens {
+            aperture,
+            diaphragm,
+            focal_length,
+            vertical_fov: self.fov.unwrap_or(default_fov),
+            aspect_ratio,
+        };
+
+        Ok(match projection {
+            Projection::Perspective => Arc::new(Camera::new(orientation, lens)),
+            Projection::Orthographic => {
+                let view_height = self.view_height.unwrap_or_else(|| {
+                    2. * (lens.vertical_fov.to_radians() / 2.).tan() * lens.focal_length
+                });
+                Arc::new(Orthographic::new(orientation, view_height, aspect_ratio))
+            }
+            Projection::FisheyeEquidistant => Arc::new(Fisheye::new(
+                orientation,
+                FisheyeMapping::Equidistant,
+                lens.vertical_fov,
+                aspect_ratio,
+            )),
+            Projection::FisheyeEquisolid => Arc::new(Fisheye::new(
+                orientation,
+                FisheyeMapping::Equisolid,
+                lens.vertical_fov,
+                aspect_ratio,
+            )),
+            Projection::Equirectangular => Arc::new(Equirectangular::new(orientation)),
+            Projection::TiltShift => Arc::new(TiltShift::new(
+                orientation,
+                lens,
+                self.tilt.unwrap_or(0.),
+                self.shift.unwrap_or_else(Vector2::zeros),
+            )),
+        })
+    }
+}
+
+impl FromStr for Projection {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s {
+            "perspective" => Ok(Projection::Perspective),
+            "orthographic" => Ok(Projection::Orthographic),
+            "fisheye-equidistant" => Ok(Projection::FisheyeEquidistant),
+            "fisheye-equisolid" => Ok(Projection::FisheyeEquisolid),
+            "equirectangular" => Ok(Projection::Equirectangular),
+            "tilt-shift" => Ok(Projection::TiltShift),
+            _ => Err(Error::InvalidConfig(format!(
+                "`{}` is not a projection; expected perspective, orthographic, \
+                 fisheye-equidistant, fisheye-equisolid, equirectangular or tilt-shift",
+                s
+            ))),
+        }
+    }
+}
+
+impl FromStr for Focus {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        if s == "auto" {
+            return Ok(Focus::Auto);
+        }
+        if let Ok(distance) = s.parse::<Float>() {
+            return Ok(Focus::Distance(distance));
+        }
+        config::parse_vector(s)
+            .map(|coords| Focus::Point(Point3::from(coords)))
+            .map_err(|_| {
+                Error::InvalidConfig(format!(
+                    "`{}` is not a focus; expected a distance (e.g. 8.5), a point (e.g. 0,1,-8) \
+                     or `auto`",
+                    s
+                ))
+            })
+    }
+}
+
+impl<'de> Deserialize<'de> for Focus {
+    fn deserialize<D>(deserializer: D) -> std::result::Result<Self, D::Error>
+    where
+        D: Deserializer<'de>,
+    {
+        #[derive(Deserialize)]
+        #[serde(untagged)]
+        enum Raw {
+            Distance(Float),
+            Point(Point3<Float>),
+            Keyword(String),
+        }
+
+        match Raw::deserialize(deserializer)? {
+            Raw::Distance(distance) => Ok(Focus::Distance(distance)),
+            Raw::Point(point) => Ok(Focus::Point(point)),
+            Raw::Keyword(keyword) => keyword.parse().map_err(serde::de::Error::custom),
+        }
     }
 }
@@ -0,0 +1,349 @@
+use crate::error::{Error, Result};
+use crate::float::Float;
+use nalgebra::{Matrix3, Vector3};
+use serde::Deserialize;
+use std::fs::File;
+use std::io::BufWriter;
+use std::path::Path;
+use std::str::FromStr;
+
+/// The chromaticities `(x, y)` of the red, green and blue primaries and of the white point of an
+/// RGB color space.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct Primaries {
+    pub red: (Float, Float),
+    pub green: (Float, Float),
+    pub blue: (Float, Float),
+    pub white: (Float, Float),
+}
+
+const D65: (Float, Float) = (0.3127, 0.3290);
+const D50: (Float, Float) = (0.3457, 0.3585);
+
+/// ITU-R BT.709, shared by sRGB.
+pub const REC709: Primaries = Primaries {
+    red: (0.64, 0.33),
+    green: (0.30, 0.60),
+    blue: (0.15, 0.06),
+    white: D65,
+};
+
+/// ITU-R BT.2020.
+pub const REC2020: Primaries = Primaries {
+    red: (0.708, 0.292),
+    green: (0.170, 0.797),
+    blue: (0.131, 0.046),
+    white: D65,
+};
+
+/// ACES AP1, the primaries of ACEScg.
+pub const ACES_AP1: Primaries = Primaries {
+    red: (0.713, 0.293),
+    green: (0.165, 0.830),
+    blue: (0.128, 0.044),
+    white: (0.32168, 0.33767),
+};
+
+/// DCI-P3 primaries with a D65 white point.
+pub const DISPLAY_P3: Primaries = Primaries {
+    red: (0.680, 0.320),
+    green: (0.265, 0.690),
+    blue: (0.150, 0.060),
+    white: D65,
+};
+
+impl Primaries {
+    /// The matrix which converts linear RGB values into CIE XYZ.
+    pub fn rgb_to_xyz(&self) -> Matrix3<Float> {
+        let xyz = |(x, y): (Float, Float)| Vector3::new(x / y, 1., (1. - x - y) / y);
+        let primaries = Matrix3::from_columns(&[xyz(self.red), xyz(self.green), xyz(self.blue)]);
+        let scale = primaries
+            .try_inverse()
+            .expect("the primaries of a color space are linearly independent")
+            * xyz(self.white);
+        primaries * Matrix3::from_diagonal(&scale)
+    }
+
+    /// The matrix which converts linear RGB values of these primaries into linear RGB values of
+    /// the `target` primaries. Differing white points are adapted with the Bradford transform.
+    pub fn conversion_to(&self, target: &Primaries) -> Matrix3<Float> {
+        let xyz_to_target = target
+            .rgb_to_xyz()
+            .try_inverse()
+            .expect("the primaries of a color space are linearly independent");
+        xyz_to_target * chromatic_adaptation(self.white, target.white) * self.rgb_to_xyz()
+    }
+}
+
+/// The Bradford chromatic adaptation of CIE XYZ values from one white point to another.
+fn chromatic_adaptation(from: (Float, Float), to: (Float, Float)) -> Matrix3<Float> {
+    if from == to {
+        return Matrix3::identity();
+    }
+
+    #[rustfmt::skip]
+    let bradford = Matrix3::new(
+        0.8951, 0.2664, -0.1614,
+        -0.7502, 1.7135, 0.0367,
+        0.0389, -0.0685, 1.0296,
+    );
+    let xyz = |(x, y): (Float, Float)| Vector3::new(x / y, 1., (1. - x - y) / y);
+    let (source, target) = (bradford * xyz(from), bradford * xyz(to));
+    let scale = Matrix3::from_diagonal(&target.component_div(&source));
+
+    bradford.try_inverse().unwrap() * scale * bradford
+}
+
+/// The linear RGB space in which the renderer works. Material colors in scene files (and the
+/// sky) are given in this space; textures and outputs are converted from and into it.
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum ColorSpace {
+    /// Linear sRGB, i.e. the primaries of Rec.709.
+    #[default]
+    #[serde(alias = "rec709")]
+    LinearSrgb,
+    /// The ACES working space for computer graphics.
+    #[serde(alias = "aces-cg")]
+    Acescg,
+    /// The wide gamut of Rec.2020.
+    Rec2020,
+}
+
+impl ColorSpace {
+    pub fn primaries(&self) -> &'static Primaries {
+        match self {
+            ColorSpace::LinearSrgb => &REC709,
+            ColorSpace::Acescg => &ACES_AP1,
+            ColorSpace::Rec2020 => &REC2020,
+        }
+    }
+
+    /// Converts a texel of an image which was encoded with `encoding` into a linear color of this
+    /// working space.
+    pub fn decode_texel(&self, texel: [u8; 3], encoding: Encoding) -> Vector3<Float> {
+        let encoded = Vector3::new(texel[0], texel[1], texel[2]).map(|c| c as Float / 255.);
+        let linear = encoded.map(|c| encoding.transfer().decode(c));
+        encoding.primaries().conversion_to(self.primaries()) * linear
+    }
+}
+
+impl FromStr for ColorSpace {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s {
+            "linear-srgb" | "rec709" => Ok(ColorSpace::LinearSrgb),
+            "acescg" | "aces-cg" => Ok(ColorSpace::Acescg),
+            "rec2020" => Ok(ColorSpace::Rec2020),
+            _ => Err(Error::InvalidConfig(format!(
+                "`{}` is not a working color space; expected linear-srgb, acescg or rec2020",
+                s
+            ))),
+        }
+    }
+}
+
+/// The curve which maps linear light to the encoded values of an image, and back.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum TransferFunction {
+    /// IEC 61966-2-1.
+    Srgb,
+    /// The camera curve of ITU-R BT.709, also used by BT.2020.
+    Bt709,
+}
+
+impl TransferFunction {
+    pub fn encode(&self, linear: Float) -> Float {
+        let linear = linear.clamp(0., 1.);
+        match self {
+            TransferFunction::Srgb if linear <= 0.0031308 => 12.92 * linear,
+            TransferFunction::Srgb => 1.055 * linear.powf(1. / 2.4) - 0.055,
+            TransferFunction::Bt709 if linear < 0.018 => 4.5 * linear,
+            TransferFunction::Bt709 => 1.099 * linear.powf(0.45) - 0.099,
+        }
+    }
+
+    pub fn decode(&self, encoded: Float) -> Float {
+        let encoded = encoded.clamp(0., 1.);
+        match self {
+            TransferFunction::Srgb if encoded <= 0.04045 => encoded / 12.92,
+            TransferFunction::Srgb => ((encoded + 0.055) / 1.055).powf(2.4),
+            TransferFunction::Bt709 if encoded < 0.081 => encoded / 4.5,
+            TransferFunction::Bt709 => ((encoded + 0.099) / 1.099).powf(1. / 0.45),
+        }
+    }
+}
+
+/// How colors are stored in an image file: its primaries and transfer function. Output images
+/// are tagged with their encoding, so that they display consistently.
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum Encoding {
+    #[default]
+    Srgb,
+    Rec709,
+    Rec2020,
+    DisplayP3,
+}
+
+impl Encoding {
+    pub fn primaries(&self) -> &'static Primaries {
+        match self {
+            Encoding::Srgb | Encoding::Rec709 => &REC709,
+            Encoding::Rec2020 => &REC2020,
+            Encoding::DisplayP3 => &DISPLAY_P3,
+        }
+    }
+
+    pub fn transfer(&self) -> TransferFunction {
+        match self {
+            Encoding::Srgb | Encoding::DisplayP3 => TransferFunction::Srgb,
+            Encoding::Rec709 | Encoding::Rec2020 => TransferFunction::Bt709,
+        }
+    }
+
+    /// The coding-independent code points (ITU-T H.273) of the encoding: color primaries,
+    /// transfer characteristics, matrix coefficients (none, since the image is RGB) and full
+    /// range flag.
+    pub fn cicp(&self) -> [u8; 4] {
+        match self {
+            Encoding::Srgb => [1, 13, 0, 1],
+            Encoding::Rec709 => [1, 1, 0, 1],
+            Encoding::Rec2020 => [9, 14, 0, 1],
+            Encoding::DisplayP3 => [12, 13, 0, 1],
+        }
+    }
+
+    fn description(&self) -> &'static str {
+        match self {
+            Encoding::Srgb => "sRGB",
+            Encoding::Rec709 => "Rec. ITU-R BT.709",
+            Encoding::Rec2020 => "Rec. ITU-R BT.2020",
+            Encoding::DisplayP3 => "Display P3",
+        }
+    }
+
+    /// A version 2 ICC display profile of the encoding, made of its primaries (adapted to the D50
+    /// profile connection space) and its transfer function.
+    pub fn icc_profile(&self) -> Vec<u8> {
+        let s15 = |value: Float| ((value * 65536.).round() as i32).to_be_bytes();
+        let xyz_tag = |xyz: Vector3<Float>| {
+            let mut tag = b"XYZ \0\0\0\0".to_vec();
+            xyz.iter().for_each(|&c| tag.extend(s15(c)));
+            tag
+        };
+
+        let mut description = b"desc\0\0\0\0".to_vec();
+        description.extend((self.description().len() as u32 + 1).to_be_bytes());
+        description.extend(self.description().as_bytes());
+        description.extend([0; 1 + 4 + 4 + 2 + 1 + 67]);
+
+        let mut copyright = b"text\0\0\0\0".to_vec();
+        copyright.extend(b"No copyright, use freely\0");
+
+        let transfer = self.transfer();
+        let mut curve = b"curv\0\0\0\0".to_vec();
+        curve.extend(1024u32.to_be_bytes());
+        for i in 0..1024 {
+            let linear = transfer.decode(i as Float / 1023.);
+            curve.extend(((linear * 65535.).round() as u16).to_be_bytes());
+        }
+
+        let to_pcs =
+            chromatic_adaptation(self.primaries().white, D50) * self.primaries().rgb_to_xyz();
+        let d50 = Vector3::new(D50.0 / D50.1, 1., (1. - D50.0 - D50.1) / D50.1);
+        let tags: Vec<(&[u8; 4], Vec<u8>)> = vec![
+            (b"desc", description),
+            (b"cprt", copyright),
+            (b"wtpt", xyz_tag(d50)),
+            (b"rXYZ", xyz_tag(to_pcs.column(0).into())),
+            (b"gXYZ", xyz_tag(to_pcs.column(1).into())),
+            (b"bXYZ", xyz_tag(to_pcs.column(2).into())),
+            (b"rTRC", curve.clone()),
+            (b"gTRC", curve.clone()),
+            (b"bTRC", curve),
+        ];
+
+        let mut table = Vec::new();
+        let mut data = Vec::new();
+        let data_start = 128 + 4 + 12 * tags.len();
+        for (signature, tag) in &tags {
+            table.extend(*signature);
+            table.extend(((data_start + data.len()) as u32).to_be_bytes());
+            table.extend((tag.len() as u32).to_be_bytes());
+            data.extend(tag);
+            // Tags start at multiples of 4 bytes.
+            data.resize(data.len().div_ceil(4) * 4, 0);
+        }
+
+        let mut header = Vec::with_capacity(128);
+        header.extend(((data_start + data.len()) as u32).to_be_bytes());
+        header.extend([0; 4]); // preferred CMM
+        header.extend(0x0210_0000u32.to_be_bytes()); // version 2.1
+        header.extend(b"mntrRGB XYZ ");
+        header.extend([0; 12]); // creation date
+        header.extend(b"acsp");
+        header.extend([0; 24]); // platform, flags, manufacturer, model, attributes
+        header.extend([0; 4]); // perceptual rendering intent
+        d50.iter().for_each(|&c| header.extend(s15(c)));
+        header.resize(128, 0);
+
+        [
+            header,
+            (tags.len() as u32).to_be_bytes().to_vec(),
+            table,
+            data,
+        ]
+        .concat()
+    }
+
+    /// Converts a linear color of the `working` space into 8-bit values of this encoding.
+    pub fn encode_pixel(&self, color: &Vector3<Float>, working: ColorSpace) -> [u8; 3] {
+        let converted = working.primaries().conversion_to(self.primaries()) * color;
+        let encoded = converted.map(|c| (self.transfer().encode(c) * 255.).round() as u8);
+        [encoded.x, encoded.y, encoded.z]
+    }
+
+    /// Writes an 8-bit RGB image as a PNG file which is tagged with the encoding: its cICP code
+    /// points and an embedded ICC profile (or the sRGB chunk, for sRGB), for readers which do not
+    /// support cICP.
+    pub fn write_png<P: AsRef<Path>>(&self, path: P, image: &image::RgbImage) -> Result<()> {
+        let file = File::create(path.as_ref())?;
+        let mut encoder = png::Encoder::new(BufWriter::new(file), image.width(), image.height());
+        encoder.set_color(png::ColorType::Rgb);
+        encoder.set_depth(png::BitDepth::Eight);
+
+        let mut writer = encoder.write_header()?;
+        writer.write_chunk(png::chunk::ChunkType(*b"cICP"), &self.cicp())?;
+        if *self == Encoding::Srgb {
+            writer.write_chunk(png::chunk::sRGB, &[0])?;
+        } else {
+            let mut iccp = self.description().as_bytes().to_vec();
+            iccp.extend([0, 0]); // end of the profile name; deflate compression
+            iccp.extend(deflate::deflate_bytes_zlib(&self.icc_profile()));
+            writer.write_chunk(png::chunk::iCCP, &iccp)?;
+        }
+
+        writer.write_image_data(image.as_raw())?;
+        writer.finish()?;
+        Ok(())
+    }
+}
+
+impl FromStr for Encoding {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s {
+            "srgb" => Ok(Encoding::Srgb),
+            "rec709" => Ok(Encoding::Rec709),
+            "rec2020" => Ok(Encoding::Rec2020),
+            "display-p3" => Ok(Encoding::DisplayP3),
+            _ => Err(Error::InvalidConfig(format!(
+                "`{}` is not an output encoding; expected srgb, rec709, rec2020 or display-p3",
+                s
+            ))),
+        }
+    }
+}
@@ -1,6 +1,18 @@
+use crate::camera::{CameraSettings, Focus, Projection};
+use crate::color::{ColorSpace, Encoding};
+use crate::error::Error;
+use crate::filter::{Filter, FilterKind};
+use crate::float::Float;
+use crate::sampler::SamplerKind;
 use clap::Parser;
+use nalgebra::{Point3, Vector2, Vector3};
 use regex::Regex;
+use serde::{Deserialize, Deserializer};
 
+use std::collections::HashMap;
+use std::env;
+use std::fs;
+use std::path::{Path, PathBuf};
 use std::str::FromStr;
 
 /// Models the size of an image.
@@ -10,47 +22,497 @@ pub struct Resolution {
     pub height: u32,
 }
 
+/// Name of the environment variable which selects the configuration file profile when
+/// `--profile` is not given.
+pub const PROFILE_ENV_VAR: &str = "RTOW_PROFILE";
+
 /// Describes the properties of the output image.
 ///
 /// * `resolution`: self explanatory
 /// * `samples`: the number of rays that are randomly sent through each pixel and then averaged
-/// together; a high number of samples provides more accurate colors, less noise and better
-/// anti-aliasing.
+///   together; a high number of samples provides more accurate colors, less noise and better
+///   anti-aliasing.
 /// * `output_filename`: self explanatory
-#[derive(Clone, Debug, Parser)]
+/// * `scene`: the scene file to render; a random scene is generated when missing
+/// * `camera_name`: the camera bookmark of the scene file to look through
+/// * `camera`: camera settings which override the ones from the scene
+/// * `frames`: the frames of the animation to render as an image sequence; a single image is
+///   rendered when missing
+/// * `skip_existing`: do not render the frames whose image already exists
+/// * `spectral`: trace wavelengths instead of RGB channels (see `Renderer::spectral`)
+/// * `scalar`: trace every camera ray on its own instead of in packets (see `Renderer::packets`)
+/// * `working_space`: the linear color space of the scene colors and of the rendering
+/// * `output_encoding`: the color encoding of the saved images
+/// * `filter`: the filter which reconstructs the pixels out of the samples
+/// * `sampler`: the sampler which supplies the random numbers of each sample
+/// * `seed`: the seed of the sampler and of the random scene
+/// * `stats`: the JSON file into which the render statistics are saved
+/// * `validate`: only check the scene for mistakes (see `validate::validate`), without rendering
+#[derive(Clone, Debug)]
 pub struct Config {
-    #[clap(short, long, default_value_t = Resolution::from_str("1080p").unwrap())]
     pub resolution: Resolution,
-    #[clap(long, default_value_t = 60.0)]
-    pub fov: f64,
-    #[clap(short, long, default_value_t = 10)]
     pub samples: u32,
-    #[clap(short, long, default_value_t = String::from("out.png"))]
     pub output_filename: String,
-    #[clap(short, long, default_value_t = num_cpus::get())]
     pub threads: usize,
+    pub scene: Option<PathBuf>,
+    pub camera_name: Option<String>,
+    pub camera: CameraSettings,
+    pub frames: Option<FrameRange>,
+    pub skip_existing: bool,
+    pub spectral: bool,
+    pub scalar: bool,
+    pub working_space: ColorSpace,
+    pub output_encoding: Encoding,
+    pub filter: Filter,
+    pub sampler: SamplerKind,
+    pub seed: u64,
+    pub stats: Option<PathBuf>,
+    pub validate: bool,
+}
+
+/// A range of animation frames, written as `START..END` (excluding `END`), `START..=END`
+/// (including `END`) or as a single frame number.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct FrameRange {
+    pub start: u32,
+    pub end: u32,
+}
+
+/// Command line arguments. Every setting is optional: the ones which are missing are taken from
+/// the configuration file (if any) and then from the defaults.
+#[derive(Clone, Debug, Default, Parser)]
+#[clap(version, about)]
+pub struct Args {
+    #[clap(short, long)]
+    pub resolution: Option<Resolution>,
+    /// Vertical field of view, in degrees.
+    #[clap(long)]
+    pub fov: Option<Float>,
+    #[clap(short, long)]
+    pub samples: Option<u32>,
+    #[clap(short, long)]
+    pub output_filename: Option<String>,
+    #[clap(short, long)]
+    pub threads: Option<usize>,
+    /// TOML scene file. A random scene is rendered when missing.
+    #[clap(long)]
+    pub scene: Option<PathBuf>,
+    /// Named camera (bookmark) of the scene file to render through.
+    #[clap(long = "camera")]
+    pub camera_name: Option<String>,
+    /// Camera model: perspective, orthographic, fisheye-equidistant, fisheye-equisolid,
+    /// equirectangular or tilt-shift.
+    #[clap(long)]
+    pub projection: Option<Projection>,
+    /// Position of the camera, e.g. `-2,1.7,0`.
+    #[clap(long, parse(try_from_str = parse_point), allow_hyphen_values = true)]
+    pub look_from: Option<Point3<Float>>,
+    /// Point the camera looks at, e.g. `0,1,-8`.
+    #[clap(long, parse(try_from_str = parse_point), allow_hyphen_values = true)]
+    pub look_at: Option<Point3<Float>>,
+    /// Upwards direction of the camera, e.g. `0,1,0`.
+    #[clap(long, parse(try_from_str = parse_vector), allow_hyphen_values = true)]
+    pub up: Option<Vector3<Float>>,
+    /// Diameter of the lens; `0` disables the depth of field.
+    #[clap(long)]
+    pub aperture: Option<Float>,
+    /// Focus distance (e.g. `8.5`), point to focus on (e.g. `0,1,-8`) or `auto` for focusing on
+    /// whatever is in the center of the image.
+    #[clap(long, allow_hyphen_values = true)]
+    pub focus: Option<Focus>,
+    /// Height of the area seen by an orthographic camera.
+    #[clap(long)]
+    pub view_height: Option<Float>,
+    /// Tilt of a tilt-shift lens, in degrees.
+    #[clap(long, allow_hyphen_values = true)]
+    pub tilt: Option<Float>,
+    /// Shift of a tilt-shift lens, as fractions of the image width and height, e.g. `0,0.2`.
+    #[clap(long, parse(try_from_str = parse_pair), allow_hyphen_values = true)]
+    pub shift: Option<Vector2<Float>>,
+    /// Number of aperture blades; makes out of focus highlights polygonal.
+    #[clap(long)]
+    pub blades: Option<u32>,
+    /// Rotation of the aperture blades, in degrees.
+    #[clap(long, allow_hyphen_values = true)]
+    pub blade_rotation: Option<Float>,
+    /// Grayscale image which gives the shape of the aperture.
+    #[clap(long)]
+    pub aperture_mask: Option<PathBuf>,
+    /// Strength of the cat's eye vignetting; 0 disables it.
+    #[clap(long)]
+    pub cat_eye: Option<Float>,
+    /// Frames of the animation to render, e.g. `0..48`. The frame number is written into the
+    /// output filename, replacing a run of `#` characters (e.g. `frame_####.png`) or before the
+    /// extension.
+    #[clap(long)]
+    pub frames: Option<FrameRange>,
+    /// Do not render the frames whose image already exists.
+    #[clap(long)]
+    pub skip_existing: bool,
+    /// Render in spectral mode, which models dispersion and the measured colors of metals.
+    #[clap(long)]
+    pub spectral: bool,
+    /// Trace every camera ray on its own instead of in SIMD packets; the image is the same.
+    #[clap(long)]
+    pub scalar: bool,
+    /// Linear color space of the scene colors and of the rendering: linear-srgb, acescg or
+    /// rec2020.
+    #[clap(long)]
+    pub working_space: Option<ColorSpace>,
+    /// Color encoding of the output image: srgb, rec709, rec2020 or display-p3. PNG files are
+    /// tagged with it.
+    #[clap(long)]
+    pub output_encoding: Option<Encoding>,
+    /// Pixel reconstruction filter: box, tent, gaussian, mitchell or lanczos.
+    #[clap(long)]
+    pub filter: Option<FilterKind>,
+    /// Radius of the reconstruction filter, in pixels. Each filter has its own default.
+    #[clap(long)]
+    pub filter_radius: Option<Float>,
+    /// Sampler: independent, stratified, halton, sobol or blue-noise.
+    #[clap(long)]
+    pub sampler: Option<SamplerKind>,
+    /// Seed of the sampler and of the random scene; renders with the same seed are identical.
+    #[clap(long)]
+    pub seed: Option<u64>,
+    /// JSON file into which the render statistics (rays, intersection tests, timings and peak
+    /// memory) are saved; they are printed in any case.
+    #[clap(long)]
+    pub stats: Option<PathBuf>,
+    /// Check the scene for mistakes (e.g. overlapping spheres or materials which reflect more
+    /// light than they receive) and print them instead of rendering.
+    #[clap(long)]
+    pub validate: bool,
+    /// TOML configuration file.
+    #[clap(short, long)]
+    pub config: Option<PathBuf>,
+    /// Profile of the configuration file to use. Defaults to the value of `$RTOW_PROFILE`.
+    #[clap(short, long)]
+    pub profile: Option<String>,
+}
+
+/// A partial `Config`, as found in the configuration file. Missing values fall back to the next
+/// layer (command line > profile > top level of the file > defaults).
+#[derive(Clone, Debug, Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Settings {
+    pub resolution: Option<Resolution>,
+    pub fov: Option<Float>,
+    pub samples: Option<u32>,
+    #[serde(alias = "output")]
+    pub output_filename: Option<String>,
+    pub threads: Option<usize>,
+    pub scene: Option<PathBuf>,
+    #[serde(alias = "camera")]
+    pub camera_name: Option<String>,
+    pub frames: Option<FrameRange>,
+    pub skip_existing: Option<bool>,
+    pub spectral: Option<bool>,
+    pub scalar: Option<bool>,
+    pub working_space: Option<ColorSpace>,
+    pub output_encoding: Option<Encoding>,
+    pub filter: Option<FilterKind>,
+    pub filter_radius: Option<Float>,
+    pub sampler: Option<SamplerKind>,
+    pub seed: Option<u64>,
+    pub stats: Option<PathBuf>,
+}
+
+/// The contents of a TOML configuration file. Top level settings apply to every profile, while
+/// each `[profiles.<name>]` table overrides them:
+///
+/// ```toml
+/// samples = 10
+///
+/// [profiles.preview]
+/// resolution = "640x360"
+/// samples = 4
+///
+/// [profiles.final]
+/// resolution = "4k"
+/// samples = 500
+/// output = "final.png"
+/// ```
+#[derive(Clone, Debug, Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct ConfigFile {
+    #[serde(flatten)]
+    pub settings: Settings,
+    #[serde(default)]
+    pub profiles: HashMap<String, Settings>,
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Config {
+            resolution: Resolution {
+                width: 1920,
+                height: 1080,
+            },
+            samples: 10,
+            output_filename: String::from("out.png"),
+            threads: num_cpus::get(),
+            scene: None,
+            camera_name: None,
+            camera: CameraSettings::default(),
+            frames: None,
+            skip_existing: false,
+            spectral: false,
+            scalar: false,
+            working_space: ColorSpace::default(),
+            output_encoding: Encoding::default(),
+            filter: Filter::default(),
+            sampler: SamplerKind::default(),
+            seed: 0,
+            stats: None,
+            validate: false,
+        }
+    }
+}
+
+impl Config {
+    /// Builds the final configuration out of the command line arguments, the configuration file
+    /// they point to and the profile selected either by `--profile` or by `$RTOW_PROFILE`.
+    pub fn from_args(args: Args) -> Result<Config, Error> {
+        let env_profile = env::var(PROFILE_ENV_VAR).ok().filter(|p| !p.is_empty());
+
+        let file_settings = match &args.config {
+            Some(path) => {
+                let file = ConfigFile::load(path)?;
+                match args.profile.as_ref().or(env_profile.as_ref()) {
+                    Some(name) => file.profile(name)?,
+                    None => file.settings,
+                }
+            }
+            None => {
+                if let Some(name) = &args.profile {
+                    return Err(Error::InvalidConfig(format!(
+                        "profile `{}` was requested, but no configuration file was given",
+                        name
+                    )));
+                }
+                Settings::default()
+            }
+        };
+
+        let camera = args.camera_settings();
+        let mut cfg = args.settings().or(file_settings).apply(Config::default());
+        cfg.camera = camera.or(cfg.camera);
+        cfg.validate = args.validate;
+        Ok(cfg)
+    }
+}
+
+impl Config {
+    /// The file in which a frame of the animation is saved: the first run of `#` characters in
+    /// the output filename is replaced by the zero padded frame number; without such a run, the
+    /// frame number is inserted before the extension.
+    pub fn output_for_frame(&self, frame: u32) -> String {
+        let name = &self.output_filename;
+        match name.find('#') {
+            Some(start) => {
+                let width = name[start..].chars().take_while(|&c| c == '#').count();
+                format!(
+                    "{}{:0width$}{}",
+                    &name[..start],
+                    frame,
+                    &name[start + width..],
+                    width = width
+                )
+            }
+            None => {
+                let path = Path::new(name);
+                let stem = path.file_stem().and_then(|s| s.to_str()).unwrap_or("");
+                let numbered = match path.extension().and_then(|s| s.to_str()) {
+                    Some(extension) => format!("{}_{:04}.{}", stem, frame, extension),
+                    None => format!("{}_{:04}", stem, frame),
+                };
+                path.with_file_name(numbered).to_string_lossy().into_owned()
+            }
+        }
+    }
+}
+
+impl FrameRange {
+    pub fn iter(&self) -> std::ops::Range<u32> {
+        self.start..self.end
+    }
+}
+
+impl FromStr for FrameRange {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self, <Self as FromStr>::Err> {
+        let invalid = || {
+            Error::InvalidConfig(format!(
+                "`{}` is not a frame range; expected START..END, START..=END or a single frame",
+                s
+            ))
+        };
+        let parse = |n: &str| n.trim().parse::<u32>().map_err(|_| invalid());
+
+        let (start, end) = if let Some((start, end)) = s.split_once("..=") {
+            (
+                parse(start)?,
+                parse(end)?.checked_add(1).ok_or_else(invalid)?,
+            )
+        } else if let Some((start, end)) = s.split_once("..") {
+            (parse(start)?, parse(end)?)
+        } else {
+            let frame = parse(s)?;
+            (frame, frame.checked_add(1).ok_or_else(invalid)?)
+        };
+
+        if start >= end {
+            return Err(Error::InvalidConfig(format!(
+                "the frame range `{}` is empty",
+                s
+            )));
+        }
+        Ok(FrameRange { start, end })
+    }
 }
 
-impl<T> From<T> for Resolution
-where
-    T: Into<String>,
-{
-    fn from(s: T) -> Self {
-        let s = s.into();
+impl<'de> Deserialize<'de> for FrameRange {
+    fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
+    where
+        D: Deserializer<'de>,
+    {
+        let s = String::deserialize(deserializer)?;
+        FrameRange::from_str(&s).map_err(serde::de::Error::custom)
+    }
+}
 
-        let re = Regex::new(r"(\d+)[xX](\d+)").unwrap();
-        let cap = re.captures(&s).unwrap();
+impl Args {
+    fn settings(&self) -> Settings {
+        Settings {
+            resolution: self.resolution.clone(),
+            fov: self.fov,
+            samples: self.samples,
+            output_filename: self.output_filename.clone(),
+            threads: self.threads,
+            scene: self.scene.clone(),
+            camera_name: self.camera_name.clone(),
+            frames: self.frames,
+            skip_existing: self.skip_existing.then_some(true),
+            spectral: self.spectral.then_some(true),
+            scalar: self.scalar.then_some(true),
+            working_space: self.working_space,
+            output_encoding: self.output_encoding,
+            filter: self.filter,
+            filter_radius: self.filter_radius,
+            sampler: self.sampler,
+            seed: self.seed,
+            stats: self.stats.clone(),
+        }
+    }
 
-        Resolution {
-            width: cap[1].parse::<u32>().unwrap(),
-            height: cap[2].parse::<u32>().unwrap(),
+    fn camera_settings(&self) -> CameraSettings {
+        CameraSettings {
+            projection: self.projection,
+            look_from: self.look_from,
+            look_at: self.look_at,
+            up: self.up,
+            aperture: self.aperture,
+            focus: self.focus,
+            fov: None,
+            view_height: self.view_height,
+            tilt: self.tilt,
+            shift: self.shift,
+            blades: self.blades,
+            blade_rotation: self.blade_rotation,
+            aperture_mask: self.aperture_mask.clone(),
+            cat_eye: self.cat_eye,
         }
     }
 }
 
-impl std::str::FromStr for Resolution {
-    type Err = regex::Error;
+impl Settings {
+    /// Fills in the values missing from `self` with the ones from `fallback`.
+    pub fn or(self, fallback: Settings) -> Settings {
+        Settings {
+            resolution: self.resolution.or(fallback.resolution),
+            fov: self.fov.or(fallback.fov),
+            samples: self.samples.or(fallback.samples),
+            output_filename: self.output_filename.or(fallback.output_filename),
+            threads: self.threads.or(fallback.threads),
+            scene: self.scene.or(fallback.scene),
+            camera_name: self.camera_name.or(fallback.camera_name),
+            frames: self.frames.or(fallback.frames),
+            skip_existing: self.skip_existing.or(fallback.skip_existing),
+            spectral: self.spectral.or(fallback.spectral),
+            scalar: self.scalar.or(fallback.scalar),
+            working_space: self.working_space.or(fallback.working_space),
+            output_encoding: self.output_encoding.or(fallback.output_encoding),
+            filter: self.filter.or(fallback.filter),
+            filter_radius: self.filter_radius.or(fallback.filter_radius),
+            sampler: self.sampler.or(fallback.sampler),
+            seed: self.seed.or(fallback.seed),
+            stats: self.stats.or(fallback.stats),
+        }
+    }
 
+    /// Overrides the values of `cfg` with the ones present in `self`.
+    pub fn apply(self, cfg: Config) -> Config {
+        Config {
+            resolution: self.resolution.unwrap_or(cfg.resolution),
+            samples: self.samples.unwrap_or(cfg.samples),
+            output_filename: self.output_filename.unwrap_or(cfg.output_filename),
+            threads: self.threads.unwrap_or(cfg.threads),
+            scene: self.scene.or(cfg.scene),
+            camera_name: self.camera_name.or(cfg.camera_name),
+            camera: CameraSettings {
+                fov: self.fov.or(cfg.camera.fov),
+                ..cfg.camera
+            },
+            frames: self.frames.or(cfg.frames),
+            skip_existing: self.skip_existing.unwrap_or(cfg.skip_existing),
+            spectral: self.spectral.unwrap_or(cfg.spectral),
+            scalar: self.scalar.unwrap_or(cfg.scalar),
+            working_space: self.working_space.unwrap_or(cfg.working_space),
+            output_encoding: self.output_encoding.unwrap_or(cfg.output_encoding),
+            filter: {
+                let filter = self.filter.map_or(cfg.filter, Filter::new);
+                self.filter_radius
+                    .map_or(filter, |radius| filter.with_radius(radius))
+            },
+            sampler: self.sampler.unwrap_or(cfg.sampler),
+            seed: self.seed.unwrap_or(cfg.seed),
+            stats: self.stats.or(cfg.stats),
+            validate: cfg.validate,
+        }
+    }
+}
+
+impl ConfigFile {
+    pub fn load<P: AsRef<Path>>(path: P) -> Result<ConfigFile, Error> {
+        let path = path.as_ref();
+        let contents = fs::read_to_string(path).map_err(|err| Error::from(err).with_path(path))?;
+        toml::from_str(&contents)
+            .map_err(|err| Error::InvalidConfig(format!("{}: {}", path.display(), err)))
+    }
+
+    /// The settings of the profile called `name`, layered over the top level settings.
+    pub fn profile(&self, name: &str) -> Result<Settings, Error> {
+        match self.profiles.get(name) {
+            Some(profile) => Ok(profile.clone().or(self.settings.clone())),
+            None => {
+                let mut known: Vec<&String> = self.profiles.keys().collect();
+                known.sort();
+                Err(Error::InvalidConfig(format!(
+                    "unknown profile `{}` (available: {:?})",
+                    name, known
+                )))
+            }
+        }
+    }
+}
+
+impl FromStr for Resolution {
+    type Err = Error;
+
+    /// Accepts either one of the named resolutions (`720p`, `1080p`, `4k`, `8k`) or an explicit
+    /// `WIDTHxHEIGHT` (e.g. `800x400`).
     fn from_str(s: &str) -> Result<Self, <Self as FromStr>::Err> {
         match s {
             "720p" => Ok(Resolution {
@@ -70,11 +532,23 @@ impl std::str::FromStr for Resolution {
                 height: 4320,
             }),
             s_ => {
-                let re = Regex::new(r"(\d+)[xX](\d+)")?;
-                let cap = re.captures(s_).unwrap();
+                let invalid = || {
+                    Error::InvalidConfig(format!(
+                        "`{}` is not a resolution; expected WIDTHxHEIGHT (e.g. 800x400), 720p, \
+                         1080p, 4k or 8k",
+                        s_
+                    ))
+                };
 
-                let width = cap[1].parse::<u32>().unwrap();
-                let height = cap[2].parse::<u32>().unwrap();
+                let re = Regex::new(r"^(\d+)[xX](\d+)$").unwrap();
+                let cap = re.captures(s_).ok_or_else(invalid)?;
+
+                let width = cap[1].parse::<u32>().map_err(|_| invalid())?;
+                let height = cap[2].parse::<u32>().map_err(|_| invalid())?;
+
+                if width == 0 || height == 0 {
+                    return Err(invalid());
+                }
 
                 Ok(Resolution { width, height })
             }
@@ -87,3 +561,51 @@ impl std::fmt::Display for Resolution {
         write!(f, "{}x{}", self.width, self.height)
     }
 }
+
+/// Parses three comma separated numbers, e.g. `0,1.5,-2`.
+pub fn parse_vector(s: &str) -> Result<Vector3<Float>, Error> {
+    let coords = s
+        .split(',')
+        .map(|c| c.trim().parse::<Float>())
+        .collect::<Result<Vec<Float>, _>>()
+        .ok()
+        .filter(|coords| coords.len() == 3)
+        .ok_or_else(|| {
+            Error::InvalidConfig(format!(
+                "`{}` is not a 3D vector; expected X,Y,Z (e.g. 0,1.5,-2)",
+                s
+            ))
+        })?;
+
+    Ok(Vector3::new(coords[0], coords[1], coords[2]))
+}
+
+/// Parses two comma separated numbers, e.g. `0,0.2`.
+pub fn parse_pair(s: &str) -> Result<Vector2<Float>, Error> {
+    match s
+        .split(',')
+        .map(|c| c.trim().parse::<Float>())
+        .collect::<Result<Vec<Float>, _>>()
+    {
+        Ok(coords) if coords.len() == 2 => Ok(Vector2::new(coords[0], coords[1])),
+        _ => Err(Error::InvalidConfig(format!(
+            "`{}` is not a pair of numbers; expected X,Y (e.g. 0,0.2)",
+            s
+        ))),
+    }
+}
+
+/// Parses a point written as three comma separated coordinates, e.g. `0,1.5,-2`.
+pub fn parse_point(s: &str) -> Result<Point3<Float>, Error> {
+    parse_vector(s).map(Point3::from)
+}
+
+impl<'de> Deserialize<'de> for Resolution {
+    fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
+    where
+        D: Deserializer<'de>,
+    {
+        let s = String::deserialize(deserializer)?;
+        Resolution::from_str(&s).map_err(serde::de::Error::custom)
+    }
+}
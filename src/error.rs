@@ -0,0 +1,91 @@
+use std::fmt;
+use std::io;
+
+/// Everything that can go wrong while configuring, rendering or saving an image.
+#[derive(Debug)]
+pub enum Error {
+    /// The configuration is malformed or inconsistent, e.g. an unparsable resolution.
+    InvalidConfig(String),
+    /// The scene cannot be rendered as requested, e.g. the render region does not fit inside the
+    /// image.
+    Scene(String),
+    /// Reading or writing a file failed.
+    Io(io::Error),
+    /// Encoding or decoding an image failed.
+    Image(image::ImageError),
+}
+
+/// Shorthand for results whose error type is the crate `Error`.
+pub type Result<T> = std::result::Result<T, Error>;
+
+impl Error {
+    /// The process exit code which corresponds to the error. Invalid configurations exit with
+    /// `2` (the same code that `clap` uses for usage errors), scene errors with `3` and I/O
+    /// failures with `4`.
+    pub fn exit_code(&self) -> i32 {
+        match self {
+            Error::InvalidConfig(_) => 2,
+            Error::Scene(_) => 3,
+            Error::Io(_) | Error::Image(_) => 4,
+        }
+    }
+
+    /// Prefixes I/O errors with the path of the file that was being accessed, so that the
+    /// message explains which file could not be read or written.
+    pub fn with_path<P: AsRef<std::path::Path>>(self, path: P) -> Error {
+        match self {
+            Error::Io(err) => Error::Io(io::Error::new(
+                err.kind(),
+                format!("{}: {}", path.as_ref().display(), err),
+            )),
+            err => err,
+        }
+    }
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Error::InvalidConfig(msg) => write!(f, "invalid configuration: {}", msg),
+            Error::Scene(msg) => write!(f, "invalid scene: {}", msg),
+            Error::Io(err) => write!(f, "I/O error: {}", err),
+            Error::Image(err) => write!(f, "image error: {}", err),
+        }
+    }
+}
+
+impl std::error::Error for Error {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            Error::InvalidConfig(_) | Error::Scene(_) => None,
+            Error::Io(err) => Some(err),
+            Error::Image(err) => Some(err),
+        }
+    }
+}
+
+impl From<io::Error> for Error {
+    fn from(err: io::Error) -> Self {
+        Error::Io(err)
+    }
+}
+
+impl From<png::EncodingError> for Error {
+    fn from(err: png::EncodingError) -> Self {
+        match err {
+            png::EncodingError::IoError(err) => Error::Io(err),
+            err => Error::Image(image::ImageError::Encoding(
+                image::error::EncodingError::new(image::ImageFormat::Png.into(), err),
+            )),
+        }
+    }
+}
+
+impl From<image::ImageError> for Error {
+    fn from(err: image::ImageError) -> Self {
+        match err {
+            image::ImageError::IoError(err) => Error::Io(err),
+            err => Error::Image(err),
+        }
+    }
+}
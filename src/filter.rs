@@ -0,0 +1,134 @@
+use crate::error::{Error, Result};
+use crate::float::{consts, Float};
+use serde::Deserialize;
+use std::str::FromStr;
+
+/// The shapes of the available reconstruction filters.
+///
+/// * `Box`: every sample within the radius counts equally; with the default radius of half a
+///   pixel, each sample only contributes to its own pixel.
+/// * `Tent`: the weight decreases linearly with the distance.
+/// * `Gaussian`: a Gaussian bell, whose standard deviation is a third of the radius.
+/// * `Mitchell`: the Mitchell–Netravali cubic with `B = C = 1/3`, which sharpens slightly.
+/// * `Lanczos`: a windowed sinc, which sharpens the most but may ring around hard edges.
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum FilterKind {
+    Box,
+    Tent,
+    Gaussian,
+    Mitchell,
+    Lanczos,
+}
+
+/// A pixel reconstruction filter: every sample contributes to all the pixels whose center is
+/// closer than `radius` (measured in pixels, along each axis), weighted by the filter.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct Filter {
+    pub kind: FilterKind,
+    pub radius: Float,
+}
+
+impl Default for Filter {
+    fn default() -> Self {
+        Filter::new(FilterKind::Box)
+    }
+}
+
+impl Filter {
+    /// A filter of the given kind, with its customary radius.
+    pub fn new(kind: FilterKind) -> Filter {
+        let radius = match kind {
+            FilterKind::Box => 0.5,
+            FilterKind::Tent => 1.,
+            FilterKind::Gaussian => 1.5,
+            FilterKind::Mitchell => 2.,
+            FilterKind::Lanczos => 3.,
+        };
+        Filter { kind, radius }
+    }
+
+    pub fn with_radius(self, radius: Float) -> Filter {
+        Filter { radius, ..self }
+    }
+
+    /// The weight of a sample at an offset of `(dx, dy)` pixels from the center of a pixel.
+    /// Filters are separable: the weight is the product of the weights along each axis.
+    pub fn weight(&self, dx: Float, dy: Float) -> Float {
+        self.weight_1d(dx) * self.weight_1d(dy)
+    }
+
+    fn weight_1d(&self, x: Float) -> Float {
+        let x = x.abs();
+        if x > self.radius {
+            return 0.;
+        }
+
+        match self.kind {
+            FilterKind::Box => 1.,
+            FilterKind::Tent => 1. - x / self.radius,
+            FilterKind::Gaussian => {
+                let sigma = self.radius / 3.;
+                let gaussian = |x: Float| (-x * x / (2. * sigma * sigma)).exp();
+                // Shifted down so that the weight reaches zero at the radius.
+                gaussian(x) - gaussian(self.radius)
+            }
+            FilterKind::Mitchell => {
+                let (b, c) = (1. / 3., 1. / 3.);
+                let x = 2. * x / self.radius;
+                if x < 1. {
+                    ((12. - 9. * b - 6. * c) * x.powi(3)
+                        + (-18. + 12. * b + 6. * c) * x.powi(2)
+                        + (6. - 2. * b))
+                        / 6.
+                } else {
+                    ((-b - 6. * c) * x.powi(3)
+                        + (6. * b + 30. * c) * x.powi(2)
+                        + (-12. * b - 48. * c) * x
+                        + (8. * b + 24. * c))
+                        / 6.
+                }
+            }
+            FilterKind::Lanczos => sinc(x) * sinc(x / self.radius),
+        }
+    }
+
+    /// Checks that the radius is usable: positive, and small enough that a sample does not
+    /// spread over an unreasonable number of pixels.
+    pub fn validate(&self) -> Result<()> {
+        if self.radius > 0. && self.radius <= 16. {
+            Ok(())
+        } else {
+            Err(Error::InvalidConfig(format!(
+                "the filter radius must be between 0 and 16 pixels, not {}",
+                self.radius
+            )))
+        }
+    }
+}
+
+fn sinc(x: Float) -> Float {
+    if x.abs() < 1e-5 {
+        1.
+    } else {
+        (consts::PI * x).sin() / (consts::PI * x)
+    }
+}
+
+impl FromStr for FilterKind {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s {
+            "box" => Ok(FilterKind::Box),
+            "tent" => Ok(FilterKind::Tent),
+            "gaussian" => Ok(FilterKind::Gaussian),
+            "mitchell" => Ok(FilterKind::Mitchell),
+            "lanczos" => Ok(FilterKind::Lanczos),
+            _ => Err(Error::InvalidConfig(format!(
+                "`{}` is not a filter; expected box, tent, gaussian, mitchell or lanczos",
+                s
+            ))),
+        }
+    }
+}
@@ -0,0 +1,34 @@
+//! The floating point type of the geometry, the colors and the samples: `f64` by default, or
+//! `f32` with the `f32` feature, which is faster and takes half the memory (e.g. for large
+//! meshes), at the cost of precision.
+
+use rand::Rng;
+use std::ops::Range;
+
+#[cfg(feature = "f32")]
+pub use std::f32::consts;
+#[cfg(not(feature = "f32"))]
+pub use std::f64::consts;
+
+#[cfg(not(feature = "f32"))]
+pub type Float = f64;
+#[cfg(feature = "f32")]
+pub type Float = f32;
+
+/// The bits of `x`, widened to 64 bits (e.g. for hashing).
+#[cfg(not(feature = "f32"))]
+pub fn to_bits(x: Float) -> u64 {
+    x.to_bits()
+}
+
+/// The bits of `x`, widened to 64 bits (e.g. for hashing).
+#[cfg(feature = "f32")]
+pub fn to_bits(x: Float) -> u64 {
+    x.to_bits() as u64
+}
+
+/// A random number in `range`. It is drawn in double precision, since drawing an `f32` takes
+/// other bits of the generator, so that both precisions place the random spheres alike.
+pub fn random<R: Rng + ?Sized>(rng: &mut R, range: Range<f64>) -> Float {
+    rng.gen_range(range) as Float
+}
@@ -1,27 +1,133 @@
+use crate::float::{self, Float};
 use crate::material::Material;
+use crate::packet::{Lanes, RayPacket, WIDTH};
 use crate::ray::Ray;
-use nalgebra::{Point3, Unit, Vector3};
+use crate::sampler;
+use crate::texture::Texture;
+use crate::util;
+use nalgebra::{Point3, Unit, Vector2, Vector3};
 use std::fmt::Debug;
 
 /// The point of contact between a ray and a material.
-pub struct HitPoint {
+#[derive(Clone, Copy)]
+pub struct HitPoint<'a> {
     /// the distance along the ray where the contact happens; more specifically, it satisfies the
     /// equation `ray.origin + t * ray.direction() = p`.
-    pub t: f64,
+    pub t: Float,
     /// the actual 3D contact point.
-    pub p: Point3<f64>,
+    pub p: Point3<Float>,
+    /// a conservative bound on the absolute error of each coordinate of `p`, which is computed
+    /// with floating point arithmetic.
+    pub p_error: Vector3<Float>,
     /// the normal vector on the material surface in point `p`.
-    pub normal: Unit<Vector3<f64>>,
+    pub normal: Unit<Vector3<Float>>,
+    /// the normal vector of the surface itself, which differs from `normal` where a mesh
+    /// interpolates the normal vectors of its vertices; rays leave the surface along it.
+    pub geometric_normal: Unit<Vector3<Float>>,
+    /// the texture coordinates of `p`, both in `[0, 1]`.
+    pub uv: Vector2<Float>,
+    /// the partial derivatives of `p` with respect to the texture coordinates; together with the
+    /// normal, they give the tangent frame in which normal maps and bump maps are applied.
+    pub dpdu: Vector3<Float>,
+    pub dpdv: Vector3<Float>,
     /// describes material properties; useful for determining what happens to the ray
     /// after contact (is it absorbed/reflected/refracted?).
-    pub material: Material,
+    pub material: &'a Material,
+}
+
+impl HitPoint<'_> {
+    /// A ray leaving the hit point towards `direction`. Its origin is moved off the surface by
+    /// the error bound of `p` (see `util::offset_ray_origin`), so that it can be traced with
+    /// `t_min = 0` without hitting the surface again.
+    pub fn spawn_ray(&self, direction: &Vector3<Float>) -> Ray {
+        let origin =
+            util::offset_ray_origin(&self.p, &self.p_error, &self.geometric_normal, direction);
+        Ray::new(origin, *direction)
+    }
+
+    /// An orthonormal frame around the normal vector: the tangent follows `dpdu` and the
+    /// bitangent completes the frame, pointing roughly along `dpdv`.
+    pub fn tangent_frame(&self) -> (Vector3<Float>, Vector3<Float>, Vector3<Float>) {
+        let normal = self.normal.into_inner();
+        let tangent = self.dpdu - self.dpdu.dot(&normal) * normal;
+        let tangent = match tangent.try_normalize(1e-12) {
+            Some(tangent) => tangent,
+            None => util::orthonormal_basis(&normal).0,
+        };
+        let mut bitangent = normal.cross(&tangent);
+        if bitangent.dot(&self.dpdv) < 0. {
+            bitangent = -bitangent;
+        }
+        (tangent, bitangent, normal)
+    }
+}
+
+/// Whether a ray goes through a cutout (see `Material::Cutout`) at a hit point with texture
+/// coordinates `uv`, at a distance `t` along the ray. Partially transparent surfaces let the ray
+/// through at random, with a probability of `1 - opacity`; the decision is a hash of the ray and
+/// of `t`, so that it stays the same however often the hit point is looked up (e.g. by the
+/// different nodes of an acceleration structure).
+pub fn passes_through(opacity: &Texture, uv: &Vector2<Float>, ray: &Ray, t: Float) -> bool {
+    let opacity = opacity.value(uv);
+    if opacity >= 1. {
+        return false;
+    }
+    if opacity <= 0. {
+        return true;
+    }
+    let (o, d) = (ray.origin(), ray.direction());
+    let u = sampler::hash_float(&[
+        float::to_bits(o.x),
+        float::to_bits(o.y),
+        float::to_bits(o.z),
+        float::to_bits(d.x),
+        float::to_bits(d.y),
+        float::to_bits(d.z),
+        float::to_bits(t),
+    ]);
+    u >= opacity
 }
 
 /// A `Hitable` object is anything that can be hit by a `Ray`, resulting in a `HitPoint`.
 pub trait Hitable {
-    /// Returns the `HitPoint` (if any) of a given `Ray` that hits the object at a `t` in `[t_min,
-    /// t_max]`. If there are multiple such hit points, the closest one (smallest `t`) is used.
-    fn hit(&self, ray: &Ray, t_min: f64, t_max: f64) -> Option<HitPoint>;
+    /// Returns the `HitPoint` (if any) of a given `Ray` that hits the object at a `t` in `(t_min,
+    /// t_max)`. If there are multiple such hit points, the closest one (smallest `t`) is used.
+    /// Hit points are only reported when `t` is larger than `t_min` even with its rounding error,
+    /// so that rays spawned from a surface (see `HitPoint::spawn_ray`) do not hit it again.
+    fn hit(&self, ray: &Ray, t_min: Float, t_max: Float) -> Option<HitPoint<'_>>;
+
+    /// Like `hit`, for each active ray of a packet, with its own `t_max`. The hit points are the
+    /// same as the ones `hit` returns for each ray. Objects with SIMD intersection code override
+    /// this; by default, the rays are traced one by one.
+    fn hit_packet(
+        &self,
+        packet: &RayPacket,
+        t_min: Float,
+        t_max: &Lanes<WIDTH>,
+    ) -> [Option<HitPoint<'_>>; WIDTH] {
+        std::array::from_fn(|lane| {
+            if packet.is_active(lane) {
+                self.hit(&packet.ray(lane), t_min, t_max[lane])
+            } else {
+                None
+            }
+        })
+    }
+}
+
+/// Keeps the closer one of the hit points of each lane; hit points with a `NaN` distance are
+/// ignored.
+fn closest_per_lane<'a>(
+    closest: &mut [Option<HitPoint<'a>>; WIDTH],
+    hitpoints: [Option<HitPoint<'a>>; WIDTH],
+) {
+    for (closest, hitpoint) in closest.iter_mut().zip(hitpoints) {
+        if let Some(hitpoint) = hitpoint.filter(|hitpoint| !hitpoint.t.is_nan()) {
+            if closest.is_none_or(|closest| hitpoint.t < closest.t) {
+                *closest = Some(hitpoint);
+            }
+        }
+    }
 }
 
 impl<T> Hitable for Vec<T>
@@ -29,10 +135,69 @@ where
     T: Hitable + Debug,
 {
     /// Returns the closest hitpoint (smallest `t`) of all `Hitable` objects contained in the
-    /// `Vec`.
-    fn hit(&self, ray: &Ray, t_min: f64, t_max: f64) -> Option<HitPoint> {
+    /// `Vec`. Hitpoints with a `NaN` distance (e.g. from degenerate objects) are ignored.
+    fn hit(&self, ray: &Ray, t_min: Float, t_max: Float) -> Option<HitPoint<'_>> {
         self.iter()
             .filter_map(|obj| obj.hit(ray, t_min, t_max))
-            .min_by(|x, y| x.t.partial_cmp(&y.t).unwrap())
+            .filter(|hitpoint| !hitpoint.t.is_nan())
+            .min_by(|x, y| x.t.total_cmp(&y.t))
+    }
+
+    fn hit_packet(
+        &self,
+        packet: &RayPacket,
+        t_min: Float,
+        t_max: &Lanes<WIDTH>,
+    ) -> [Option<HitPoint<'_>>; WIDTH] {
+        let mut closest = [None; WIDTH];
+        for obj in self {
+            closest_per_lane(&mut closest, obj.hit_packet(packet, t_min, t_max));
+        }
+        closest
+    }
+}
+
+impl<T> Hitable for &T
+where
+    T: Hitable + ?Sized,
+{
+    fn hit(&self, ray: &Ray, t_min: Float, t_max: Float) -> Option<HitPoint<'_>> {
+        (**self).hit(ray, t_min, t_max)
+    }
+
+    fn hit_packet(
+        &self,
+        packet: &RayPacket,
+        t_min: Float,
+        t_max: &Lanes<WIDTH>,
+    ) -> [Option<HitPoint<'_>>; WIDTH] {
+        (**self).hit_packet(packet, t_min, t_max)
+    }
+}
+
+impl<A, B> Hitable for (A, B)
+where
+    A: Hitable,
+    B: Hitable,
+{
+    /// Returns the closest hitpoint of the two `Hitable` objects, e.g. the spheres and the
+    /// meshes of a scene.
+    fn hit(&self, ray: &Ray, t_min: Float, t_max: Float) -> Option<HitPoint<'_>> {
+        let first = self.0.hit(ray, t_min, t_max);
+        let t_max = first.as_ref().map_or(t_max, |hitpoint| hitpoint.t);
+        self.1.hit(ray, t_min, t_max).or(first)
+    }
+
+    fn hit_packet(
+        &self,
+        packet: &RayPacket,
+        t_min: Float,
+        t_max: &Lanes<WIDTH>,
+    ) -> [Option<HitPoint<'_>>; WIDTH] {
+        let first = self.0.hit_packet(packet, t_min, t_max);
+        let t_max =
+            std::array::from_fn(|lane| first[lane].map_or(t_max[lane], |hitpoint| hitpoint.t));
+        let second = self.1.hit_packet(packet, t_min, &t_max);
+        std::array::from_fn(|lane| second[lane].or(first[lane]))
     }
 }
@@ -7,164 +7,171 @@ extern crate pretty_env_logger;
 extern crate rand;
 extern crate regex;
 
+pub mod animation;
+pub mod aperture;
+pub mod bvh;
 pub mod camera;
+pub mod color;
 pub mod config;
+pub mod error;
+pub mod filter;
+pub mod float;
 pub mod hitable;
 pub mod material;
+pub mod medium;
+pub mod mesh;
+pub mod microfacet;
+pub mod packet;
 pub mod ray;
+pub mod renderer;
+pub mod sampler;
+pub mod scene;
+pub mod spectrum;
 pub mod sphere;
+pub mod stats;
+pub mod texture;
 pub mod util;
+pub mod validate;
 
-use camera::{Camera, Lens, Orientation};
 pub use config::{Config, Resolution};
-use image::{GenericImage, GenericImageView, Rgba};
+pub use error::{Error, Result};
+use float::Float;
 use indicatif::{ProgressBar, ProgressFinish, ProgressStyle};
-use material::Material;
-use material::Material::{Lambertian, Metal};
-use nalgebra::{Point3, Vector3};
-use rand::prelude::SliceRandom;
-use rand::{thread_rng, Rng};
-use sphere::Sphere;
-use std::f64;
-use std::thread;
-
-const EARTH_RADIUS: f64 = 6.371e6;
-
-fn random_scene(object_count: u32) -> Vec<Sphere> {
-    let ground = Sphere::new(
-        Point3::new(0., -EARTH_RADIUS, 0.),
-        EARTH_RADIUS,
-        Lambertian {
-            attenuation: Vector3::new(0.5, 0.5, 0.5),
-        },
-    );
-
-    let metal_sphere = Sphere::new(
-        Point3::new(0., 1., -4.),
-        1.,
-        Metal {
-            attenuation: Vector3::new(0.7, 0.6, 0.5),
-            fuzziness: 0.,
-        },
-    );
-    let glass_sphere = Sphere::new(Point3::new(0., 1., -8.), 1., Material::random_dielectric());
-    let matte_sphere = Sphere::new(Point3::new(0., 1., -12.), 1., Material::random_lambertian());
-
-    let mut world = vec![ground.clone(), glass_sphere, matte_sphere, metal_sphere];
-    let mut rng = thread_rng();
-
-    for _ in 0..object_count {
-        loop {
-            let x: f64 = rng.gen_range(-30.0..30.0);
-            let z: f64 = rng.gen_range(-60.0..5.0);
-            let radius: f64 = rng.gen_range(0.1..0.5);
-
-            // Account for the curvature of the earth.
-            let sea_level =
-                (ground.radius().powf(2.) - (x - ground.x()).powf(2.) - (z - ground.z()).powf(2.))
-                    .sqrt()
-                    + ground.y();
-            debug!("sea level: {}", sea_level);
-
-            let y = sea_level + radius;
-            let sphere = Sphere::new(Point3::new(x, y, z), y, Material::random_material());
-
-            if world.iter().any(|existing| existing.intersects(&sphere)) {
-                continue;
-            } else {
-                world.push(sphere);
-                break;
+pub use renderer::{Framebuffer, Region, Renderer};
+pub use scene::{Scene, SceneFile};
+use stats::Stats;
+use std::path::Path;
+use std::time::Instant;
+use validate::{Issue, Object, Severity};
+
+/// Entry point for the application. Loads the scene file (or generates a random world), simulates
+/// the ray tracing and finally saves the rendered frame (or the frames of the animation) to disk,
+/// as specified by the `Config`. The render statistics are printed at the end.
+pub fn run(cfg: Config) -> Result<()> {
+    let mut stats = Stats::default();
+    let start = Instant::now();
+    let aspect_ratio = cfg.resolution.width as Float / cfg.resolution.height as Float;
+    let scene_file = match &cfg.scene {
+        Some(path) => Some(SceneFile::load(path)?),
+        None => {
+            if let Some(name) = &cfg.camera_name {
+                return Err(Error::InvalidConfig(format!(
+                    "camera `{}` was requested, but no scene file was given",
+                    name
+                )));
             }
+            None
         }
-    }
-
-    for sphere in &world {
-        info!("Sphere: {}", sphere.center());
-    }
-
-    world
-}
+    };
 
-/// Entry point for the application. Generates a hardcoded world, simulates the ray tracing and
-/// finally saves the rendered frame to disk, as specified by the `Config`.
-pub fn run(cfg: Config) {
-    let world = random_scene(500);
-    let orientation = Orientation {
-        look_from: Point3::new(-2., 1.7, 0.),
-        look_at: Point3::new(0., 1., -8.),
-        upwards: Vector3::new(0., 1., 0.),
+    // The random world is generated only once, so that it stays the same in every frame.
+    let random_scene = match scene_file {
+        Some(_) => None,
+        None => Some(Scene::random(500, cfg.seed, &cfg.camera, aspect_ratio)?),
     };
-    let lens = Lens {
-        aperture: 0.1,
-        focal_length: util::length(&(orientation.look_from - orientation.look_at)),
-        vertical_fov: cfg.fov,
-        aspect_ratio: cfg.resolution.width as f64 / cfg.resolution.height as f64,
+    stats.scene_build += start.elapsed();
+
+    let scene_at = |frame: u32, stats: &mut Stats| -> Result<Scene> {
+        let start = Instant::now();
+        let scene = match (&scene_file, &random_scene) {
+            (Some(file), _) => file.at_frame(frame as Float).into_scene(
+                cfg.camera_name.as_deref(),
+                &cfg.camera,
+                aspect_ratio,
+            ),
+            (None, Some(scene)) => Ok(scene.clone()),
+            (None, None) => unreachable!(),
+        }?;
+        scene.prepare();
+        stats.scene_build += start.elapsed();
+        Ok(scene)
     };
 
-    let camera = Camera::new(orientation, lens);
-
-    let r = {
-        let (s, r): (chan::Sender<_>, chan::Receiver<_>) = chan::r#async();
-
-        let cfg = cfg.clone();
-        let mut pixels: Vec<(u32, u32, image::Rgba<u8>)> =
-            image::DynamicImage::new_rgb8(cfg.resolution.width, cfg.resolution.height)
-                .pixels()
-                .into_iter()
-                .collect();
-
-        // Some areas of the image take more time to render. This makes the progress bar
-        // advance unevenly. Shuffling the pixels leads to a more even distribution and a more
-        // accurate ETA.
-        pixels.shuffle(&mut thread_rng());
-        pixels.into_iter().for_each(|pixel| s.send(pixel));
-        r
+    // Refers to the objects by their names in the scene file, if there is one.
+    let describe = |object: &Object| match &scene_file {
+        Some(file) => file.describe(object),
+        None => object.to_string(),
+    };
+    let issue_line = |issue: &Issue| {
+        format!(
+            "{}: {}: {}",
+            issue.severity,
+            describe(&issue.object),
+            issue.message
+        )
     };
 
-    let (ret_s, ret_r) = chan::r#async();
-    let wg = chan::WaitGroup::new();
-
-    for _ in 0..cfg.threads {
-        wg.add(1);
-
-        let wg = wg.clone();
-        let r = r.clone();
-        let cfg = cfg.clone();
-        let world = world.clone();
-        let ret_s = ret_s.clone();
-        thread::spawn(move || {
-            for pixel in r {
-                let mut color: Vector3<f64> = (0..cfg.samples)
-                    .into_iter()
-                    .map(|_| {
-                        let mut rng = thread_rng();
-                        let u = (pixel.0 as f64 + rng.gen::<f64>()) / cfg.resolution.width as f64;
-                        let v = (pixel.1 as f64 + rng.gen::<f64>()) / cfg.resolution.height as f64;
-
-                        camera.get_ray(u, v).color(&world, 0)
-                    })
-                    .sum();
+    if cfg.validate {
+        let frames = cfg
+            .frames
+            .map_or_else(|| vec![0], |frames| frames.iter().collect());
+        let (mut errors, mut warnings) = (0, 0);
+        for frame in frames {
+            let scene = scene_at(frame, &mut stats)?;
+            for issue in validate::validate(&scene) {
+                match issue.severity {
+                    Severity::Warning => warnings += 1,
+                    Severity::Error => errors += 1,
+                }
+                match cfg.frames {
+                    Some(_) => println!("frame {}: {}", frame, issue_line(&issue)),
+                    None => println!("{}", issue_line(&issue)),
+                }
+            }
+        }
+        eprintln!("{} errors, {} warnings", errors, warnings);
+        return match errors {
+            0 => Ok(()),
+            _ => Err(Error::Scene(format!("found {} errors", errors))),
+        };
+    }
 
-                color /= cfg.samples as f64;
-                color.x = color.x.sqrt();
-                color.y = color.y.sqrt();
-                color.z = color.z.sqrt();
-                color *= 255.99;
+    // The render goes ahead despite warnings, but not despite errors.
+    let check = |scene: &Scene| -> Result<()> {
+        let issues = validate::validate(scene);
+        for issue in issues.iter().filter(|i| i.severity == Severity::Warning) {
+            warn!("{}", issue_line(issue));
+        }
+        match issues.iter().find(|i| i.severity == Severity::Error) {
+            Some(issue) => Err(Error::Scene(format!(
+                "{}: {} (see `--validate` for all the issues)",
+                describe(&issue.object),
+                issue.message
+            ))),
+            None => Ok(()),
+        }
+    };
 
-                let transformed_pixel = (
-                    pixel.0,
-                    cfg.resolution.height - pixel.1 - 1, // vertical axis is reversed
-                    Rgba([color.x as u8, color.y as u8, color.z as u8, 255]),
-                );
-                ret_s.send(transformed_pixel);
+    match cfg.frames {
+        None => {
+            let scene = scene_at(0, &mut stats)?;
+            check(&scene)?;
+            render_to_file(&cfg, &scene, &cfg.output_filename, &mut stats)?;
+        }
+        Some(frames) => {
+            for frame in frames.iter() {
+                let filename = cfg.output_for_frame(frame);
+                if cfg.skip_existing && Path::new(&filename).exists() {
+                    info!("Skipping frame {}: {} already exists", frame, filename);
+                    continue;
+                }
+
+                info!("Rendering frame {} into {}", frame, filename);
+                let scene = scene_at(frame, &mut stats)?;
+                check(&scene)?;
+                render_to_file(&cfg, &scene, &filename, &mut stats)?;
             }
-            wg.done();
-        });
+        }
     }
 
-    drop(ret_s);
+    eprintln!("{}", stats);
+    match &cfg.stats {
+        Some(path) => stats.save(path),
+        None => Ok(()),
+    }
+}
 
-    let mut img = image::DynamicImage::new_rgb8(cfg.resolution.width, cfg.resolution.height);
+fn render_to_file(cfg: &Config, scene: &Scene, filename: &str, stats: &mut Stats) -> Result<()> {
     let pb = ProgressBar::new((cfg.resolution.width * cfg.resolution.height).into());
     pb.set_style(
         ProgressStyle::default_bar()
@@ -174,10 +181,16 @@ pub fn run(cfg: Config) {
             .progress_chars("##-")
             .on_finish(ProgressFinish::AndLeave),
     );
-    for pixel in ret_r {
-        img.put_pixel(pixel.0, pixel.1, pixel.2);
-        pb.inc(1);
-    }
-    wg.wait();
-    img.save(cfg.output_filename).unwrap();
+
+    let start = Instant::now();
+    let mut renderer = Renderer::from_config(cfg).on_progress(|done, _| pb.set_position(done));
+    let framebuffer = renderer.render(scene)?;
+    stats.render += start.elapsed();
+    stats.counters += renderer.counters();
+    stats.frames += 1;
+
+    let start = Instant::now();
+    framebuffer.save(filename, cfg.output_encoding)?;
+    stats.write += start.elapsed();
+    Ok(())
 }
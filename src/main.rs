@@ -7,12 +7,25 @@ extern crate log;
 extern crate pretty_env_logger;
 
 use clap::Parser;
+use rtow::config::Args;
 use rtow::Config;
+use std::process;
 
 fn main() {
-    let cfg = Config::parse();
+    let args = Args::parse();
     pretty_env_logger::init();
 
+    let cfg = match Config::from_args(args) {
+        Ok(cfg) => cfg,
+        Err(err) => {
+            eprintln!("error: {}", err);
+            process::exit(err.exit_code());
+        }
+    };
+
     info!("Running with the following configuration: {:#?}", &cfg);
-    rtow::run(cfg);
+    if let Err(err) = rtow::run(cfg) {
+        eprintln!("error: {}", err);
+        process::exit(err.exit_code());
+    }
 }
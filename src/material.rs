@@ -1,151 +1,1036 @@
+use crate::color::Encoding;
+use crate::error::Result;
+use crate::float::{self, consts, Float};
 use crate::hitable::HitPoint;
-use crate::ray::Ray;
-use crate::sphere::Sphere;
+use crate::medium::Medium;
+use crate::microfacet::Ggx;
+use crate::sampler::Sampler;
+use crate::spectrum::{self, Conductor, SampledSpectrum, ThinFilm, Wavelengths};
+use crate::texture::{ImageTexture, Texture};
 use crate::util;
-use nalgebra::Vector3;
-use rand::{thread_rng, Rng};
-use std::cmp::Ordering;
+use nalgebra::{Unit, Vector2, Vector3};
+use rand::Rng;
+use serde::Deserialize;
+use std::path::Path;
 
-/// Percentage of each RGB color that persists after a ray gets scattered.
-pub type Attenuation = Vector3<f64>;
+/// Percentage of the light of each RGB channel (or of each wavelength, in spectral mode) that
+/// persists after a ray gets scattered.
+pub type Attenuation = SampledSpectrum;
 
 /// `0.0` for smooth and shiny; `1.0` for fuzzy.
-pub type Fuzziness = f64;
+pub type Fuzziness = Float;
 
 /// [Refractive index](https://en.wikipedia.org/wiki/Refractive_index)
-pub type RefractiveIndex = f64;
+pub type RefractiveIndex = Float;
 
-#[derive(Debug, Copy, Clone)]
+/// In scene files, materials are tables tagged by their `type`, e.g. `{ type = "metal",
+/// attenuation = [0.7, 0.6, 0.5], fuzziness = 0.1 }`. Composite materials contain other
+/// materials, e.g. `{ type = "clear_coat", base = { type = "lambertian", attenuation = [0.5,
+/// 0.2, 0.1] } }`.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case", deny_unknown_fields)]
 pub enum Material {
     /// Matte.
-    Lambertian { attenuation: Vector3<f64> },
-    /// Metal.
+    Lambertian { attenuation: Vector3<Float> },
+    /// Metal. When a `conductor` is given, the reflected light follows its measured refractive
+    /// index (e.g. `conductor = "gold"`) and is further tinted by the `attenuation`.
     Metal {
-        attenuation: Vector3<f64>,
-        fuzziness: f64,
+        #[serde(default = "white")]
+        attenuation: Vector3<Float>,
+        fuzziness: Float,
+        #[serde(default)]
+        conductor: Option<Conductor>,
+    },
+    /// A rough metal, whose surface is made of tiny mirrors (microfacets) tilted according to
+    /// the GGX distribution. `roughness` goes from `0` (a perfect mirror) to `1`; `anisotropy`
+    /// (between `-1` and `1`) brushes the metal along the tangent (the direction in which the
+    /// texture coordinate `u` grows) when positive, and along the bitangent when negative,
+    /// stretching the highlights across the brush strokes. `conductor` and `attenuation` work
+    /// like for `Metal`. A `thin_film`, e.g. `{ thickness = 300, refractive_index = 1.4 }`, adds
+    /// the iridescence of an oxide layer or an oil slick; without a `conductor`, the film lies on
+    /// a surface which reflects the `attenuation` when seen head-on.
+    Microfacet {
+        #[serde(default = "white")]
+        attenuation: Vector3<Float>,
+        roughness: Float,
+        #[serde(default)]
+        anisotropy: Float,
+        #[serde(default)]
+        conductor: Option<Conductor>,
+        #[serde(default)]
+        thin_film: Option<ThinFilm>,
+    },
+    /// i.e. glass. Glass with an `abbe_number` disperses light into its colors in spectral mode.
+    /// A `thin_film` makes soap bubbles (with a `refractive_index` of `1`, i.e. air inside) or
+    /// coated lenses.
+    Dielectric {
+        refractive_index: Float,
+        #[serde(default)]
+        abbe_number: Option<Float>,
+        #[serde(default)]
+        thin_film: Option<ThinFilm>,
+    },
+    /// A translucent material, e.g. skin, wax, marble or milk. Its surface reflects and refracts
+    /// the light like a `Dielectric`; inside, the light scatters around (a random walk) until it
+    /// leaves the object again or gets absorbed. `mean_free_path` is the average distance
+    /// between two scattering events, per color channel (in scene units); `albedo` is the share
+    /// of the light that survives each event; `anisotropy` (between `-1` and `1`) makes the
+    /// light scatter backwards or forwards. Only closed objects (spheres or closed meshes) can be
+    /// made of it.
+    Subsurface {
+        #[serde(default = "varnish")]
+        refractive_index: Float,
+        mean_free_path: Vector3<Float>,
+        albedo: Vector3<Float>,
+        #[serde(default)]
+        anisotropy: Float,
+    },
+    /// Blends two materials, e.g. paint and bare metal. The `weight` (a number or a `Texture`)
+    /// is the share of the `second` material.
+    Mix {
+        first: Box<Material>,
+        second: Box<Material>,
+        weight: Texture,
+    },
+    /// A smooth layer of varnish over a `base` material, e.g. car paint or varnished wood. The
+    /// varnish reflects some of the light, more so at grazing angles, and lets the rest through
+    /// to the base.
+    ClearCoat {
+        base: Box<Material>,
+        #[serde(default = "varnish")]
+        refractive_index: Float,
+    },
+    /// Different materials on each side of a surface: the `front` faces the way of the normal
+    /// vector (e.g. the outside of a sphere) and the `back` the opposite way.
+    TwoSided {
+        front: Box<Material>,
+        back: Box<Material>,
+    },
+    /// Adds relief to a `base` material by tilting its normal vector along the slopes of a
+    /// `height` texture, whose values are multiplied by `scale` (in scene units).
+    Bump {
+        base: Box<Material>,
+        height: Texture,
+        scale: Float,
+    },
+    /// Replaces the normal vector of a `base` material with the one stored in the image at
+    /// `path`, in tangent space: red along the tangent (`u`), green along the bitangent (`v`)
+    /// and blue along the normal, each mapped from `[-1, 1]` to `[0, 1]`.
+    NormalMap {
+        base: Box<Material>,
+        #[serde(rename = "path")]
+        map: ImageTexture,
+    },
+    /// Cuts holes into a surface, e.g. for leaves, fences or decals: where the `opacity` texture
+    /// is `0`, rays go through as if nothing was there; where it is `1`, they hit the `base`
+    /// material. Values in between let through that share of the rays, chosen at random. Only
+    /// the outermost material of a surface can be a cutout.
+    Cutout {
+        base: Box<Material>,
+        opacity: Texture,
+    },
+    /// Tints the light scattered by a `base` material with the colors of the image at `path`,
+    /// e.g. a label or the grain of wood, usually over a white base. The image is decoded from
+    /// its `encoding` (`"srgb"` unless given) into the working color space. Images which hold
+    /// data rather than colors (normal maps, heights, opacities) are never decoded.
+    Textured {
+        base: Box<Material>,
+        #[serde(rename = "path")]
+        image: ImageTexture,
+        #[serde(default)]
+        encoding: Encoding,
     },
-    /// i.e. glass.
-    Dielectric { refractive_index: f64 },
+}
+
+fn white() -> Vector3<Float> {
+    Vector3::new(1., 1., 1.)
+}
+
+fn varnish() -> Float {
+    1.5
 }
 
 impl Material {
-    pub fn random_lambertian() -> Material {
-        let mut rng = thread_rng();
+    pub fn random_lambertian<R: Rng>(rng: &mut R) -> Material {
         Material::Lambertian {
             attenuation: Vector3::new(
-                rng.gen_range(0.0..1.0),
-                rng.gen_range(0.0..1.0),
-                rng.gen_range(0.0..1.0),
+                float::random(rng, 0.0..1.0),
+                float::random(rng, 0.0..1.0),
+                float::random(rng, 0.0..1.0),
             ),
         }
     }
 
-    pub fn random_metal() -> Material {
-        let mut rng = thread_rng();
+    pub fn random_metal<R: Rng>(rng: &mut R) -> Material {
         Material::Metal {
             attenuation: Vector3::new(
-                rng.gen_range(0.0..1.0),
-                rng.gen_range(0.0..1.0),
-                rng.gen_range(0.0..1.0),
+                float::random(rng, 0.0..1.0),
+                float::random(rng, 0.0..1.0),
+                float::random(rng, 0.0..1.0),
             ),
-            fuzziness: rng.gen_range(0.0..1.0),
+            fuzziness: float::random(rng, 0.0..1.0),
+            conductor: None,
         }
     }
 
-    pub fn random_dielectric() -> Material {
-        let mut rng = thread_rng();
+    pub fn random_dielectric<R: Rng>(rng: &mut R) -> Material {
         Material::Dielectric {
-            refractive_index: rng.gen_range(1.3..3.2),
+            refractive_index: float::random(rng, 1.3..3.2),
+            abbe_number: None,
+            thin_film: None,
         }
     }
 
-    /// Box the material-generating closures in order to make them lazy.
-    /// This way only one material is generated.
-    pub fn random_material() -> Material {
-        let mut rng = thread_rng();
-        let fns = vec![
-            Box::new(Material::random_metal) as Box<dyn Fn() -> Material>,
-            Box::new(Material::random_lambertian) as Box<dyn Fn() -> Material>,
-            Box::new(Material::random_dielectric) as Box<dyn Fn() -> Material>,
+    /// Picks one of the material generators at random, so that only one material is generated.
+    pub fn random_material<R: Rng>(rng: &mut R) -> Material {
+        let fns: [fn(&mut R) -> Material; 3] = [
+            Material::random_metal,
+            Material::random_lambertian,
+            Material::random_dielectric,
         ];
-        fns[rng.gen::<usize>() % 3]()
+        fns[rng.gen::<usize>() % 3](rng)
+    }
+}
+
+impl Material {
+    /// The opacity texture of a `Cutout`, which `Hitable` objects check before reporting a hit
+    /// point (see `hitable::passes_through`).
+    pub fn opacity(&self) -> Option<&Texture> {
+        match self {
+            Material::Cutout { opacity, .. } => Some(opacity),
+            _ => None,
+        }
+    }
+
+    /// The medium inside the objects made of a `Subsurface` material (possibly wrapped by a
+    /// `Bump`, `NormalMap`, `Cutout` or `Textured`), which the rays that enter them travel through.
+    pub fn interior(&self) -> Option<Medium> {
+        match self {
+            Material::Subsurface {
+                mean_free_path,
+                albedo,
+                anisotropy,
+                ..
+            } => Some(Medium {
+                mean_free_path: *mean_free_path,
+                albedo: *albedo,
+                anisotropy: *anisotropy,
+            }),
+            Material::Bump { base, .. }
+            | Material::NormalMap { base, .. }
+            | Material::Cutout { base, .. }
+            | Material::Textured { base, .. } => base.interior(),
+            _ => None,
+        }
+    }
+
+    /// Loads the images of the material and of the materials it contains, with their paths
+    /// relative to `directory`.
+    pub(crate) fn load_images(&mut self, directory: &Path) -> Result<()> {
+        match self {
+            Material::Mix {
+                first,
+                second,
+                weight,
+            } => {
+                first.load_images(directory)?;
+                second.load_images(directory)?;
+                weight.load_images(directory)
+            }
+            Material::TwoSided { front, back } => {
+                front.load_images(directory)?;
+                back.load_images(directory)
+            }
+            Material::ClearCoat { base, .. } => base.load_images(directory),
+            Material::Bump { base, height, .. } => {
+                base.load_images(directory)?;
+                height.load_images(directory)
+            }
+            Material::NormalMap { base, map } => {
+                base.load_images(directory)?;
+                map.load_in(directory)
+            }
+            Material::Cutout { base, opacity } => {
+                base.load_images(directory)?;
+                opacity.load_images(directory)
+            }
+            Material::Textured { base, image, .. } => {
+                base.load_images(directory)?;
+                image.load_in(directory)
+            }
+            _ => Ok(()),
+        }
     }
 }
 
-/// A `Scatterable` scatters the light rays that hit it.
-pub trait Scatterable {
-    /// Returns the new `Ray` (if any) and its `Attenuation` which results from a given `Ray` hitting a `Scatterable` object.
-    /// The new ray usually describes a physical phenomenon (reflection, refraction or absorption).
-    fn scatter(&self, ray: &Ray, hitpoint: &HitPoint) -> Option<(Ray, Attenuation)>;
+/// A direction chosen by `Bsdf::sample`, along with the value of the BSDF and the probability
+/// density of choosing that direction.
+#[derive(Debug, Clone, Copy)]
+pub struct BsdfSample {
+    /// The unit direction of the scattered ray.
+    pub direction: Vector3<Float>,
+    /// The value of the BSDF for the sampled direction (without the cosine term).
+    pub value: SampledSpectrum,
+    /// The probability density of the direction, with respect to the solid angle. For delta
+    /// lobes, it is the probability of choosing the lobe instead.
+    pub pdf: Float,
+    /// Whether the direction comes from a delta lobe (a perfect mirror or a smooth refraction),
+    /// which `eval` and `pdf` cannot reproduce.
+    pub delta: bool,
+}
+
+impl BsdfSample {
+    /// The factor by which the light arriving along the sampled direction is scaled when it
+    /// leaves the surface, i.e. `value * |cos| / pdf`.
+    pub fn weight(&self, normal: &Vector3<Float>) -> SampledSpectrum {
+        if self.pdf > 0. {
+            self.value * (self.direction.dot(normal).abs() / self.pdf)
+        } else {
+            SampledSpectrum::zeros()
+        }
+    }
 }
 
-impl Scatterable for Material {
-    /// Different materials scatter in different ways:
-    ///
-    /// * A `Lambertian` (matte) object reflects the ray along the direction of the normal vector,
-    /// which is slightly altered by adding a random delta.
-    /// * A `Metal` reflects the ray along a direction which is [symmetrical to the normal
-    /// vector](https://upload.wikimedia.org/wikipedia/commons/1/10/Reflection_angles.svg).
-    /// Depending on the fuzziness of the metal, a random delta may be added to this direction.
-    /// * A `Dielectric` (i.e. glass) material can either reflect or refract the ray. The
-    /// probability of each event depends on multiple factors, such as the refractive index and the
-    /// angle of incidence. This probability is roughly approximated by the `schlick` polynomial.
-    fn scatter(&self, ray: &Ray, hitpoint: &HitPoint) -> Option<(Ray, Attenuation)> {
+/// The bidirectional scattering distribution function of a surface: how much of the light
+/// arriving from one direction leaves towards another.
+///
+/// Directions are unit vectors pointing away from the surface: `wo` towards the viewer (i.e.
+/// opposite to the incoming ray) and `wi` towards the light.
+pub trait Bsdf {
+    /// Chooses the direction of the scattered ray, with values from the `sampler`. `None` means
+    /// that the light is absorbed. Wavelength dependent events may terminate some of the
+    /// `wavelengths` carried by the ray.
+    fn sample(
+        &self,
+        wo: &Vector3<Float>,
+        hitpoint: &HitPoint,
+        wavelengths: &mut Wavelengths,
+        sampler: &mut dyn Sampler,
+    ) -> Option<BsdfSample>;
+
+    /// The value of the BSDF for a pair of directions. Delta lobes are not included.
+    fn eval(
+        &self,
+        wo: &Vector3<Float>,
+        wi: &Vector3<Float>,
+        hitpoint: &HitPoint,
+        wavelengths: &Wavelengths,
+    ) -> SampledSpectrum;
+
+    /// The probability density with which `sample` chooses `wi`. Delta lobes are not included.
+    fn pdf(&self, wo: &Vector3<Float>, wi: &Vector3<Float>, hitpoint: &HitPoint) -> Float;
+
+    /// Whether the BSDF only consists of delta lobes, in which case `eval` and `pdf` are always
+    /// zero and light sampling is useless.
+    fn is_delta(&self) -> bool;
+}
+
+/// Different materials scatter in different ways:
+///
+/// * A `Lambertian` (matte) object scatters the light evenly in all directions; the directions
+///   are sampled proportionally to their cosine with the normal vector.
+/// * A `Metal` reflects the ray along a direction which is [symmetrical to the normal
+///   vector](https://upload.wikimedia.org/wikipedia/commons/1/10/Reflection_angles.svg).
+///   Depending on the fuzziness of the metal, the direction is moved to a random point of a
+///   ball around it; smooth metals are perfect mirrors (a delta lobe). Conductors reflect each
+///   wavelength according to the Fresnel equations.
+/// * A `Microfacet` metal reflects the ray off a microfacet whose normal is sampled among those
+///   visible from `wo`. Each sample is then weighted by the reflectance and the share of the
+///   reflected light which is not blocked by other microfacets, so that, like the fuzzy `Metal`,
+///   it never reflects more than a mirror would. A thin film replaces the Fresnel equations with
+///   the interference of the light bouncing inside the film, wavelength by wavelength.
+/// * A `Dielectric` (i.e. glass) material can either reflect or refract the ray, both of which
+///   are delta lobes. The probability of each event depends on multiple factors, such as the
+///   refractive index and the angle of incidence. This probability is roughly approximated by
+///   the `schlick` polynomial. Dispersive glass refracts each wavelength differently, so only
+///   the hero wavelength is followed. With a thin film, the probability of a reflection is the
+///   average reflectance of the film over the wavelengths, and the value of each lobe makes up
+///   for the differences, so that each wavelength is still split without loss.
+/// * The surface of a `Subsurface` material is a smooth dielectric; the random walk inside is
+///   simulated by `Ray::color`.
+/// * A `Mix` picks one of its materials at random, in proportion to the weight, and combines
+///   the values and densities of both.
+/// * A `ClearCoat` either reflects the ray off the varnish (a delta lobe, with the probability
+///   given by the `schlick` polynomial) or lets its base scatter it; the light going through
+///   the varnish is dimmed by the reflections on the way in and on the way out.
+/// * A `TwoSided` material behaves like its front or its back material, depending on the side
+///   from which the ray comes. The back material sees the normal vector flipped.
+/// * A `Cutout` behaves like its base material: the rays which go through its holes never hit
+///   it in the first place.
+/// * A `Textured` material scatters like its base material, with the values multiplied by the
+///   color of the image at the hit point.
+/// * `Bump` and `NormalMap` materials let their base material scatter the ray around the
+///   perturbed (shading) normal vector. The values are corrected so that the cosine which the
+///   integrator takes with the original normal is replaced by the one with the shading normal.
+impl Bsdf for Material {
+    fn sample(
+        &self,
+        wo: &Vector3<Float>,
+        hitpoint: &HitPoint,
+        wavelengths: &mut Wavelengths,
+        sampler: &mut dyn Sampler,
+    ) -> Option<BsdfSample> {
         match *self {
-            Material::Lambertian { attenuation } => {
-                let direction =
-                    hitpoint.normal.into_inner() + Sphere::random_point_in_unit_sphere();
-                let scattered_ray = Ray::new(hitpoint.p, direction);
-                Some((scattered_ray, attenuation))
+            Material::Lambertian { .. } => {
+                let normal = facing(&hitpoint.normal, wo);
+                let (tangent, bitangent) = util::orthonormal_basis(&normal);
+                let local = util::cosine_hemisphere(sampler.get_2d());
+                let direction = local.x * tangent + local.y * bitangent + local.z * normal;
+                sampled(self, wo, direction, hitpoint, wavelengths)
             }
             Material::Metal {
                 attenuation,
                 fuzziness,
+                conductor,
+            } => {
+                let reflected = util::reflection(&-wo, &hitpoint.normal);
+                if fuzziness > 0. {
+                    let direction = (reflected
+                        + fuzziness * util::uniform_ball(sampler.get_2d(), sampler.get_1d()))
+                    .normalize();
+                    return sampled(self, wo, direction, hitpoint, wavelengths);
+                }
+
+                let cosine = reflected.dot(&hitpoint.normal);
+                if cosine <= 0. {
+                    return None;
+                }
+                let reflectance = metal_reflectance(
+                    attenuation,
+                    conductor,
+                    None,
+                    wo.dot(&hitpoint.normal),
+                    wavelengths,
+                );
+                Some(BsdfSample {
+                    direction: reflected,
+                    value: reflectance / cosine,
+                    pdf: 1.,
+                    delta: true,
+                })
+            }
+            Material::Microfacet {
+                attenuation,
+                roughness,
+                conductor,
+                thin_film,
+                ..
+            } if roughness <= 0. => {
+                let normal = facing(&hitpoint.normal, wo);
+                let direction = util::reflection(&-wo, &normal);
+                let cosine = direction.dot(&normal);
+                if cosine <= 0. {
+                    return None;
+                }
+                let reflectance = metal_reflectance(
+                    attenuation,
+                    conductor,
+                    thin_film,
+                    wo.dot(&normal),
+                    wavelengths,
+                );
+                Some(BsdfSample {
+                    direction,
+                    value: reflectance / cosine,
+                    pdf: 1.,
+                    delta: true,
+                })
+            }
+            Material::Microfacet {
+                roughness,
+                anisotropy,
+                ..
             } => {
-                let reflection_direction =
-                    util::reflection(&ray.direction().normalize(), &hitpoint.normal)
-                        + fuzziness * Sphere::random_point_in_unit_sphere();
-                let scattered_ray = Ray::new(hitpoint.p, reflection_direction);
-
-                match scattered_ray
-                    .direction()
-                    .dot(&hitpoint.normal)
-                    .partial_cmp(&0.)
-                {
-                    Some(Ordering::Greater) => Some((scattered_ray, attenuation)),
-                    _ => None,
+                let frame = LocalFrame::new(wo, hitpoint);
+                let wo_local = frame.to_local(wo);
+                let h = Ggx::new(roughness, anisotropy)
+                    .sample_visible_normal(&wo_local, sampler.get_2d());
+                let wi_local = 2. * wo_local.dot(&h) * h - wo_local;
+                if wi_local.z <= 0. {
+                    return None;
                 }
+                sampled(self, wo, frame.to_world(&wi_local), hitpoint, wavelengths)
             }
-            Material::Dielectric { refractive_index } => {
-                let reflected_vector = util::reflection(ray.direction(), &hitpoint.normal);
-                let air_refractive_index = 1.;
-                let attenuation = Vector3::new(1., 1., 1.); // glass absorbs nothing
-
-                let mut outward_normal = hitpoint.normal;
-                let mut refractive_index_ratio = air_refractive_index / refractive_index;
-                let mut cosine =
-                    -ray.direction().dot(&hitpoint.normal) / util::length(ray.direction());
-
-                if ray.direction().dot(&hitpoint.normal) > 0. {
-                    outward_normal = -hitpoint.normal;
-                    refractive_index_ratio = refractive_index / air_refractive_index;
-                    cosine = refractive_index * ray.direction().dot(&hitpoint.normal)
-                        / util::length(ray.direction());
+            Material::Dielectric {
+                refractive_index,
+                abbe_number,
+                thin_film,
+            } => {
+                let refractive_index = match (abbe_number, wavelengths.hero()) {
+                    (Some(abbe_number), Some(lambda)) => {
+                        wavelengths.terminate_secondary();
+                        spectrum::cauchy(refractive_index, abbe_number, lambda)
+                    }
+                    _ => refractive_index,
+                };
+                dielectric(
+                    refractive_index,
+                    thin_film,
+                    wo,
+                    hitpoint,
+                    wavelengths,
+                    sampler,
+                )
+            }
+            Material::Subsurface {
+                refractive_index, ..
+            } => dielectric(refractive_index, None, wo, hitpoint, wavelengths, sampler),
+            Material::Mix {
+                ref first,
+                ref second,
+                ref weight,
+            } => {
+                let weight = weight.value(&hitpoint.uv).clamp(0., 1.);
+                let (chosen, share) = if sampler.get_1d() < weight {
+                    (second, weight)
+                } else {
+                    (first, 1. - weight)
+                };
+                let mut sample = chosen.sample(wo, hitpoint, wavelengths, sampler)?;
+                if sample.delta {
+                    sample.value *= share;
+                    sample.pdf *= share;
+                } else {
+                    sample.value = self.eval(wo, &sample.direction, hitpoint, wavelengths);
+                    sample.pdf = self.pdf(wo, &sample.direction, hitpoint);
+                }
+                Some(sample)
+            }
+            Material::ClearCoat {
+                ref base,
+                refractive_index,
+            } => {
+                let coat = util::schlick(wo.dot(&hitpoint.normal).abs(), refractive_index);
+                if sampler.get_1d() < coat {
+                    let direction = util::reflection(&-wo, &hitpoint.normal);
+                    let cosine = direction.dot(&hitpoint.normal).abs();
+                    if cosine == 0. {
+                        return None;
+                    }
+                    return Some(BsdfSample {
+                        direction,
+                        value: SampledSpectrum::constant(coat / cosine),
+                        pdf: coat,
+                        delta: true,
+                    });
                 }
 
-                let mut final_vector =
-                    util::refraction(ray.direction(), &outward_normal, refractive_index_ratio)
-                        .unwrap_or(reflected_vector);
+                let mut sample = base.sample(wo, hitpoint, wavelengths, sampler)?;
+                if sample.delta {
+                    let transmitted = (1. - coat)
+                        * (1.
+                            - util::schlick(
+                                sample.direction.dot(&hitpoint.normal).abs(),
+                                refractive_index,
+                            ));
+                    sample.value *= transmitted;
+                    sample.pdf *= 1. - coat;
+                } else {
+                    sample.value = self.eval(wo, &sample.direction, hitpoint, wavelengths);
+                    sample.pdf = self.pdf(wo, &sample.direction, hitpoint);
+                }
+                Some(sample)
+            }
+            Material::TwoSided {
+                ref front,
+                ref back,
+            } => {
+                let (material, hitpoint) = side(front, back, wo, hitpoint);
+                material.sample(wo, &hitpoint, wavelengths, sampler)
+            }
+            Material::Cutout { ref base, .. } => {
+                let hitpoint = HitPoint {
+                    material: base,
+                    ..*hitpoint
+                };
+                base.sample(wo, &hitpoint, wavelengths, sampler)
+            }
+            Material::Textured {
+                ref base,
+                ref image,
+                encoding,
+            } => {
+                let hitpoint = HitPoint {
+                    material: base,
+                    ..*hitpoint
+                };
+                let mut sample = base.sample(wo, &hitpoint, wavelengths, sampler)?;
+                sample.value *= tint(image, encoding, &hitpoint, wavelengths);
+                Some(sample)
+            }
+            Material::Bump { ref base, .. } | Material::NormalMap { ref base, .. } => {
+                let shading = self.shading_point(base, hitpoint);
+                let mut sample = base.sample(wo, &shading, wavelengths, sampler)?;
+                sample.value *= shading_correction(&sample.direction, &shading, hitpoint);
+                Some(sample)
+            }
+        }
+    }
 
-                let reflection_prob = util::schlick(cosine, refractive_index);
-                let mut rng = thread_rng();
+    fn eval(
+        &self,
+        wo: &Vector3<Float>,
+        wi: &Vector3<Float>,
+        hitpoint: &HitPoint,
+        wavelengths: &Wavelengths,
+    ) -> SampledSpectrum {
+        match *self {
+            Material::Lambertian { attenuation } => {
+                if wi.dot(&facing(&hitpoint.normal, wo)) > 0. {
+                    wavelengths.upsample(&attenuation) * consts::FRAC_1_PI
+                } else {
+                    SampledSpectrum::zeros()
+                }
+            }
+            Material::Metal {
+                attenuation,
+                fuzziness,
+                conductor,
+            } if fuzziness > 0. => {
+                // Chosen so that each sample is weighted by the reflectance, like a mirror
+                // whose reflections are blurred.
+                let cosine = wi.dot(&hitpoint.normal);
+                if cosine <= 0. {
+                    return SampledSpectrum::zeros();
+                }
+                let reflectance = metal_reflectance(
+                    attenuation,
+                    conductor,
+                    None,
+                    wo.dot(&hitpoint.normal),
+                    wavelengths,
+                );
+                reflectance * (self.pdf(wo, wi, hitpoint) / cosine)
+            }
+            Material::Microfacet {
+                attenuation,
+                roughness,
+                anisotropy,
+                conductor,
+                thin_film,
+            } if roughness > 0. => {
+                let frame = LocalFrame::new(wo, hitpoint);
+                let (wo, wi) = (frame.to_local(wo), frame.to_local(wi));
+                if wo.z <= 0. || wi.z <= 0. {
+                    return SampledSpectrum::zeros();
+                }
+                let h = (wo + wi).normalize();
+                let ggx = Ggx::new(roughness, anisotropy);
+                let reflectance =
+                    metal_reflectance(attenuation, conductor, thin_film, wo.dot(&h), wavelengths);
+                reflectance * (ggx.d(&h) * ggx.g(&wo, &wi) / (4. * wo.z * wi.z))
+            }
+            Material::Metal { .. }
+            | Material::Microfacet { .. }
+            | Material::Dielectric { .. }
+            | Material::Subsurface { .. } => SampledSpectrum::zeros(),
+            Material::Mix {
+                ref first,
+                ref second,
+                ref weight,
+            } => {
+                let weight = weight.value(&hitpoint.uv).clamp(0., 1.);
+                first.eval(wo, wi, hitpoint, wavelengths) * (1. - weight)
+                    + second.eval(wo, wi, hitpoint, wavelengths) * weight
+            }
+            Material::ClearCoat {
+                ref base,
+                refractive_index,
+            } => {
+                let transmitted = (1.
+                    - util::schlick(wo.dot(&hitpoint.normal).abs(), refractive_index))
+                    * (1. - util::schlick(wi.dot(&hitpoint.normal).abs(), refractive_index));
+                base.eval(wo, wi, hitpoint, wavelengths) * transmitted
+            }
+            Material::TwoSided {
+                ref front,
+                ref back,
+            } => {
+                let (material, hitpoint) = side(front, back, wo, hitpoint);
+                material.eval(wo, wi, &hitpoint, wavelengths)
+            }
+            Material::Cutout { ref base, .. } => base.eval(wo, wi, hitpoint, wavelengths),
+            Material::Textured {
+                ref base,
+                ref image,
+                encoding,
+            } => {
+                base.eval(wo, wi, hitpoint, wavelengths)
+                    * tint(image, encoding, hitpoint, wavelengths)
+            }
+            Material::Bump { ref base, .. } | Material::NormalMap { ref base, .. } => {
+                let shading = self.shading_point(base, hitpoint);
+                base.eval(wo, wi, &shading, wavelengths)
+                    * shading_correction(wi, &shading, hitpoint)
+            }
+        }
+    }
 
-                if (rng.gen_range(0.0..1.0) as f64) < reflection_prob {
-                    final_vector = reflected_vector;
+    fn pdf(&self, wo: &Vector3<Float>, wi: &Vector3<Float>, hitpoint: &HitPoint) -> Float {
+        match *self {
+            Material::Lambertian { .. } => {
+                wi.dot(&facing(&hitpoint.normal, wo)).max(0.) * consts::FRAC_1_PI
+            }
+            Material::Metal { fuzziness, .. } if fuzziness > 0. => {
+                if wi.dot(&hitpoint.normal) <= 0. {
+                    return 0.;
                 }
+                let reflected = util::reflection(&-wo, &hitpoint.normal);
+                fuzzy_reflection_pdf(&reflected, fuzziness, wi)
+            }
+            Material::Microfacet {
+                roughness,
+                anisotropy,
+                ..
+            } if roughness > 0. => {
+                let frame = LocalFrame::new(wo, hitpoint);
+                let (wo, wi) = (frame.to_local(wo), frame.to_local(wi));
+                if wo.z <= 0. || wi.z <= 0. {
+                    return 0.;
+                }
+                let h = (wo + wi).normalize();
+                // The reflection about `h` squeezes the solid angle by `4 |wo·h|`.
+                Ggx::new(roughness, anisotropy).visible_normal_pdf(&wo, &h) / (4. * wo.dot(&h))
+            }
+            Material::Metal { .. }
+            | Material::Microfacet { .. }
+            | Material::Dielectric { .. }
+            | Material::Subsurface { .. } => 0.,
+            Material::Mix {
+                ref first,
+                ref second,
+                ref weight,
+            } => {
+                let weight = weight.value(&hitpoint.uv).clamp(0., 1.);
+                (1. - weight) * first.pdf(wo, wi, hitpoint) + weight * second.pdf(wo, wi, hitpoint)
+            }
+            Material::ClearCoat {
+                ref base,
+                refractive_index,
+            } => {
+                let coat = util::schlick(wo.dot(&hitpoint.normal).abs(), refractive_index);
+                (1. - coat) * base.pdf(wo, wi, hitpoint)
+            }
+            Material::TwoSided {
+                ref front,
+                ref back,
+            } => {
+                let (material, hitpoint) = side(front, back, wo, hitpoint);
+                material.pdf(wo, wi, &hitpoint)
+            }
+            Material::Cutout { ref base, .. } | Material::Textured { ref base, .. } => {
+                base.pdf(wo, wi, hitpoint)
+            }
+            Material::Bump { ref base, .. } | Material::NormalMap { ref base, .. } => {
+                base.pdf(wo, wi, &self.shading_point(base, hitpoint))
+            }
+        }
+    }
+
+    fn is_delta(&self) -> bool {
+        match *self {
+            Material::Lambertian { .. } => false,
+            Material::Metal { fuzziness, .. } => fuzziness <= 0.,
+            Material::Microfacet { roughness, .. } => roughness <= 0.,
+            Material::Dielectric { .. } | Material::Subsurface { .. } => true,
+            Material::Mix {
+                ref first,
+                ref second,
+                ..
+            } => first.is_delta() && second.is_delta(),
+            Material::ClearCoat { ref base, .. } => base.is_delta(),
+            Material::TwoSided {
+                ref front,
+                ref back,
+            } => front.is_delta() && back.is_delta(),
+            Material::Bump { ref base, .. }
+            | Material::NormalMap { ref base, .. }
+            | Material::Cutout { ref base, .. }
+            | Material::Textured { ref base, .. } => base.is_delta(),
+        }
+    }
+}
 
-                Some((Ray::new(hitpoint.p, final_vector), attenuation))
+/// The color of a `Textured` material at the hit point, as carried by the `wavelengths`.
+fn tint(
+    image: &ImageTexture,
+    encoding: Encoding,
+    hitpoint: &HitPoint,
+    wavelengths: &Wavelengths,
+) -> SampledSpectrum {
+    wavelengths.upsample(&image.color(&hitpoint.uv, wavelengths.space(), encoding))
+}
+
+/// The side of a two-sided material which `wo` looks at, along with the hit point as that side
+/// sees it.
+fn side<'a>(
+    front: &'a Material,
+    back: &'a Material,
+    wo: &Vector3<Float>,
+    hitpoint: &HitPoint<'a>,
+) -> (&'a Material, HitPoint<'a>) {
+    if wo.dot(&hitpoint.normal) >= 0. {
+        (
+            front,
+            HitPoint {
+                material: front,
+                ..*hitpoint
+            },
+        )
+    } else {
+        (
+            back,
+            HitPoint {
+                normal: -hitpoint.normal,
+                material: back,
+                ..*hitpoint
+            },
+        )
+    }
+}
+
+/// The step (in texture coordinates) of the finite differences which give the slopes of bump
+/// maps.
+const BUMP_DELTA: Float = 1. / 1024.;
+
+impl Material {
+    /// The hit point as the `base` of a `Bump` or `NormalMap` material sees it: with the normal
+    /// vector and the tangent frame perturbed. Other materials see the hit point unchanged.
+    fn shading_point<'a>(&self, base: &'a Material, hitpoint: &HitPoint<'a>) -> HitPoint<'a> {
+        let normal = hitpoint.normal.into_inner();
+        let (normal, dpdu, dpdv) = match self {
+            Material::Bump { height, scale, .. } => {
+                let h = height.value(&hitpoint.uv);
+                let h_u = height.value(&(hitpoint.uv + Vector2::new(BUMP_DELTA, 0.)));
+                let h_v = height.value(&(hitpoint.uv + Vector2::new(0., BUMP_DELTA)));
+                let dpdu = hitpoint.dpdu + scale * (h_u - h) / BUMP_DELTA * normal;
+                let dpdv = hitpoint.dpdv + scale * (h_v - h) / BUMP_DELTA * normal;
+                (dpdu.cross(&dpdv), dpdu, dpdv)
+            }
+            Material::NormalMap { map, .. } => {
+                let (tangent, bitangent, normal) = hitpoint.tangent_frame();
+                let m = 2. * map.texel(&hitpoint.uv) - Vector3::new(1., 1., 1.);
+                let shading = m.x * tangent + m.y * bitangent + m.z * normal;
+                (shading, hitpoint.dpdu, hitpoint.dpdv)
             }
+            _ => (normal, hitpoint.dpdu, hitpoint.dpdv),
+        };
+
+        // Degenerate frames (e.g. at the poles of a sphere) keep the original normal.
+        let shading = match normal.try_normalize(1e-12) {
+            Some(shading) if shading.dot(&hitpoint.normal) < 0. => -shading,
+            Some(shading) => shading,
+            None => hitpoint.normal.into_inner(),
+        };
+        HitPoint {
+            normal: Unit::new_unchecked(shading),
+            dpdu,
+            dpdv,
+            material: base,
+            ..*hitpoint
         }
     }
 }
+
+/// The ratio of the cosines of `wi` with the shading normal and with the original normal.
+fn shading_correction(wi: &Vector3<Float>, shading: &HitPoint, hitpoint: &HitPoint) -> Float {
+    let cosine = wi.dot(&hitpoint.normal).abs();
+    if cosine > 0. {
+        wi.dot(&shading.normal).abs() / cosine
+    } else {
+        0.
+    }
+}
+
+/// Samples the reflection or the refraction of a smooth dielectric surface (see
+/// `Material::Dielectric`), optionally coated with a `thin_film`.
+fn dielectric(
+    refractive_index: Float,
+    thin_film: Option<ThinFilm>,
+    wo: &Vector3<Float>,
+    hitpoint: &HitPoint,
+    wavelengths: &Wavelengths,
+    sampler: &mut dyn Sampler,
+) -> Option<BsdfSample> {
+    let direction = -wo;
+    let reflected = util::reflection(&direction, &hitpoint.normal);
+    let air_refractive_index = 1.;
+
+    let mut outward_normal = hitpoint.normal.into_inner();
+    let mut refractive_index_ratio = air_refractive_index / refractive_index;
+    let mut cosine = -direction.dot(&hitpoint.normal);
+
+    if direction.dot(&hitpoint.normal) > 0. {
+        outward_normal = -outward_normal;
+        refractive_index_ratio = refractive_index / air_refractive_index;
+        cosine = refractive_index * direction.dot(&hitpoint.normal);
+    }
+
+    // Glass absorbs nothing: the light is split between the two lobes, and each lobe is chosen
+    // with the probability of its share.
+    let (direction, reflectance, probability) =
+        match util::refraction(&direction, &outward_normal, refractive_index_ratio) {
+            Some(refracted) => {
+                let reflectance = match thin_film {
+                    Some(film) => {
+                        // The reflectance of the film is the same from both sides; on the way
+                        // out, it is taken at the angle of the refracted ray.
+                        let outside = if wo.dot(&hitpoint.normal) >= 0. {
+                            wo
+                        } else {
+                            &refracted
+                        };
+                        let cosine = outside.dot(&hitpoint.normal).abs();
+                        wavelengths
+                            .map(|lambda| film.reflectance(cosine, lambda, (refractive_index, 0.)))
+                    }
+                    None => SampledSpectrum::constant(util::schlick(cosine, refractive_index)),
+                };
+                let lanes = wavelengths.lanes();
+                let reflection_prob =
+                    (0..lanes).map(|i| reflectance[i]).sum::<Float>() / lanes as Float;
+                if sampler.get_1d() < reflection_prob {
+                    (reflected, reflectance, reflection_prob)
+                } else {
+                    (
+                        refracted,
+                        SampledSpectrum::ones() - reflectance,
+                        1. - reflection_prob,
+                    )
+                }
+            }
+            None => (reflected, SampledSpectrum::ones(), 1.),
+        };
+
+    let cosine = direction.dot(&hitpoint.normal).abs();
+    if cosine == 0. {
+        return None;
+    }
+    Some(BsdfSample {
+        direction,
+        value: reflectance / cosine,
+        pdf: probability,
+        delta: true,
+    })
+}
+
+/// The normal vector, flipped if needed to lie on the same side of the surface as `wo`.
+fn facing(normal: &Vector3<Float>, wo: &Vector3<Float>) -> Vector3<Float> {
+    if normal.dot(wo) < 0. {
+        -normal
+    } else {
+        *normal
+    }
+}
+
+/// Completes a sample of a non-delta lobe with the values of `eval` and `pdf`.
+fn sampled(
+    material: &Material,
+    wo: &Vector3<Float>,
+    direction: Vector3<Float>,
+    hitpoint: &HitPoint,
+    wavelengths: &Wavelengths,
+) -> Option<BsdfSample> {
+    let pdf = material.pdf(wo, &direction, hitpoint);
+    if pdf <= 0. {
+        return None;
+    }
+    Some(BsdfSample {
+        direction,
+        value: material.eval(wo, &direction, hitpoint, wavelengths),
+        pdf,
+        delta: false,
+    })
+}
+
+/// The share of the light which a metal reflects, seen at an angle whose cosine is `cosine`.
+fn metal_reflectance(
+    attenuation: Vector3<Float>,
+    conductor: Option<Conductor>,
+    thin_film: Option<ThinFilm>,
+    cosine: Float,
+    wavelengths: &Wavelengths,
+) -> SampledSpectrum {
+    let attenuation = wavelengths.upsample(&attenuation);
+    match (conductor, thin_film) {
+        (Some(conductor), None) => {
+            attenuation * wavelengths.map(|lambda| conductor.reflectance(cosine, lambda))
+        }
+        (Some(conductor), Some(film)) => {
+            attenuation
+                * wavelengths.map(|lambda| {
+                    film.reflectance(cosine, lambda, conductor.refractive_index(lambda))
+                })
+        }
+        (None, None) => attenuation,
+        (None, Some(film)) => {
+            // A dielectric substrate with the attenuation as its reflectance at normal
+            // incidence.
+            let lambda = wavelengths.map(|lambda| lambda);
+            let lanes = wavelengths.lanes();
+            SampledSpectrum(std::array::from_fn(|i| {
+                if i >= lanes {
+                    return 0.;
+                }
+                let r0 = attenuation[i].clamp(0., 0.999).sqrt();
+                film.reflectance(cosine, lambda[i], ((1. + r0) / (1. - r0), 0.))
+            }))
+        }
+    }
+}
+
+/// The frame of a hit point, with the normal on the side of `wo` (see `HitPoint::tangent_frame`).
+struct LocalFrame {
+    tangent: Vector3<Float>,
+    bitangent: Vector3<Float>,
+    normal: Vector3<Float>,
+}
+
+impl LocalFrame {
+    fn new(wo: &Vector3<Float>, hitpoint: &HitPoint) -> LocalFrame {
+        let (tangent, bitangent, normal) = hitpoint.tangent_frame();
+        let normal = facing(&normal, wo);
+        LocalFrame {
+            tangent,
+            bitangent,
+            normal,
+        }
+    }
+
+    fn to_local(&self, v: &Vector3<Float>) -> Vector3<Float> {
+        Vector3::new(
+            v.dot(&self.tangent),
+            v.dot(&self.bitangent),
+            v.dot(&self.normal),
+        )
+    }
+
+    fn to_world(&self, v: &Vector3<Float>) -> Vector3<Float> {
+        v.x * self.tangent + v.y * self.bitangent + v.z * self.normal
+    }
+}
+
+/// The density (with respect to the solid angle) of the direction `wi`, when the direction is
+/// obtained by normalizing a uniformly distributed point of the ball of radius `fuzziness`
+/// around the unit vector `reflected`.
+///
+/// The points which map to `wi` form the segment `{s * wi : s1 <= s <= s2}` inside the ball, so
+/// the density is the volume of the corresponding cone, `(s2³ - s1³) / 3` per steradian,
+/// divided by the volume of the ball.
+fn fuzzy_reflection_pdf(
+    reflected: &Vector3<Float>,
+    fuzziness: Float,
+    wi: &Vector3<Float>,
+) -> Float {
+    let c = wi.dot(reflected);
+    let discriminant = c * c - reflected.norm_squared() + fuzziness * fuzziness;
+    if discriminant <= 0. {
+        return 0.;
+    }
+    let s2 = c + discriminant.sqrt();
+    if s2 <= 0. {
+        return 0.;
+    }
+    let s1 = (c - discriminant.sqrt()).max(0.);
+    (s2.powi(3) - s1.powi(3)) / (4. * consts::PI * fuzziness.powi(3))
+}
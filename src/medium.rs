@@ -0,0 +1,122 @@
+use crate::float::{consts, Float};
+use crate::sampler::Sampler;
+use crate::spectrum::{SampledSpectrum, Wavelengths};
+use crate::util;
+use nalgebra::{Vector2, Vector3};
+
+/// A homogeneous participating medium, e.g. the inside of a `Subsurface` object.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct Medium {
+    /// The average distance between two interactions of the light with the medium, per color
+    /// channel.
+    pub mean_free_path: Vector3<Float>,
+    /// The share of the light which is scattered (rather than absorbed) at each interaction, per
+    /// color channel.
+    pub albedo: Vector3<Float>,
+    /// The asymmetry of the Henyey–Greenstein phase function: `0` scatters evenly in all
+    /// directions, positive values favour forward scattering and negative values backward
+    /// scattering.
+    pub anisotropy: Float,
+}
+
+/// What happens to a ray which travels through a medium.
+pub enum Interaction {
+    /// The light scatters at distance `t` along the ray.
+    Scatter { t: Float, weight: SampledSpectrum },
+    /// The light reaches the surface at the end of the ray.
+    Surface { weight: SampledSpectrum },
+    /// The light is absorbed by the medium.
+    Absorbed,
+}
+
+/// The most tentative collisions while looking for the next interaction, after which the light
+/// is considered absorbed.
+const MAX_COLLISIONS: u32 = 1 << 16;
+
+impl Medium {
+    /// Samples what happens to a ray which travels through the medium: it either scatters, gets
+    /// absorbed or reaches the surface at distance `t_max` (`t` is measured in units of the ray
+    /// direction, which must be a unit vector). The returned weight multiplies the `throughput`
+    /// of the path so far.
+    ///
+    /// The channels (or wavelengths) attenuate differently, so the distances are sampled with
+    /// spectral tracking (Kutz et al., 2017): tentative collisions happen at the rate of the
+    /// densest channel, and each of them is an absorption, a scattering or a null event (for the
+    /// thinner channels), with probabilities which follow the `throughput`. This keeps the
+    /// weights close to `1` even after long random walks.
+    pub fn sample_interaction(
+        &self,
+        t_max: Float,
+        throughput: &SampledSpectrum,
+        wavelengths: &Wavelengths,
+        sampler: &mut dyn Sampler,
+    ) -> Interaction {
+        let lanes = wavelengths.lanes();
+        let mean_free_path = wavelengths.upsample(&self.mean_free_path);
+        let albedo = wavelengths.upsample(&self.albedo);
+        let sigma_t = SampledSpectrum(std::array::from_fn(|i| {
+            if i < lanes {
+                1. / mean_free_path[i].max(Float::MIN_POSITIVE)
+            } else {
+                0.
+            }
+        }));
+        let sigma_s = sigma_t * albedo;
+        let sigma_a = sigma_t - sigma_s;
+        let majorant = sigma_t.max();
+        let sigma_n = SampledSpectrum::constant(majorant) - sigma_t;
+        let average = |spectrum: SampledSpectrum| {
+            (0..lanes).map(|i| spectrum[i]).sum::<Float>() / lanes as Float
+        };
+
+        let mut t = 0.;
+        let mut weight = SampledSpectrum::ones();
+        for _ in 0..MAX_COLLISIONS {
+            t -= (1. - sampler.get_1d()).ln() / majorant;
+            if t >= t_max {
+                return Interaction::Surface { weight };
+            }
+
+            let history = *throughput * weight;
+            let p_a = average(history * sigma_a).max(0.);
+            let p_s = average(history * sigma_s).max(0.);
+            let p_n = average(history * sigma_n).max(0.);
+            let total = p_a + p_s + p_n;
+            if total <= 0. {
+                return Interaction::Absorbed;
+            }
+
+            let u = sampler.get_1d() * total;
+            if u < p_a {
+                return Interaction::Absorbed;
+            } else if u < p_a + p_s {
+                return Interaction::Scatter {
+                    t,
+                    weight: weight * sigma_s * (total / (majorant * p_s)),
+                };
+            } else {
+                weight = weight * sigma_n * (total / (majorant * p_n));
+            }
+        }
+        Interaction::Absorbed
+    }
+
+    /// Samples the direction of a scattered ray, given the unit `direction` in which the light
+    /// was travelling. The phase function is sampled exactly, so the weight of the new direction
+    /// is `1`.
+    pub fn sample_phase(&self, direction: &Vector3<Float>, u: Vector2<Float>) -> Vector3<Float> {
+        let g = self.anisotropy;
+        let cos_theta = if g.abs() < 1e-3 {
+            1. - 2. * u.x
+        } else {
+            let s = (1. - g * g) / (1. - g + 2. * g * u.x);
+            (1. + g * g - s * s) / (2. * g)
+        }
+        .clamp(-1., 1.);
+        let sin_theta = (1. - cos_theta * cos_theta).max(0.).sqrt();
+        let phi = 2. * consts::PI * u.y;
+
+        let (tangent, bitangent) = util::orthonormal_basis(direction);
+        sin_theta * phi.cos() * tangent + sin_theta * phi.sin() * bitangent + cos_theta * direction
+    }
+}
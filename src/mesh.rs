@@ -0,0 +1,516 @@
+use crate::bvh::{Aabb, Bounded, Bvh};
+use crate::error::{Error, Result};
+use crate::float::Float;
+use crate::hitable::{self, HitPoint, Hitable};
+use crate::material::Material;
+use crate::packet::{self, Lanes, RayPacket, Vectors, WIDTH};
+use crate::ray::Ray;
+use crate::stats;
+use crate::texture::Texture;
+use crate::util;
+use nalgebra::{Point3, Unit, UnitQuaternion, Vector2, Vector3};
+use std::fmt;
+use std::fs;
+use std::path::Path;
+use std::sync::Arc;
+
+/// A corner of a triangle: the indices of its position and, if the model has them, of its
+/// texture coordinates and of its normal vector.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct Corner {
+    pub position: usize,
+    pub uv: Option<usize>,
+    pub normal: Option<usize>,
+}
+
+/// The shape of a triangle mesh, shared between the meshes (and the frames) that use it.
+#[derive(Clone)]
+pub struct Geometry {
+    positions: Vec<Point3<Float>>,
+    uvs: Vec<Vector2<Float>>,
+    normals: Vec<Vector3<Float>>,
+    triangles: Vec<[Corner; 3]>,
+    /// The hierarchy of the bounding boxes of the triangles.
+    bvh: Bvh,
+}
+
+impl Geometry {
+    /// Builds a geometry out of its parts. Every index of the `triangles` must be valid.
+    pub fn new(
+        positions: Vec<Point3<Float>>,
+        uvs: Vec<Vector2<Float>>,
+        normals: Vec<Vector3<Float>>,
+        triangles: Vec<[Corner; 3]>,
+    ) -> Geometry {
+        let mut geometry = Geometry {
+            positions,
+            uvs,
+            normals,
+            triangles,
+            bvh: Bvh::default(),
+        };
+        geometry.update_bvh();
+        geometry
+    }
+
+    /// Loads the vertices (`v`, `vt` and `vn`) and the faces (`f`) of a Wavefront OBJ file.
+    /// Polygons are split into triangles; everything else (groups, materials, ...) is ignored.
+    pub fn load_obj<P: AsRef<Path>>(path: P) -> Result<Geometry> {
+        let path = path.as_ref();
+        let contents = fs::read_to_string(path).map_err(|err| Error::from(err).with_path(path))?;
+        parse_obj(&contents)
+            .map_err(|(line, msg)| Error::Scene(format!("{}:{}: {}", path.display(), line, msg)))
+    }
+
+    /// Parses the contents of an OBJ file (see `load_obj`).
+    pub fn parse_obj(contents: &str) -> Result<Geometry> {
+        parse_obj(contents).map_err(|(line, msg)| Error::Scene(format!("line {}: {}", line, msg)))
+    }
+
+    /// Scales the geometry by `scale` and then moves it by `translation`.
+    pub fn transform(&mut self, scale: Float, translation: &Vector3<Float>) {
+        for position in &mut self.positions {
+            *position = Point3::from(position.coords * scale) + translation;
+        }
+        self.update_bvh();
+    }
+
+    /// Turns the geometry around the origin.
+    pub fn rotate(&mut self, rotation: &UnitQuaternion<Float>) {
+        for position in &mut self.positions {
+            *position = rotation * *position;
+        }
+        for normal in &mut self.normals {
+            *normal = rotation * *normal;
+        }
+        self.update_bvh();
+    }
+
+    /// Moves every vertex along its (smooth) normal vector by the value of the `height` texture
+    /// at its texture coordinates, multiplied by `scale`. The normal vectors are recomputed
+    /// afterwards, from the displaced surface.
+    ///
+    /// Vertices which share a position move together, so that the surface does not crack; the
+    /// texture is looked up at the coordinates of the first corner which uses the position.
+    pub fn displace(&mut self, height: &Texture, scale: Float) {
+        let normals = self.smooth_normals();
+        let mut uvs = vec![None; self.positions.len()];
+        for corner in self.triangles.iter().flatten() {
+            if uvs[corner.position].is_none() {
+                uvs[corner.position] = corner.uv.map(|uv| self.uvs[uv]);
+            }
+        }
+
+        for (i, position) in self.positions.iter_mut().enumerate() {
+            let uv = uvs[i].unwrap_or_else(Vector2::zeros);
+            *position += normals[i] * height.value(&uv) * scale;
+        }
+
+        self.normals = self.smooth_normals();
+        for triangle in &mut self.triangles {
+            for corner in triangle {
+                corner.normal = Some(corner.position);
+            }
+        }
+        self.update_bvh();
+    }
+
+    /// One normal vector per position: the average of the normals of the triangles around it,
+    /// weighted by their area.
+    fn smooth_normals(&self) -> Vec<Vector3<Float>> {
+        let mut normals = vec![Vector3::zeros(); self.positions.len()];
+        for triangle in &self.triangles {
+            let [a, b, c] = triangle.map(|corner| self.positions[corner.position]);
+            let normal = (b - a).cross(&(c - a));
+            for corner in triangle {
+                normals[corner.position] += normal;
+            }
+        }
+        normals
+            .into_iter()
+            .map(|normal| normal.try_normalize(1e-12).unwrap_or_else(Vector3::zeros))
+            .collect()
+    }
+
+    fn update_bvh(&mut self) {
+        let bounds: Vec<Aabb> = (0..self.triangles.len())
+            .map(|i| self.triangle_bounds(i))
+            .collect();
+        self.bvh = Bvh::build(&bounds);
+    }
+
+    fn triangle_bounds(&self, triangle: usize) -> Aabb {
+        Aabb::from_points(
+            self.triangles[triangle]
+                .iter()
+                .map(|corner| &self.positions[corner.position]),
+        )
+    }
+
+    /// The bounds of the whole geometry.
+    pub fn bounds(&self) -> Aabb {
+        Aabb::from_points(&self.positions)
+    }
+
+    pub fn triangle_count(&self) -> usize {
+        self.triangles.len()
+    }
+
+    /// The positions of the corners of the triangle at `index`.
+    pub fn triangle(&self, index: usize) -> [Point3<Float>; 3] {
+        self.triangles[index].map(|corner| self.positions[corner.position])
+    }
+}
+
+/// Parses the contents of an OBJ file. Errors come with their line number.
+fn parse_obj(contents: &str) -> std::result::Result<Geometry, (usize, String)> {
+    let mut positions = vec![];
+    let mut uvs = vec![];
+    let mut normals = vec![];
+    let mut triangles = vec![];
+
+    for (number, line) in contents.lines().enumerate() {
+        let fail = |msg: String| (number + 1, msg);
+        let mut words = line.split_whitespace();
+        let floats = |words: std::str::SplitWhitespace, count: usize| {
+            let values = words
+                .take(count)
+                .map(|word| word.parse::<Float>())
+                .collect::<std::result::Result<Vec<Float>, _>>()
+                .map_err(|err| fail(format!("invalid number: {}", err)))?;
+            if values.len() < count {
+                return Err(fail(format!("expected {} numbers", count)));
+            }
+            Ok(values)
+        };
+
+        match words.next() {
+            Some("v") => {
+                let v = floats(words, 3)?;
+                positions.push(Point3::new(v[0], v[1], v[2]));
+            }
+            Some("vt") => {
+                let v = floats(words, 2)?;
+                uvs.push(Vector2::new(v[0], v[1]));
+            }
+            Some("vn") => {
+                let v = floats(words, 3)?;
+                normals.push(Vector3::new(v[0], v[1], v[2]).normalize());
+            }
+            Some("f") => {
+                let corners = words
+                    .map(|word| {
+                        parse_corner(word, positions.len(), uvs.len(), normals.len()).map_err(fail)
+                    })
+                    .collect::<std::result::Result<Vec<Corner>, _>>()?;
+                if corners.len() < 3 {
+                    return Err(fail("a face needs at least 3 vertices".to_string()));
+                }
+                for i in 1..corners.len() - 1 {
+                    triangles.push([corners[0], corners[i], corners[i + 1]]);
+                }
+            }
+            _ => {}
+        }
+    }
+
+    if triangles.is_empty() {
+        return Err((0, "the model has no faces".to_string()));
+    }
+    Ok(Geometry::new(positions, uvs, normals, triangles))
+}
+
+/// Parses a corner of a face, e.g. `3`, `3/1`, `3//2` or `3/1/2`. Indices start at 1, and
+/// negative indices count backwards from the last vertex read so far.
+fn parse_corner(
+    word: &str,
+    positions: usize,
+    uvs: usize,
+    normals: usize,
+) -> std::result::Result<Corner, String> {
+    let index = |part: Option<&str>, count: usize| -> std::result::Result<Option<usize>, String> {
+        match part {
+            None | Some("") => Ok(None),
+            Some(part) => {
+                let index: i64 = part
+                    .parse()
+                    .map_err(|_| format!("invalid vertex index `{}`", part))?;
+                let resolved = if index < 0 {
+                    count as i64 + index
+                } else {
+                    index - 1
+                };
+                if resolved < 0 || resolved >= count as i64 {
+                    Err(format!("vertex index `{}` is out of range", part))
+                } else {
+                    Ok(Some(resolved as usize))
+                }
+            }
+        }
+    };
+
+    let mut parts = word.split('/');
+    let position =
+        index(parts.next(), positions)?.ok_or_else(|| format!("missing position in `{}`", word))?;
+    Ok(Corner {
+        position,
+        uv: index(parts.next(), uvs)?,
+        normal: index(parts.next(), normals)?,
+    })
+}
+
+impl fmt::Debug for Geometry {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Geometry")
+            .field("positions", &self.positions.len())
+            .field("triangles", &self.triangles.len())
+            .finish()
+    }
+}
+
+/// A triangle mesh, made of one material.
+#[derive(Clone, Debug)]
+pub struct Mesh {
+    geometry: Arc<Geometry>,
+    material: Material,
+}
+
+impl Mesh {
+    pub fn new(geometry: Arc<Geometry>, material: Material) -> Mesh {
+        Mesh { geometry, material }
+    }
+
+    pub fn geometry(&self) -> &Geometry {
+        &self.geometry
+    }
+
+    pub fn material(&self) -> &Material {
+        &self.material
+    }
+
+    /// Intersects the rays of a packet with one triangle (the Möller–Trumbore algorithm), all
+    /// lanes at once. Returns the distance along the ray and the barycentric coordinates of the
+    /// second and third corners, for each lane which hits the triangle at a `t` in `(t_min,
+    /// t_max)`.
+    fn hit_triangle<const N: usize>(
+        &self,
+        triangle: &[Corner; 3],
+        packet: &RayPacket<N>,
+        t_min: Float,
+        t_max: &Lanes<N>,
+    ) -> Option<[Option<(Float, Float, Float)>; N]> {
+        stats::count(|c| c.primitive_tests += 1);
+        let [a, b, c] = triangle.map(|corner| self.geometry.positions[corner.position]);
+        let (ab, ac) = (b - a, c - a);
+        let (origin, direction) = (packet.origin(), packet.direction());
+        let (ab_lanes, ac_lanes) = (packet::splat(&ab), packet::splat(&ac));
+        let p = cross_lanes(direction, &ac_lanes);
+        // Only rays exactly parallel to the triangle are rejected here: the determinant scales
+        // with the size of the triangle, so no fixed epsilon suits every scene; nearly parallel
+        // rays get a large error bound on `t` instead.
+        let determinant = packet::dot(&ab_lanes, &p);
+        let mut hit: [bool; N] =
+            std::array::from_fn(|i| packet.is_active(i) && determinant[i] != 0.);
+        if !packet::any(&hit) {
+            return None;
+        }
+
+        let inverse = determinant.map(|determinant| 1. / determinant);
+        let s = [0, 1, 2].map(|axis| origin[axis].map(|o| o - a[axis]));
+        let u = packet::dot(&s, &p);
+        let u: Lanes<N> = packet::lanes(|i| u[i] * inverse[i]);
+        for i in 0..N {
+            hit[i] &= (0. ..=1.).contains(&u[i]);
+        }
+        if !packet::any(&hit) {
+            return None;
+        }
+        let q = cross_lanes(&s, &ab_lanes);
+        let v = packet::dot(direction, &q);
+        let v: Lanes<N> = packet::lanes(|i| v[i] * inverse[i]);
+        for i in 0..N {
+            hit[i] &= !(v[i] < 0. || u[i] + v[i] > 1.);
+        }
+        if !packet::any(&hit) {
+            return None;
+        }
+        let t = packet::dot(&ac_lanes, &q);
+        let t: Lanes<N> = packet::lanes(|i| t[i] * inverse[i]);
+
+        // A conservative bound on the rounding errors of the triple products which give `t`.
+        let bound = packet::splat(&abs_cross(&ab.abs(), &ac.abs()));
+        let (s_bound, direction_bound) = (
+            packet::dot(&packet::abs(&s), &bound),
+            packet::dot(&packet::abs(direction), &bound),
+        );
+        Some(std::array::from_fn(|i| {
+            let t_error =
+                util::gamma(8) * (s_bound[i] + t[i].abs() * direction_bound[i]) * inverse[i].abs()
+                    + util::gamma(2) * t[i].abs();
+            (hit[i] && t_min < t[i] - t_error && t[i] < t_max[i]).then_some((t[i], u[i], v[i]))
+        }))
+    }
+
+    /// The texture coordinates of the corners of a triangle. Without texture coordinates, the
+    /// corners get `(0, 0)`, `(1, 0)` and `(1, 1)`.
+    fn corner_uvs(&self, triangle: &[Corner; 3]) -> [Vector2<Float>; 3] {
+        match triangle.map(|corner| corner.uv) {
+            [Some(a), Some(b), Some(c)] => [a, b, c].map(|uv| self.geometry.uvs[uv]),
+            _ => [
+                Vector2::new(0., 0.),
+                Vector2::new(1., 0.),
+                Vector2::new(1., 1.),
+            ],
+        }
+    }
+
+    /// Whether the ray goes through a cutout of the material at the point with barycentric
+    /// coordinates `u` and `v` (see `hitable::passes_through`).
+    fn passes_through(
+        &self,
+        triangle: &[Corner; 3],
+        ray: &Ray,
+        t: Float,
+        u: Float,
+        v: Float,
+    ) -> bool {
+        self.material.opacity().is_some_and(|opacity| {
+            hitable::passes_through(opacity, &self.uv(triangle, u, v), ray, t)
+        })
+    }
+
+    /// The texture coordinates of the point with barycentric coordinates `u` and `v`.
+    fn uv(&self, triangle: &[Corner; 3], u: Float, v: Float) -> Vector2<Float> {
+        let [a, b, c] = self.corner_uvs(triangle);
+        a * (1. - u - v) + b * u + c * v
+    }
+
+    fn hitpoint(&self, triangle: &[Corner; 3], t: Float, u: Float, v: Float) -> HitPoint<'_> {
+        let geometry = &self.geometry;
+        let weights = [1. - u - v, u, v];
+        let [a, b, c] = triangle.map(|corner| geometry.positions[corner.position]);
+        let p = Point3::from(a.coords * weights[0] + b.coords * weights[1] + c.coords * weights[2]);
+        let p_error = util::gamma(7)
+            * (a.coords.abs() * weights[0].abs()
+                + b.coords.abs() * weights[1].abs()
+                + c.coords.abs() * weights[2].abs());
+
+        // Counter-clockwise triangles face the viewer.
+        let geometric = (b - a).cross(&(c - a)).normalize();
+        let normal = match triangle.map(|corner| corner.normal) {
+            [Some(na), Some(nb), Some(nc)] => {
+                let normal = geometry.normals[na] * weights[0]
+                    + geometry.normals[nb] * weights[1]
+                    + geometry.normals[nc] * weights[2];
+                match normal.try_normalize(1e-12) {
+                    Some(normal) if normal.dot(&geometric) < 0. => -normal,
+                    Some(normal) => normal,
+                    None => geometric,
+                }
+            }
+            _ => geometric,
+        };
+
+        let [uv_a, uv_b, uv_c] = self.corner_uvs(triangle);
+        let uv = uv_a * weights[0] + uv_b * weights[1] + uv_c * weights[2];
+
+        let (duv_ac, duv_bc) = (uv_a - uv_c, uv_b - uv_c);
+        let (dp_ac, dp_bc) = (a - c, b - c);
+        let determinant = duv_ac.x * duv_bc.y - duv_ac.y * duv_bc.x;
+        let (dpdu, dpdv) = if determinant.abs() < 1e-12 {
+            util::orthonormal_basis(&normal)
+        } else {
+            (
+                (duv_bc.y * dp_ac - duv_ac.y * dp_bc) / determinant,
+                (duv_ac.x * dp_bc - duv_bc.x * dp_ac) / determinant,
+            )
+        };
+
+        HitPoint {
+            t,
+            p,
+            p_error,
+            normal: Unit::new_unchecked(normal),
+            geometric_normal: Unit::new_unchecked(geometric),
+            uv,
+            dpdu,
+            dpdv,
+            material: &self.material,
+        }
+    }
+}
+
+/// The cross product of the vectors of each lane, in the same order as `nalgebra` computes it.
+fn cross_lanes<const N: usize>(a: &Vectors<N>, b: &Vectors<N>) -> Vectors<N> {
+    [
+        packet::lanes(|i| a[1][i] * b[2][i] - a[2][i] * b[1][i]),
+        packet::lanes(|i| a[2][i] * b[0][i] - a[0][i] * b[2][i]),
+        packet::lanes(|i| a[0][i] * b[1][i] - a[1][i] * b[0][i]),
+    ]
+}
+
+/// The cross product of two vectors with non-negative coordinates, with the terms of each
+/// coordinate added instead of subtracted: a bound on the magnitude of the terms of the cross
+/// product of any vectors with these absolute values.
+fn abs_cross(a: &Vector3<Float>, b: &Vector3<Float>) -> Vector3<Float> {
+    Vector3::new(
+        a.y * b.z + a.z * b.y,
+        a.z * b.x + a.x * b.z,
+        a.x * b.y + a.y * b.x,
+    )
+}
+
+impl Hitable for Mesh {
+    /// Finds the closest triangle through the bounding volume hierarchy. Triangles which are
+    /// transparent at the hit point (see `Material::Cutout`) are skipped.
+    fn hit(&self, ray: &Ray, t_min: Float, t_max: Float) -> Option<HitPoint<'_>> {
+        let single = RayPacket::single(ray);
+        let mut closest = None;
+        self.geometry
+            .bvh
+            .traverse(ray, t_min, t_max, |index, t_max| {
+                let triangle = &self.geometry.triangles[index];
+                let [hit] = self.hit_triangle(triangle, &single, t_min, &[t_max])?;
+                let (t, u, v) = hit?;
+                if self.passes_through(triangle, ray, t, u, v) {
+                    return None;
+                }
+                closest = Some((triangle, t, u, v));
+                Some(t)
+            });
+        closest.map(|(triangle, t, u, v)| self.hitpoint(triangle, t, u, v))
+    }
+
+    fn hit_packet(
+        &self,
+        packet: &RayPacket,
+        t_min: Float,
+        t_max: &Lanes<WIDTH>,
+    ) -> [Option<HitPoint<'_>>; WIDTH] {
+        let mut closest = [None; WIDTH];
+        self.geometry
+            .bvh
+            .traverse_packet(packet, t_min, t_max, |index, packet, t_max| {
+                let triangle = &self.geometry.triangles[index];
+                let hits = match self.hit_triangle(triangle, packet, t_min, t_max) {
+                    Some(hits) => hits,
+                    None => return [None; WIDTH],
+                };
+                std::array::from_fn(|lane| {
+                    let (t, u, v) = hits[lane]?;
+                    if self.passes_through(triangle, &packet.ray(lane), t, u, v) {
+                        return None;
+                    }
+                    closest[lane] = Some((triangle, t, u, v));
+                    Some(t)
+                })
+            });
+        closest.map(|closest| closest.map(|(triangle, t, u, v)| self.hitpoint(triangle, t, u, v)))
+    }
+}
+
+impl Bounded for Mesh {
+    fn bounds(&self) -> Aabb {
+        self.geometry.bounds()
+    }
+}
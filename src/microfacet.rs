@@ -0,0 +1,97 @@
+use crate::float::{consts, Float};
+use nalgebra::{Vector2, Vector3};
+
+/// The anisotropic GGX (Trowbridge–Reitz) distribution of the normals of the microfacets which
+/// make up a rough surface.
+///
+/// Directions are given in the local frame of the surface: `x` along the tangent, `y` along the
+/// bitangent and `z` along the normal.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct Ggx {
+    pub alpha_x: Float,
+    pub alpha_y: Float,
+}
+
+/// The smallest roughness along either axis, below which the distribution is numerically
+/// indistinguishable from a perfect mirror.
+const MIN_ALPHA: Float = 1e-4;
+
+impl Ggx {
+    /// The distribution of a surface with the given `roughness` in `[0, 1]` (squared, so that it
+    /// changes the look of the surface evenly), brushed along the tangent for positive
+    /// `anisotropy` and along the bitangent for negative `anisotropy` (see
+    /// `Material::Microfacet`).
+    pub fn new(roughness: Float, anisotropy: Float) -> Ggx {
+        let alpha = roughness * roughness;
+        let aspect = (1. - 0.9 * anisotropy.clamp(-1., 1.)).sqrt();
+        Ggx {
+            alpha_x: (alpha * aspect).max(MIN_ALPHA),
+            alpha_y: (alpha / aspect).max(MIN_ALPHA),
+        }
+    }
+
+    /// The density of microfacets whose normal is `h`, with respect to the solid angle and
+    /// projected onto the surface.
+    pub fn d(&self, h: &Vector3<Float>) -> Float {
+        if h.z <= 0. {
+            return 0.;
+        }
+        let e = (h.x / self.alpha_x).powi(2) + (h.y / self.alpha_y).powi(2) + h.z * h.z;
+        1. / (consts::PI * self.alpha_x * self.alpha_y * e * e)
+    }
+
+    /// Smith's auxiliary function, which measures how much the microfacets seen from `w` hide
+    /// each other.
+    pub fn lambda(&self, w: &Vector3<Float>) -> Float {
+        if w.z == 0. {
+            return Float::INFINITY;
+        }
+        let tan2 = ((self.alpha_x * w.x).powi(2) + (self.alpha_y * w.y).powi(2)) / (w.z * w.z);
+        0.5 * ((1. + tan2).sqrt() - 1.)
+    }
+
+    /// The share of the microfacets which is visible from `w`.
+    pub fn g1(&self, w: &Vector3<Float>) -> Float {
+        1. / (1. + self.lambda(w))
+    }
+
+    /// The share of the microfacets which is visible from both `wo` and `wi` (the height
+    /// correlated masking-shadowing function).
+    pub fn g(&self, wo: &Vector3<Float>, wi: &Vector3<Float>) -> Float {
+        1. / (1. + self.lambda(wo) + self.lambda(wi))
+    }
+
+    /// Samples the normal of a microfacet visible from `wo` (with `wo.z > 0`), in proportion to
+    /// its projected area (Heitz, 2018). The density of `h` is `visible_normal_pdf`.
+    pub fn sample_visible_normal(&self, wo: &Vector3<Float>, u: Vector2<Float>) -> Vector3<Float> {
+        // Stretch the view direction so that the distribution becomes the hemisphere.
+        let wh = Vector3::new(self.alpha_x * wo.x, self.alpha_y * wo.y, wo.z).normalize();
+        let length2 = wh.x * wh.x + wh.y * wh.y;
+        let t1 = if length2 > 0. {
+            Vector3::new(-wh.y, wh.x, 0.) / length2.sqrt()
+        } else {
+            Vector3::x()
+        };
+        let t2 = wh.cross(&t1);
+
+        // Sample the disk which the hemisphere projects onto, as seen from `wh`.
+        let r = u.x.sqrt();
+        let phi = 2. * consts::PI * u.y;
+        let p1 = r * phi.cos();
+        let s = 0.5 * (1. + wh.z);
+        let p2 = (1. - s) * (1. - p1 * p1).max(0.).sqrt() + s * r * phi.sin();
+        let nh = p1 * t1 + p2 * t2 + (1. - p1 * p1 - p2 * p2).max(0.).sqrt() * wh;
+
+        // Unstretch.
+        Vector3::new(self.alpha_x * nh.x, self.alpha_y * nh.y, nh.z.max(0.)).normalize()
+    }
+
+    /// The density (with respect to the solid angle) with which `sample_visible_normal` chooses
+    /// `h`, seen from `wo`.
+    pub fn visible_normal_pdf(&self, wo: &Vector3<Float>, h: &Vector3<Float>) -> Float {
+        if wo.z <= 0. {
+            return 0.;
+        }
+        self.g1(wo) * wo.dot(h).max(0.) * self.d(h) / wo.z
+    }
+}
@@ -0,0 +1,124 @@
+//! Packets of rays which are traced together. The camera rays of neighbouring samples go in
+//! nearly the same direction and hit nearly the same objects, so a packet visits the bounding
+//! boxes of the scene once for all of its rays and intersects the primitives with all of them
+//! at once.
+//!
+//! The rays are stored coordinate by coordinate, with one lane per ray (`[Float; N]`), and the
+//! intersection code does the same operations on every lane, which the compiler turns into SIMD
+//! instructions (build with e.g. `-C target-cpu=native` for the widest ones). The intersection
+//! code is written for any number of lanes: the scalar code uses the very same arithmetic with a
+//! single lane, so that both find exactly the same hit points.
+
+use crate::float::Float;
+use crate::ray::Ray;
+use nalgebra::{Point3, Vector3};
+
+/// The number of rays in a packet: as many as fit into a 256-bit SIMD register (e.g. AVX).
+pub const WIDTH: usize = 32 / std::mem::size_of::<Float>();
+
+/// One value per ray of a packet.
+pub type Lanes<const N: usize> = [Float; N];
+
+/// One 3D vector per ray of a packet, stored coordinate by coordinate.
+pub type Vectors<const N: usize> = [Lanes<N>; 3];
+
+/// Up to `N` rays which are traced together. Lanes without a ray are inactive: their results
+/// are meaningless and ignored.
+#[derive(Copy, Clone, Debug)]
+pub struct RayPacket<const N: usize = WIDTH> {
+    origin: Vectors<N>,
+    direction: Vectors<N>,
+    active: [bool; N],
+}
+
+impl<const N: usize> RayPacket<N> {
+    /// A packet with one ray per lane; the lanes of the `None`s are inactive.
+    pub fn new(rays: &[Option<Ray>; N]) -> RayPacket<N> {
+        let coordinate = |f: fn(&Ray) -> Vector3<Float>, axis: usize| {
+            lanes(|lane| rays[lane].as_ref().map_or(0., |ray| f(ray)[axis]))
+        };
+        let vectors = |f: fn(&Ray) -> Vector3<Float>| [0, 1, 2].map(|axis| coordinate(f, axis));
+        RayPacket {
+            origin: vectors(|ray| ray.origin().coords),
+            direction: vectors(|ray| *ray.direction()),
+            active: std::array::from_fn(|lane| rays[lane].is_some()),
+        }
+    }
+
+    pub fn origin(&self) -> &Vectors<N> {
+        &self.origin
+    }
+
+    pub fn direction(&self) -> &Vectors<N> {
+        &self.direction
+    }
+
+    pub fn active(&self) -> &[bool; N] {
+        &self.active
+    }
+
+    pub fn is_active(&self, lane: usize) -> bool {
+        self.active[lane]
+    }
+
+    /// The ray of a lane.
+    pub fn ray(&self, lane: usize) -> Ray {
+        Ray::new(
+            Point3::new(
+                self.origin[0][lane],
+                self.origin[1][lane],
+                self.origin[2][lane],
+            ),
+            Vector3::new(
+                self.direction[0][lane],
+                self.direction[1][lane],
+                self.direction[2][lane],
+            ),
+        )
+    }
+
+    /// The same packet, with only the lanes which are also active in `mask`.
+    pub fn restrict(&self, mask: &[bool; N]) -> RayPacket<N> {
+        RayPacket {
+            active: std::array::from_fn(|lane| self.active[lane] && mask[lane]),
+            ..*self
+        }
+    }
+}
+
+impl RayPacket<1> {
+    /// A packet of a single ray, for the scalar versions of the intersection code.
+    pub fn single(ray: &Ray) -> RayPacket<1> {
+        let (origin, direction) = (ray.origin(), ray.direction());
+        RayPacket {
+            origin: [[origin.x], [origin.y], [origin.z]],
+            direction: [[direction.x], [direction.y], [direction.z]],
+            active: [true],
+        }
+    }
+}
+
+/// The lanes with the values of `f` for each lane.
+pub fn lanes<const N: usize, F: FnMut(usize) -> Float>(f: F) -> Lanes<N> {
+    std::array::from_fn(f)
+}
+
+/// The same vector in every lane.
+pub fn splat<const N: usize>(v: &Vector3<Float>) -> Vectors<N> {
+    [[v.x; N], [v.y; N], [v.z; N]]
+}
+
+/// The dot product of the vectors of each lane, summed up in the same order as `nalgebra` does.
+pub fn dot<const N: usize>(a: &Vectors<N>, b: &Vectors<N>) -> Lanes<N> {
+    lanes(|i| a[0][i] * b[0][i] + a[1][i] * b[1][i] + a[2][i] * b[2][i])
+}
+
+/// The absolute values of the coordinates of the vectors of each lane.
+pub fn abs<const N: usize>(v: &Vectors<N>) -> Vectors<N> {
+    v.map(|axis| axis.map(Float::abs))
+}
+
+/// Whether any lane of the mask is set.
+pub fn any<const N: usize>(mask: &[bool; N]) -> bool {
+    mask.contains(&true)
+}
@@ -1,68 +1,176 @@
-use crate::hitable::Hitable;
-use crate::material::Scatterable;
+use crate::float::Float;
+use crate::hitable::{HitPoint, Hitable};
+use crate::material::Bsdf;
+use crate::medium::{Interaction, Medium};
+use crate::sampler::Sampler;
+use crate::spectrum::{SampledSpectrum, Wavelengths};
+use crate::stats;
 use nalgebra::{Point3, Vector3};
-use std::f64;
+
+/// The most scattering events of a random walk through a medium, after which the light is
+/// considered absorbed.
+const MAX_SCATTERING_EVENTS: u32 = 4096;
 
 /// A ray of light.
-#[derive(Debug)]
+#[derive(Clone, Debug)]
 pub struct Ray {
     /// The source of the ray. Keep in mind that the ray is "reversed" i.e. it starts in the
     /// observer point and goes backwards towards the light emitting source.
-    origin: Point3<f64>,
+    origin: Point3<Float>,
     /// The 3D direction of the ray.
-    direction: Vector3<f64>,
+    direction: Vector3<Float>,
 }
 
 impl Ray {
-    pub fn new(origin: Point3<f64>, direction: Vector3<f64>) -> Ray {
+    pub fn new(origin: Point3<Float>, direction: Vector3<Float>) -> Ray {
         Ray { origin, direction }
     }
 
-    pub fn origin(&self) -> &Point3<f64> {
+    pub fn origin(&self) -> &Point3<Float> {
         &self.origin
     }
 
-    pub fn direction(&self) -> &Vector3<f64> {
+    pub fn direction(&self) -> &Vector3<Float> {
         &self.direction
     }
 
     /// The point at some parameter `t` is defined as a 3D point located on the ray at a distance
     /// of `t * direction` from the origin.
-    pub fn point_at_parameter(&self, t: f64) -> Point3<f64> {
+    pub fn point_at_parameter(&self, t: Float) -> Point3<Float> {
         self.origin + t * self.direction
     }
 
     /// Traces the ray backwards and computes its color. It simulates at most 100 hit points with
-    /// the elements of the world. For each hit point, it continues the simulation using the scattered ray instead of the original one. Depending on the attenuation of the materials which are hit, each consecutive ray loses some color intensity. When no additional object is hit, the world background (a vertical gradient from cyan to white) is used for the color.
-    /// Note: when computing hitpoints, `t_min = 0.001` is used in order to prevent [shadow
-    /// acne](https://computergraphics.stackexchange.com/questions/2192/cause-of-shadow-acne).
-    pub fn color(&self, world: &dyn Hitable, depth: i32) -> Vector3<f64> {
-        match world.hit(self, 0.001, f64::INFINITY) {
+    /// the elements of the world. For each hit point, it continues the simulation using a scattered
+    /// ray sampled from the BSDF of the material. Depending on the BSDF weights of the materials
+    /// which are hit, each consecutive ray loses some color intensity. When no additional object is
+    /// hit, the world background (a vertical gradient from cyan to white) is used for the color.
+    /// Note: scattered rays start slightly off the surface, by the error bound of the hit point
+    /// (see `HitPoint::spawn_ray`), in order to prevent [shadow
+    /// acne](https://computergraphics.stackexchange.com/questions/2192/cause-of-shadow-acne) at
+    /// any scale.
+    /// The color is given at the `wavelengths` carried by the ray (see `Wavelengths::to_rgb`). The
+    /// scattered rays are chosen with values from the `sampler`.
+    pub fn color(
+        &self,
+        world: &dyn Hitable,
+        depth: i32,
+        wavelengths: &mut Wavelengths,
+        sampler: &mut dyn Sampler,
+    ) -> SampledSpectrum {
+        self.trace(world, depth, None, wavelengths, sampler)
+    }
+
+    /// Like `color`, for a ray whose hit point with the world is already known (e.g. a camera
+    /// ray traced as part of a packet, see `Hitable::hit_packet`).
+    pub fn color_with_hit<'w>(
+        &self,
+        hit: Option<HitPoint<'w>>,
+        world: &'w dyn Hitable,
+        depth: i32,
+        wavelengths: &mut Wavelengths,
+        sampler: &mut dyn Sampler,
+    ) -> SampledSpectrum {
+        Ray::count(depth);
+        self.shade(hit, world, depth, None, wavelengths, sampler)
+    }
+
+    /// Like `color`, for a ray which may start inside the `medium` of a `Subsurface` object. The
+    /// random walk through the medium is simulated step by step: each scattering event moves the
+    /// ray, until it reaches the surface of the object.
+    fn trace(
+        &self,
+        world: &dyn Hitable,
+        depth: i32,
+        medium: Option<Medium>,
+        wavelengths: &mut Wavelengths,
+        sampler: &mut dyn Sampler,
+    ) -> SampledSpectrum {
+        Ray::count(depth);
+        let hit = world.hit(self, 0., Float::INFINITY);
+        self.shade(hit, world, depth, medium, wavelengths, sampler)
+    }
+
+    /// Counts a ray which is `depth` bounces away from the camera.
+    fn count(depth: i32) {
+        if depth == 0 {
+            stats::count(|c| c.primary_rays += 1);
+        } else {
+            stats::count(|c| c.secondary_rays += 1);
+        }
+    }
+
+    /// Computes the color of the ray out of its hit point `hit` with the `world` (see `trace`).
+    fn shade<'w>(
+        &self,
+        hit: Option<HitPoint<'w>>,
+        world: &'w dyn Hitable,
+        depth: i32,
+        medium: Option<Medium>,
+        wavelengths: &mut Wavelengths,
+        sampler: &mut dyn Sampler,
+    ) -> SampledSpectrum {
+        let mut ray = Ray::new(self.origin, self.direction);
+        let mut hit = hit;
+        let mut weight = SampledSpectrum::ones();
+
+        if let Some(medium) = medium {
+            let mut events = 0;
+            loop {
+                let t_max = hit.as_ref().map_or(Float::INFINITY, |hitpoint| hitpoint.t);
+                match medium.sample_interaction(t_max, &weight, wavelengths, sampler) {
+                    Interaction::Surface { weight: w } => {
+                        weight *= w;
+                        break;
+                    }
+                    Interaction::Scatter { t, weight: w } => {
+                        weight *= w;
+                        events += 1;
+                        if events > MAX_SCATTERING_EVENTS || weight.is_black() {
+                            return SampledSpectrum::zeros();
+                        }
+                        let direction = medium.sample_phase(ray.direction(), sampler.get_2d());
+                        ray = Ray::new(ray.point_at_parameter(t), direction);
+                        stats::count(|c| c.secondary_rays += 1);
+                        hit = world.hit(&ray, 0., Float::INFINITY);
+                    }
+                    Interaction::Absorbed => return SampledSpectrum::zeros(),
+                }
+            }
+        }
+
+        match hit {
             Some(hitpoint) => {
                 if depth > 100 {
-                    return Vector3::new(0., 0., 0.);
+                    return SampledSpectrum::zeros();
                 }
 
-                match hitpoint.material.scatter(self, &hitpoint) {
-                    Some((scattered_ray, attenuation)) => {
-                        let color = scattered_ray.color(world, depth + 1);
-
-                        Vector3::new(
-                            attenuation.x * color.x,
-                            attenuation.y * color.y,
-                            attenuation.z * color.z,
-                        )
+                let wo = -ray.direction().normalize();
+                match hitpoint
+                    .material
+                    .sample(&wo, &hitpoint, wavelengths, sampler)
+                {
+                    Some(sample) => {
+                        let scattered_ray = hitpoint.spawn_ray(&sample.direction);
+                        // Rays which go into a translucent object travel through its medium.
+                        let interior = hitpoint
+                            .material
+                            .interior()
+                            .filter(|_| sample.direction.dot(&hitpoint.normal) < 0.);
+                        weight
+                            * sample.weight(&hitpoint.normal)
+                            * scattered_ray.trace(world, depth + 1, interior, wavelengths, sampler)
                     }
-                    None => Vector3::new(0., 0., 0.),
+                    None => SampledSpectrum::zeros(),
                 }
             }
             None => {
-                let unit_direction = self.direction().normalize();
+                let unit_direction = ray.direction().normalize();
                 let t = 0.5 * (unit_direction.y + 1.);
 
                 let white = Vector3::new(1., 1., 1.);
                 let cyan = Vector3::new(0.5, 0.7, 1.);
-                (1. - t) * white + t * cyan
+                weight * wavelengths.upsample(&((1. - t) * white + t * cyan))
             }
         }
     }
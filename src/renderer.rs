@@ -0,0 +1,605 @@
+use crate::color::{ColorSpace, Encoding};
+use crate::config::{Config, Resolution};
+use crate::error::{Error, Result};
+use crate::filter::Filter;
+use crate::float::Float;
+use crate::hitable::Hitable;
+use crate::packet::{RayPacket, WIDTH};
+use crate::ray::Ray;
+use crate::sampler::{Sampler, SamplerKind};
+use crate::scene::Scene;
+use crate::spectrum::Wavelengths;
+use crate::stats::{self, Counters};
+use nalgebra::Vector3;
+use rand::prelude::SliceRandom;
+use rand::thread_rng;
+use std::path::Path;
+use std::thread;
+
+/// A rectangular area of the image, measured in pixels. `(x, y)` is the upper left corner.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct Region {
+    pub x: u32,
+    pub y: u32,
+    pub width: u32,
+    pub height: u32,
+}
+
+impl Region {
+    /// The region which covers the entire image.
+    pub fn full(resolution: &Resolution) -> Region {
+        Region {
+            x: 0,
+            y: 0,
+            width: resolution.width,
+            height: resolution.height,
+        }
+    }
+
+    /// Number of pixels inside the region.
+    pub fn area(&self) -> u64 {
+        self.width as u64 * self.height as u64
+    }
+
+    fn is_inside(&self, resolution: &Resolution) -> bool {
+        self.x as u64 + self.width as u64 <= resolution.width as u64
+            && self.y as u64 + self.height as u64 <= resolution.height as u64
+    }
+
+    /// Splits the region into tiles of at most `size` by `size` pixels.
+    fn tiles(&self, size: u32) -> Vec<Region> {
+        (self.y..self.y + self.height)
+            .step_by(size as usize)
+            .flat_map(|y| {
+                (self.x..self.x + self.width)
+                    .step_by(size as usize)
+                    .map(move |x| Region {
+                        x,
+                        y,
+                        width: size.min(self.x + self.width - x),
+                        height: size.min(self.y + self.height - y),
+                    })
+            })
+            .collect()
+    }
+
+    /// The region grown by `margin` pixels on every side and then clipped to `bounds`. The
+    /// result is empty if the grown region lies outside of `bounds`.
+    fn expand(&self, margin: u32, bounds: &Region) -> Region {
+        let x = self.x.saturating_sub(margin).max(bounds.x);
+        let y = self.y.saturating_sub(margin).max(bounds.y);
+        let right = (self.x + self.width + margin).min(bounds.x + bounds.width);
+        let bottom = (self.y + self.height + margin).min(bounds.y + bounds.height);
+        Region {
+            x,
+            y,
+            width: right.saturating_sub(x),
+            height: bottom.saturating_sub(y),
+        }
+    }
+}
+
+/// The result of a render: the linear (not gamma corrected) color of every pixel inside the
+/// rendered `Region`, in the working color space of the renderer. Pixels are stored row by row,
+/// starting with the upper left corner.
+#[derive(Clone, Debug)]
+pub struct Framebuffer {
+    region: Region,
+    space: ColorSpace,
+    pixels: Vec<Vector3<Float>>,
+}
+
+impl Framebuffer {
+    pub fn region(&self) -> Region {
+        self.region
+    }
+
+    pub fn width(&self) -> u32 {
+        self.region.width
+    }
+
+    pub fn height(&self) -> u32 {
+        self.region.height
+    }
+
+    pub fn color_space(&self) -> ColorSpace {
+        self.space
+    }
+
+    pub fn pixels(&self) -> &[Vector3<Float>] {
+        &self.pixels
+    }
+
+    /// The color of the pixel at `(x, y)`, relative to the upper left corner of the region.
+    pub fn pixel(&self, x: u32, y: u32) -> &Vector3<Float> {
+        &self.pixels[self.index(x, y)]
+    }
+
+    fn index(&self, x: u32, y: u32) -> usize {
+        (y * self.region.width + x) as usize
+    }
+
+    /// Converts the framebuffer into an 8-bit image with the given encoding.
+    pub fn to_image(&self, encoding: Encoding) -> image::RgbImage {
+        image::RgbImage::from_fn(self.width(), self.height(), |x, y| {
+            image::Rgb(encoding.encode_pixel(self.pixel(x, y), self.space))
+        })
+    }
+
+    /// Saves the framebuffer as an 8-bit image with the given encoding. PNG files are tagged
+    /// with the encoding; other formats are saved without color metadata.
+    pub fn save<P: AsRef<Path>>(&self, path: P, encoding: Encoding) -> Result<()> {
+        let path = path.as_ref();
+        let image = self.to_image(encoding);
+        let is_png = path
+            .extension()
+            .is_some_and(|extension| extension.eq_ignore_ascii_case("png"));
+
+        if is_png {
+            encoding.write_png(path, &image)
+        } else {
+            warn!(
+                "{}: only PNG files are tagged with their color encoding",
+                path.display()
+            );
+            image.save(path).map_err(Error::from)
+        }
+        .map_err(|err| err.with_path(path))
+    }
+}
+
+/// Renders a `Scene` into a `Framebuffer`. The work is split between multiple threads, each of
+/// them taking pixels from a shared queue.
+///
+/// ```no_run
+/// # use rtow::{Renderer, Region, Resolution, Scene};
+/// # use rtow::camera::CameraSettings;
+/// let scene = Scene::random(10, 0, &CameraSettings::default(), 16. / 9.).unwrap();
+/// let framebuffer = Renderer::new(Resolution::from_str("720p").unwrap())
+///     .samples(4)
+///     .region(Region { x: 0, y: 0, width: 64, height: 64 })
+///     .on_progress(|done, total| println!("{}/{}", done, total))
+///     .render(&scene)
+///     .unwrap();
+/// # use std::str::FromStr;
+/// ```
+pub struct Renderer<'a> {
+    resolution: Resolution,
+    samples: u32,
+    threads: usize,
+    spectral: bool,
+    color_space: ColorSpace,
+    filter: Filter,
+    sampler: SamplerKind,
+    seed: u64,
+    packets: bool,
+    region: Option<Region>,
+    progress: Option<Box<dyn FnMut(u64, u64) + 'a>>,
+    counters: Counters,
+}
+
+impl<'a> Renderer<'a> {
+    /// Creates a renderer for images of the given resolution, which uses 10 samples per pixel and
+    /// one thread per CPU.
+    pub fn new(resolution: Resolution) -> Renderer<'a> {
+        Renderer {
+            resolution,
+            samples: 10,
+            threads: num_cpus::get(),
+            spectral: false,
+            color_space: ColorSpace::default(),
+            filter: Filter::default(),
+            sampler: SamplerKind::default(),
+            seed: 0,
+            packets: true,
+            region: None,
+            progress: None,
+            counters: Counters::default(),
+        }
+    }
+
+    /// Creates a renderer with the resolution, samples, threads, rendering mode, working color
+    /// space, reconstruction filter, sampler and packet tracing given by a `Config`.
+    pub fn from_config(cfg: &Config) -> Renderer<'a> {
+        Renderer::new(cfg.resolution.clone())
+            .samples(cfg.samples)
+            .threads(cfg.threads)
+            .spectral(cfg.spectral)
+            .color_space(cfg.working_space)
+            .filter(cfg.filter)
+            .sampler(cfg.sampler)
+            .seed(cfg.seed)
+            .packets(!cfg.scalar)
+    }
+
+    /// The number of rays that are randomly sent through each pixel and then averaged together.
+    pub fn samples(mut self, samples: u32) -> Renderer<'a> {
+        self.samples = samples;
+        self
+    }
+
+    pub fn threads(mut self, threads: usize) -> Renderer<'a> {
+        self.threads = threads;
+        self
+    }
+
+    /// Traces rays at randomly sampled wavelengths instead of RGB channels. Material colors are
+    /// converted into spectra and the light is converted back to RGB at the end; this enables
+    /// dispersion and the measured colors of conductors.
+    pub fn spectral(mut self, spectral: bool) -> Renderer<'a> {
+        self.spectral = spectral;
+        self
+    }
+
+    /// The linear color space in which the colors of the scene are given and in which the light
+    /// is computed. Linear sRGB by default.
+    pub fn color_space(mut self, space: ColorSpace) -> Renderer<'a> {
+        self.color_space = space;
+        self
+    }
+
+    /// The filter which reconstructs the pixels out of the samples. A box filter of half a
+    /// pixel (i.e. averaging the samples of each pixel) by default.
+    pub fn filter(mut self, filter: Filter) -> Renderer<'a> {
+        self.filter = filter;
+        self
+    }
+
+    /// The sampler which supplies the random numbers of each sample. Scrambled Sobol by default.
+    pub fn sampler(mut self, sampler: SamplerKind) -> Renderer<'a> {
+        self.sampler = sampler;
+        self
+    }
+
+    /// The seed of the sampler. Renders with the same seed (and settings) are identical, up to
+    /// floating point rounding.
+    pub fn seed(mut self, seed: u64) -> Renderer<'a> {
+        self.seed = seed;
+        self
+    }
+
+    /// Traces the camera rays of neighbouring samples together, in packets (see
+    /// `rtow::packet`), instead of one by one. On by default; the images are the same either way.
+    pub fn packets(mut self, packets: bool) -> Renderer<'a> {
+        self.packets = packets;
+        self
+    }
+
+    /// Restricts the render to a sub-rectangle of the image. The rest of the image is not
+    /// computed, apart from the samples within the radius of the filter around the region, so
+    /// that regions stitched together give the same image as a full render.
+    pub fn region(mut self, region: Region) -> Renderer<'a> {
+        self.region = Some(region);
+        self
+    }
+
+    /// Registers a callback which is invoked after each rendered tile with the number of pixels
+    /// done so far and the total number of pixels.
+    pub fn on_progress<F>(mut self, callback: F) -> Renderer<'a>
+    where
+        F: FnMut(u64, u64) + 'a,
+    {
+        self.progress = Some(Box::new(callback));
+        self
+    }
+
+    /// The rays traced and the intersection tests done by the last call to `render`.
+    pub fn counters(&self) -> Counters {
+        self.counters
+    }
+
+    /// Renders the scene. Fails if the renderer is misconfigured (e.g. zero samples) or if the
+    /// requested region does not fit inside the image.
+    pub fn render(&mut self, scene: &Scene) -> Result<Framebuffer> {
+        if self.samples == 0 {
+            return Err(Error::InvalidConfig(String::from(
+                "at least one sample per pixel is needed",
+            )));
+        }
+        if self.threads == 0 {
+            return Err(Error::InvalidConfig(String::from(
+                "at least one thread is needed",
+            )));
+        }
+        self.filter.validate()?;
+
+        let region = self
+            .region
+            .unwrap_or_else(|| Region::full(&self.resolution));
+        if region.area() == 0 || !region.is_inside(&self.resolution) {
+            return Err(Error::Scene(format!(
+                "region {}x{} at ({}, {}) does not fit inside the {} image",
+                region.width, region.height, region.x, region.y, self.resolution
+            )));
+        }
+
+        // The samples just outside the region contribute to the pixels at its edges.
+        let margin = (self.filter.radius - 0.5).max(0.).ceil() as u32;
+        let sampled = region.expand(margin, &Region::full(&self.resolution));
+        let r = {
+            let (s, r) = chan::r#async();
+            let mut tiles = sampled.tiles(TILE_SIZE);
+
+            // Some areas of the image take more time to render. This makes the progress
+            // advance unevenly. Shuffling the tiles leads to a more even distribution and a more
+            // accurate ETA.
+            tiles.shuffle(&mut thread_rng());
+            tiles.into_iter().for_each(|tile| s.send(tile));
+            r
+        };
+
+        let (ret_s, ret_r) = chan::r#async();
+        let tile_renderer = TileRenderer {
+            scene,
+            resolution: &self.resolution,
+            samples: self.samples,
+            spectral: self.spectral,
+            color_space: self.color_space,
+            filter: self.filter,
+            sampler: self.sampler,
+            seed: self.seed,
+            packets: self.packets,
+            margin,
+            bounds: region,
+        };
+        let progress = &mut self.progress;
+
+        // Samples near the border of a tile also contribute to the pixels of the neighbouring
+        // tiles. Each tile is therefore splatted into its own buffer, which includes a margin
+        // around the tile, and the buffers are summed up by this thread only.
+        let mut splats = Splats::new(region);
+        let mut counters = Counters::default();
+        thread::scope(|scope| {
+            let workers: Vec<_> = (0..self.threads)
+                .map(|_| {
+                    let r = r.clone();
+                    let ret_s = ret_s.clone();
+                    let tile_renderer = &tile_renderer;
+                    scope.spawn(move || {
+                        stats::take();
+                        for tile in r {
+                            ret_s.send((tile, tile_renderer.render(&tile)));
+                        }
+                        stats::take()
+                    })
+                })
+                .collect();
+            drop(ret_s);
+
+            let total = sampled.area();
+            let mut done = 0;
+            for (tile, tile_splats) in ret_r.iter() {
+                splats.merge(&tile_splats);
+                done += tile.area();
+                if let Some(callback) = progress {
+                    callback(done, total);
+                }
+            }
+
+            // A panic of a worker leaves its tiles unrendered, so it is passed on.
+            for worker in workers {
+                counters += worker
+                    .join()
+                    .unwrap_or_else(|panic| std::panic::resume_unwind(panic));
+            }
+        });
+        self.counters = counters;
+
+        Ok(splats.resolve(self.color_space))
+    }
+}
+
+/// Side of the square tiles into which the image is split, in pixels.
+const TILE_SIZE: u32 = 16;
+
+/// Everything the worker threads need in order to render a tile.
+struct TileRenderer<'s> {
+    scene: &'s Scene,
+    resolution: &'s Resolution,
+    samples: u32,
+    spectral: bool,
+    color_space: ColorSpace,
+    filter: Filter,
+    sampler: SamplerKind,
+    seed: u64,
+    packets: bool,
+    /// How far (in pixels) the samples of a pixel reach into its neighbours.
+    margin: u32,
+    /// The rendered region; the splats onto the pixels outside of it are discarded.
+    bounds: Region,
+}
+
+impl<'s> TileRenderer<'s> {
+    /// Sends `samples` random rays through each pixel of the tile and splats their colors onto
+    /// the nearby pixels.
+    fn render(&self, tile: &Region) -> Splats {
+        let mut splats = Splats::new(tile.expand(self.margin, &self.bounds));
+        if self.packets {
+            self.render_packets(tile, &mut splats);
+            return splats;
+        }
+
+        let mut sampler = self.sampler.create(self.samples, self.seed);
+        for y in tile.y..tile.y + tile.height {
+            for x in tile.x..tile.x + tile.width {
+                for index in 0..self.samples {
+                    sampler.start_pixel_sample(x, y, index);
+                    let offset = sampler.get_2d();
+                    let (film_x, film_y) = (x as Float + offset.x, y as Float + offset.y);
+                    let (mut wavelengths, ray) = self.camera_ray(film_x, film_y, sampler.as_mut());
+                    let color = match ray {
+                        Some(ray) => {
+                            let light =
+                                ray.color(self.scene, 0, &mut wavelengths, sampler.as_mut());
+                            wavelengths.to_rgb(&light)
+                        }
+                        None => Vector3::zeros(),
+                    };
+                    splats.add(film_x, film_y, &color, &self.filter);
+                }
+            }
+        }
+        splats
+    }
+
+    /// Like `render`, with the camera rays of consecutive samples (those of a pixel, then those
+    /// of the next one) traced in packets. Each lane of the packet has its own sampler, so that
+    /// every path gets the same random numbers as in `render`.
+    fn render_packets(&self, tile: &Region, splats: &mut Splats) {
+        let mut samplers: [Box<dyn Sampler>; WIDTH] =
+            std::array::from_fn(|_| self.sampler.create(self.samples, self.seed));
+        let samples = (tile.y..tile.y + tile.height).flat_map(|y| {
+            (tile.x..tile.x + tile.width)
+                .flat_map(move |x| (0..self.samples).map(move |index| (x, y, index)))
+        });
+
+        let mut samples = samples.peekable();
+        while samples.peek().is_some() {
+            let mut film = [None; WIDTH];
+            let mut wavelengths = [Wavelengths::Rgb {
+                space: self.color_space,
+            }; WIDTH];
+            let mut rays: [Option<Ray>; WIDTH] = Default::default();
+            for (lane, (x, y, index)) in samples.by_ref().take(WIDTH).enumerate() {
+                let sampler = samplers[lane].as_mut();
+                sampler.start_pixel_sample(x, y, index);
+                let offset = sampler.get_2d();
+                let (film_x, film_y) = (x as Float + offset.x, y as Float + offset.y);
+                (wavelengths[lane], rays[lane]) = self.camera_ray(film_x, film_y, sampler);
+                film[lane] = Some((film_x, film_y));
+            }
+
+            let packet = RayPacket::new(&rays);
+            let hits = self
+                .scene
+                .hit_packet(&packet, 0., &[Float::INFINITY; WIDTH]);
+            for lane in 0..WIDTH {
+                let (film_x, film_y) = match film[lane] {
+                    Some(film) => film,
+                    None => break,
+                };
+                let color = match &rays[lane] {
+                    Some(ray) => {
+                        let wavelengths = &mut wavelengths[lane];
+                        let light = ray.color_with_hit(
+                            hits[lane],
+                            self.scene,
+                            0,
+                            wavelengths,
+                            samplers[lane].as_mut(),
+                        );
+                        wavelengths.to_rgb(&light)
+                    }
+                    None => Vector3::zeros(),
+                };
+                splats.add(film_x, film_y, &color, &self.filter);
+            }
+        }
+    }
+
+    /// The wavelengths and the camera ray of a sample through the point `(film_x, film_y)` of
+    /// the image, measured in pixels from its upper left corner.
+    fn camera_ray(
+        &self,
+        film_x: Float,
+        film_y: Float,
+        sampler: &mut dyn Sampler,
+    ) -> (Wavelengths, Option<Ray>) {
+        let u = film_x / self.resolution.width as Float;
+        // The vertical axis of the sensor points upwards, while the rows of the image go
+        // downwards.
+        let v = (self.resolution.height as Float - film_y) / self.resolution.height as Float;
+
+        let wavelengths = if self.spectral {
+            Wavelengths::sample(sampler.get_1d(), self.color_space)
+        } else {
+            Wavelengths::Rgb {
+                space: self.color_space,
+            }
+        };
+        (wavelengths, self.scene.camera().get_ray(u, v, sampler))
+    }
+}
+
+/// The filtered sum of the samples splatted onto each pixel of a region, along with the sum of
+/// their weights.
+struct Splats {
+    region: Region,
+    colors: Vec<Vector3<Float>>,
+    weights: Vec<Float>,
+}
+
+impl Splats {
+    fn new(region: Region) -> Splats {
+        Splats {
+            region,
+            colors: vec![Vector3::zeros(); region.area() as usize],
+            weights: vec![0.; region.area() as usize],
+        }
+    }
+
+    fn index(&self, x: u32, y: u32) -> usize {
+        ((y - self.region.y) * self.region.width + x - self.region.x) as usize
+    }
+
+    /// Adds a sample at `(film_x, film_y)` to every pixel of the region whose center is within
+    /// the radius of the filter.
+    fn add(&mut self, film_x: Float, film_y: Float, color: &Vector3<Float>, filter: &Filter) {
+        let (left, top) = (self.region.x as Float, self.region.y as Float);
+        let (right, bottom) = (
+            (self.region.x + self.region.width) as Float,
+            (self.region.y + self.region.height) as Float,
+        );
+        let first_x = (film_x - 0.5 - filter.radius).ceil().max(left) as u32;
+        let last_x = (film_x - 0.5 + filter.radius).floor().min(right - 1.);
+        let first_y = (film_y - 0.5 - filter.radius).ceil().max(top) as u32;
+        let last_y = (film_y - 0.5 + filter.radius).floor().min(bottom - 1.);
+        if last_x < first_x as Float || last_y < first_y as Float {
+            return;
+        }
+
+        for y in first_y..=last_y as u32 {
+            for x in first_x..=last_x as u32 {
+                let weight = filter.weight(x as Float + 0.5 - film_x, y as Float + 0.5 - film_y);
+                if weight != 0. {
+                    let index = self.index(x, y);
+                    self.colors[index] += weight * color;
+                    self.weights[index] += weight;
+                }
+            }
+        }
+    }
+
+    /// Adds the splats of a region which lies inside this one.
+    fn merge(&mut self, other: &Splats) {
+        for y in other.region.y..other.region.y + other.region.height {
+            for x in other.region.x..other.region.x + other.region.width {
+                let (index, other_index) = (self.index(x, y), other.index(x, y));
+                self.colors[index] += other.colors[other_index];
+                self.weights[index] += other.weights[other_index];
+            }
+        }
+    }
+
+    /// Normalizes the sum of each pixel by the sum of its weights.
+    fn resolve(self, space: ColorSpace) -> Framebuffer {
+        let pixels = self
+            .colors
+            .iter()
+            .zip(&self.weights)
+            .map(|(color, &weight)| {
+                if weight != 0. {
+                    color / weight
+                } else {
+                    Vector3::zeros()
+                }
+            })
+            .collect();
+
+        Framebuffer {
+            region: self.region,
+            space,
+            pixels,
+        }
+    }
+}
@@ -0,0 +1,518 @@
+use crate::error::{Error, Result};
+use crate::float::Float;
+use nalgebra::Vector2;
+use serde::Deserialize;
+use std::str::FromStr;
+use std::sync::OnceLock;
+
+/// Supplies the random numbers used while tracing a sample: the position inside the pixel, the
+/// wavelengths, the point of the lens and the directions of the bounces. Each call to `get_1d` or
+/// `get_2d` consumes the next dimension(s) of the current sample; samplers which are better than
+/// independent random numbers spread the values of each dimension evenly over the samples of a
+/// pixel.
+///
+/// All the samplers are deterministic: the same pixel, sample index and seed always produce the
+/// same values.
+pub trait Sampler {
+    /// Moves to the sample `index` of the pixel `(x, y)`, starting again from the first
+    /// dimension.
+    fn start_pixel_sample(&mut self, x: u32, y: u32, index: u32);
+
+    /// A value in `[0, 1)`.
+    fn get_1d(&mut self) -> Float;
+
+    /// A point in `[0, 1)²`.
+    fn get_2d(&mut self) -> Vector2<Float>;
+}
+
+/// The names of the available samplers.
+///
+/// * `Independent`: uncorrelated random numbers.
+/// * `Stratified`: each dimension is split into one stratum per sample (a grid, for pairs of
+///   dimensions) and each sample falls into a different, randomly chosen, stratum.
+/// * `Halton`: the Halton sequence, Owen scrambled separately for each pixel.
+/// * `Sobol`: the Sobol sequence in pairs of dimensions, whose samples are shuffled and Owen
+///   scrambled separately for each pixel and pair of dimensions.
+/// * `BlueNoise`: the same Owen scrambled Sobol sequence for every pixel, shifted by a blue noise
+///   mask. The error is the same as with `Sobol`, but it is distributed as high frequency noise,
+///   which is less visible.
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum SamplerKind {
+    Independent,
+    Stratified,
+    Halton,
+    #[default]
+    Sobol,
+    BlueNoise,
+}
+
+impl SamplerKind {
+    /// Creates a sampler which is used for `samples_per_pixel` samples in each pixel.
+    pub fn create(&self, samples_per_pixel: u32, seed: u64) -> Box<dyn Sampler> {
+        let state = SampleState::new(seed);
+        match self {
+            SamplerKind::Independent => Box::new(IndependentSampler { state }),
+            SamplerKind::Stratified => Box::new(StratifiedSampler {
+                state,
+                samples_per_pixel: samples_per_pixel.max(1),
+            }),
+            SamplerKind::Halton => Box::new(HaltonSampler { state }),
+            SamplerKind::Sobol => Box::new(SobolSampler { state }),
+            SamplerKind::BlueNoise => Box::new(BlueNoiseSampler { state }),
+        }
+    }
+}
+
+impl FromStr for SamplerKind {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s {
+            "independent" => Ok(SamplerKind::Independent),
+            "stratified" => Ok(SamplerKind::Stratified),
+            "halton" => Ok(SamplerKind::Halton),
+            "sobol" => Ok(SamplerKind::Sobol),
+            "blue-noise" => Ok(SamplerKind::BlueNoise),
+            _ => Err(Error::InvalidConfig(format!(
+                "`{}` is not a sampler; expected independent, stratified, halton, sobol or \
+                 blue-noise",
+                s
+            ))),
+        }
+    }
+}
+
+/// The current pixel, sample and dimension.
+struct SampleState {
+    seed: u64,
+    x: u32,
+    y: u32,
+    index: u32,
+    dimension: u32,
+}
+
+impl SampleState {
+    fn new(seed: u64) -> SampleState {
+        SampleState {
+            seed,
+            x: 0,
+            y: 0,
+            index: 0,
+            dimension: 0,
+        }
+    }
+
+    fn start(&mut self, x: u32, y: u32, index: u32) {
+        self.x = x;
+        self.y = y;
+        self.index = index;
+        self.dimension = 0;
+    }
+
+    /// Returns the current dimension and moves on to the next `count` dimensions.
+    fn next_dimensions(&mut self, count: u32) -> u32 {
+        let dimension = self.dimension;
+        self.dimension += count;
+        dimension
+    }
+
+    /// A hash of the pixel, the dimension and `extra`, which is the same for all the samples of
+    /// the pixel.
+    fn pixel_hash(&self, dimension: u32, extra: u64) -> u64 {
+        hash(&[
+            self.seed,
+            self.x as u64,
+            self.y as u64,
+            dimension as u64,
+            extra,
+        ])
+    }
+
+    /// An independent random value for the current sample.
+    fn independent(&self, dimension: u32) -> Float {
+        let hash = self.pixel_hash(dimension, self.index as u64 + 1);
+        ((hash >> 11) as Float / (1u64 << 53) as Float).min(ONE_MINUS_EPSILON)
+    }
+}
+
+struct IndependentSampler {
+    state: SampleState,
+}
+
+impl Sampler for IndependentSampler {
+    fn start_pixel_sample(&mut self, x: u32, y: u32, index: u32) {
+        self.state.start(x, y, index);
+    }
+
+    fn get_1d(&mut self) -> Float {
+        let dimension = self.state.next_dimensions(1);
+        self.state.independent(dimension)
+    }
+
+    fn get_2d(&mut self) -> Vector2<Float> {
+        Vector2::new(self.get_1d(), self.get_1d())
+    }
+}
+
+struct StratifiedSampler {
+    state: SampleState,
+    samples_per_pixel: u32,
+}
+
+impl Sampler for StratifiedSampler {
+    fn start_pixel_sample(&mut self, x: u32, y: u32, index: u32) {
+        self.state.start(x, y, index);
+    }
+
+    fn get_1d(&mut self) -> Float {
+        let dimension = self.state.next_dimensions(1);
+        let n = self.samples_per_pixel;
+        let stratum = permutation_element(
+            self.state.index % n,
+            n,
+            self.state.pixel_hash(dimension, 0) as u32,
+        );
+        (stratum as Float + self.state.independent(dimension)) / n as Float
+    }
+
+    fn get_2d(&mut self) -> Vector2<Float> {
+        let dimension = self.state.next_dimensions(2);
+        // The grid has (at least) one cell per sample; the samples fall into distinct cells.
+        let columns = (self.samples_per_pixel as Float).sqrt().floor().max(1.) as u32;
+        let rows = self.samples_per_pixel.div_ceil(columns);
+        let cells = columns * rows;
+        let cell = permutation_element(
+            self.state.index % cells,
+            cells,
+            self.state.pixel_hash(dimension, 0) as u32,
+        );
+
+        Vector2::new(
+            ((cell % columns) as Float + self.state.independent(dimension)) / columns as Float,
+            ((cell / columns) as Float + self.state.independent(dimension + 1)) / rows as Float,
+        )
+    }
+}
+
+/// The first primes, used as the bases of the dimensions of the Halton sequence. Dimensions past
+/// these fall back to independent random numbers.
+const PRIMES: [u64; 64] = [
+    2, 3, 5, 7, 11, 13, 17, 19, 23, 29, 31, 37, 41, 43, 47, 53, 59, 61, 67, 71, 73, 79, 83, 89, 97,
+    101, 103, 107, 109, 113, 127, 131, 137, 139, 149, 151, 157, 163, 167, 173, 179, 181, 191, 193,
+    197, 199, 211, 223, 227, 229, 233, 239, 241, 251, 257, 263, 269, 271, 277, 281, 283, 293, 307,
+    311,
+];
+
+struct HaltonSampler {
+    state: SampleState,
+}
+
+impl Sampler for HaltonSampler {
+    fn start_pixel_sample(&mut self, x: u32, y: u32, index: u32) {
+        self.state.start(x, y, index);
+    }
+
+    fn get_1d(&mut self) -> Float {
+        let dimension = self.state.next_dimensions(1);
+        match PRIMES.get(dimension as usize) {
+            Some(&base) => owen_scrambled_radical_inverse(
+                base,
+                self.state.index as u64,
+                self.state.pixel_hash(dimension, 0),
+            ),
+            None => self.state.independent(dimension),
+        }
+    }
+
+    fn get_2d(&mut self) -> Vector2<Float> {
+        Vector2::new(self.get_1d(), self.get_1d())
+    }
+}
+
+/// The digits of `a` in the given base, mirrored around the radix point, with every digit
+/// permuted depending on the digits that precede it (Owen scrambling).
+fn owen_scrambled_radical_inverse(base: u64, mut a: u64, hash: u64) -> Float {
+    let inverse_base = 1. / base as Float;
+    let mut reversed_digits: u64 = 0;
+    let mut inverse_base_m = 1.;
+
+    // Digits past 2^-40 do not make a difference anymore.
+    while inverse_base_m > 1e-12 {
+        let next = a / base;
+        let digit = a - next * base;
+        let digit_hash = mix_bits(hash ^ reversed_digits) as u32;
+        let digit = permutation_element(digit as u32, base as u32, digit_hash) as u64;
+
+        reversed_digits = reversed_digits * base + digit;
+        inverse_base_m *= inverse_base;
+        a = next;
+    }
+
+    (inverse_base_m * reversed_digits as Float).min(ONE_MINUS_EPSILON)
+}
+
+struct SobolSampler {
+    state: SampleState,
+}
+
+impl Sampler for SobolSampler {
+    fn start_pixel_sample(&mut self, x: u32, y: u32, index: u32) {
+        self.state.start(x, y, index);
+    }
+
+    fn get_1d(&mut self) -> Float {
+        let dimension = self.state.next_dimensions(1);
+        let hash = |extra| self.state.pixel_hash(dimension, extra) as u32;
+        let index = nested_uniform_scramble(self.state.index, hash(0));
+        to_unit_float(nested_uniform_scramble(index.reverse_bits(), hash(1)))
+    }
+
+    fn get_2d(&mut self) -> Vector2<Float> {
+        let dimension = self.state.next_dimensions(2);
+        let hash = |extra| self.state.pixel_hash(dimension, extra) as u32;
+        let index = nested_uniform_scramble(self.state.index, hash(0));
+        Vector2::new(
+            to_unit_float(nested_uniform_scramble(index.reverse_bits(), hash(1))),
+            to_unit_float(nested_uniform_scramble(
+                sobol_second_dimension(index),
+                hash(2),
+            )),
+        )
+    }
+}
+
+struct BlueNoiseSampler {
+    state: SampleState,
+}
+
+impl BlueNoiseSampler {
+    /// Shifts `value` by the blue noise mask, which is offset by a different amount for each
+    /// dimension (and component).
+    fn shift(&self, value: Float, dimension: u32, component: u64) -> Float {
+        let offset = hash(&[self.state.seed, dimension as u64, component]);
+        let x = (self.state.x as u64 + (offset & 0xffff)) % BLUE_NOISE_SIZE as u64;
+        let y = (self.state.y as u64 + (offset >> 16 & 0xffff)) % BLUE_NOISE_SIZE as u64;
+        let noise = blue_noise_mask()[(y * BLUE_NOISE_SIZE as u64 + x) as usize];
+        (value + noise).fract()
+    }
+
+    /// A hash which is shared by all the pixels, so that they use the same sequence.
+    fn hash(&self, dimension: u32, extra: u64) -> u32 {
+        hash(&[self.state.seed, dimension as u64, extra]) as u32
+    }
+}
+
+impl Sampler for BlueNoiseSampler {
+    fn start_pixel_sample(&mut self, x: u32, y: u32, index: u32) {
+        self.state.start(x, y, index);
+    }
+
+    fn get_1d(&mut self) -> Float {
+        let dimension = self.state.next_dimensions(1);
+        let index = nested_uniform_scramble(self.state.index, self.hash(dimension, 0));
+        let value = to_unit_float(nested_uniform_scramble(
+            index.reverse_bits(),
+            self.hash(dimension, 1),
+        ));
+        self.shift(value, dimension, 0)
+    }
+
+    fn get_2d(&mut self) -> Vector2<Float> {
+        let dimension = self.state.next_dimensions(2);
+        let index = nested_uniform_scramble(self.state.index, self.hash(dimension, 0));
+        let x = to_unit_float(nested_uniform_scramble(
+            index.reverse_bits(),
+            self.hash(dimension, 1),
+        ));
+        let y = to_unit_float(nested_uniform_scramble(
+            sobol_second_dimension(index),
+            self.hash(dimension, 2),
+        ));
+        Vector2::new(self.shift(x, dimension, 0), self.shift(y, dimension, 1))
+    }
+}
+
+/// The second dimension of the Sobol sequence, whose generator matrix is the Pascal triangle
+/// modulo 2. (The first dimension is the bit reversal of the index.)
+fn sobol_second_dimension(mut index: u32) -> u32 {
+    let mut direction = 1u32 << 31;
+    let mut result = 0;
+    while index != 0 {
+        if index & 1 == 1 {
+            result ^= direction;
+        }
+        index >>= 1;
+        direction ^= direction >> 1;
+    }
+    result
+}
+
+/// Owen scrambling of the bits of `x` (most significant bit first), using the hash based
+/// permutation of Laine and Karras, as described by Burley, "Practical Hash-based Owen
+/// Scrambling" (2020).
+fn nested_uniform_scramble(x: u32, seed: u32) -> u32 {
+    let mut x = x.reverse_bits().wrapping_add(seed);
+    x ^= x.wrapping_mul(0x6c50b47c);
+    x ^= x.wrapping_mul(0xb82f1e52);
+    x ^= x.wrapping_mul(0xc7afe638);
+    x ^= x.wrapping_mul(0x8d22f6e6);
+    x.reverse_bits()
+}
+
+/// The largest `Float` below 1.
+const ONE_MINUS_EPSILON: Float = 1. - Float::EPSILON / 2.;
+
+/// Maps `x` onto `[0, 1)`; with `f32`, large values would otherwise round up to 1.
+fn to_unit_float(x: u32) -> Float {
+    (x as Float / (1u64 << 32) as Float).min(ONE_MINUS_EPSILON)
+}
+
+/// The element at position `i` of a random permutation of `0..length`, chosen by `seed`
+/// (Kensler, "Correlated Multi-Jittered Sampling", 2013).
+fn permutation_element(mut i: u32, length: u32, seed: u32) -> u32 {
+    let mut w = length - 1;
+    w |= w >> 1;
+    w |= w >> 2;
+    w |= w >> 4;
+    w |= w >> 8;
+    w |= w >> 16;
+
+    loop {
+        i ^= seed;
+        i = i.wrapping_mul(0xe170893d);
+        i ^= seed >> 16;
+        i ^= (i & w) >> 4;
+        i ^= seed >> 8;
+        i = i.wrapping_mul(0x0929eb3f);
+        i ^= seed >> 23;
+        i ^= (i & w) >> 1;
+        i = i.wrapping_mul(1 | seed >> 27);
+        i = i.wrapping_mul(0x6935fa69);
+        i ^= (i & w) >> 11;
+        i = i.wrapping_mul(0x74dcb303);
+        i ^= (i & w) >> 2;
+        i = i.wrapping_mul(0x9e501cc3);
+        i ^= (i & w) >> 2;
+        i = i.wrapping_mul(0xc860a3df);
+        i &= w;
+        i ^= i >> 5;
+        if i < length {
+            break;
+        }
+    }
+    (i.wrapping_add(seed)) % length
+}
+
+/// The finalizer of MurmurHash3, which mixes the bits of `v` thoroughly.
+fn mix_bits(mut v: u64) -> u64 {
+    v ^= v >> 31;
+    v = v.wrapping_mul(0x7fb5d329728ea185);
+    v ^= v >> 27;
+    v = v.wrapping_mul(0x81dadef4bc2dd44d);
+    v ^= v >> 33;
+    v
+}
+
+/// A value in `[0, 1)` which looks random, but is a deterministic function of the `values`.
+pub fn hash_float(values: &[u64]) -> Float {
+    to_unit_float((hash(values) >> 32) as u32)
+}
+
+fn hash(values: &[u64]) -> u64 {
+    values
+        .iter()
+        .fold(0x9e3779b97f4a7c15, |h, &v| mix_bits(h ^ mix_bits(v)))
+}
+
+/// Side of the (tileable) blue noise mask, in pixels.
+const BLUE_NOISE_SIZE: usize = 64;
+
+/// A blue noise mask: values in `[0, 1)` in which neighbouring pixels differ as much as possible.
+/// It is generated once, with the void-and-cluster method of Ulichney (1993).
+fn blue_noise_mask() -> &'static [Float] {
+    static MASK: OnceLock<Vec<Float>> = OnceLock::new();
+    MASK.get_or_init(|| {
+        const N: usize = BLUE_NOISE_SIZE * BLUE_NOISE_SIZE;
+        let sigma: f64 = 1.5;
+
+        // The energies are added up and taken away again many times, and always in `f64`: with
+        // `f32`, the rounding errors pile up until the relaxation below never settles.
+        // The energy that a point contributes to the points at each (toroidal) offset.
+        let kernel: Vec<f64> = (0..N)
+            .map(|i| {
+                let distance = |d: usize| d.min(BLUE_NOISE_SIZE - d) as f64;
+                let (dx, dy) = (distance(i % BLUE_NOISE_SIZE), distance(i / BLUE_NOISE_SIZE));
+                (-(dx * dx + dy * dy) / (2. * sigma * sigma)).exp()
+            })
+            .collect();
+        let offset = |from: usize, to: usize| {
+            let dx =
+                (to % BLUE_NOISE_SIZE + BLUE_NOISE_SIZE - from % BLUE_NOISE_SIZE) % BLUE_NOISE_SIZE;
+            let dy =
+                (to / BLUE_NOISE_SIZE + BLUE_NOISE_SIZE - from / BLUE_NOISE_SIZE) % BLUE_NOISE_SIZE;
+            dy * BLUE_NOISE_SIZE + dx
+        };
+        let toggle = |pattern: &mut Vec<bool>, energy: &mut Vec<f64>, point: usize| {
+            pattern[point] = !pattern[point];
+            let sign = if pattern[point] { 1. } else { -1. };
+            for (other, e) in energy.iter_mut().enumerate() {
+                *e += sign * kernel[offset(point, other)];
+            }
+        };
+        // The densest point of the pattern, or the emptiest point outside of it.
+        let extreme = |pattern: &[bool], energy: &[f64], ones: bool| {
+            let candidates = (0..N).filter(|&i| pattern[i] == ones);
+            if ones {
+                candidates.max_by(|&a, &b| energy[a].total_cmp(&energy[b]))
+            } else {
+                candidates.min_by(|&a, &b| energy[a].total_cmp(&energy[b]))
+            }
+            .unwrap()
+        };
+
+        // An initial random pattern with a tenth of the points, relaxed by moving its densest
+        // points into its emptiest areas until it is evenly spread.
+        let mut pattern = vec![false; N];
+        let mut energy = vec![0_f64; N];
+        let mut ones = 0;
+        for i in 0.. {
+            if ones == N / 10 {
+                break;
+            }
+            let point = (hash(&[i]) % N as u64) as usize;
+            if !pattern[point] {
+                toggle(&mut pattern, &mut energy, point);
+                ones += 1;
+            }
+        }
+        loop {
+            let cluster = extreme(&pattern, &energy, true);
+            toggle(&mut pattern, &mut energy, cluster);
+            let void = extreme(&pattern, &energy, false);
+            toggle(&mut pattern, &mut energy, void);
+            if void == cluster {
+                break;
+            }
+        }
+
+        // The points of the pattern are ranked by removing the densest ones first; the other
+        // points are ranked by filling in the emptiest areas first.
+        let mut rank = vec![0; N];
+        let (mut removed, mut removed_energy) = (pattern.clone(), energy.clone());
+        for r in (0..ones).rev() {
+            let cluster = extreme(&removed, &removed_energy, true);
+            toggle(&mut removed, &mut removed_energy, cluster);
+            rank[cluster] = r;
+        }
+        for r in ones..N {
+            let void = extreme(&pattern, &energy, false);
+            toggle(&mut pattern, &mut energy, void);
+            rank[void] = r;
+        }
+
+        rank.into_iter()
+            .map(|r| (r as Float + 0.5) / N as Float)
+            .collect()
+    })
+}
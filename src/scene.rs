@@ -0,0 +1,561 @@
+use crate::animation::{Animation, Transform};
+use crate::bvh::{Aabb, Bounded, Bvh};
+use crate::camera::{CameraModel, CameraSettings};
+use crate::error::{Error, Result};
+use crate::float::{self, Float};
+use crate::hitable::{HitPoint, Hitable};
+use crate::material::Material;
+use crate::material::Material::{Lambertian, Metal};
+use crate::mesh::{Geometry, Mesh};
+use crate::packet::{Lanes, RayPacket, WIDTH};
+use crate::ray::Ray;
+use crate::sphere::Sphere;
+use crate::texture::Texture;
+use crate::validate::Object;
+use nalgebra::{Point3, UnitQuaternion, Vector3};
+use rand::rngs::StdRng;
+use rand::{Rng, SeedableRng};
+use serde::Deserialize;
+use std::collections::HashMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, OnceLock};
+
+/// The radius of the ground of the random scene: the Earth's.
+#[cfg(not(feature = "f32"))]
+const GROUND_RADIUS: Float = 6.371e6;
+/// Single precision cannot tell the points near the surface of an Earth-sized sphere apart
+/// (they are half a unit apart), so the ground is the smaller one of the original book.
+#[cfg(feature = "f32")]
+const GROUND_RADIUS: Float = 1e3;
+
+/// Everything that is needed in order to render an image: the objects of the world and the camera
+/// through which they are observed.
+///
+/// Scenes are put together with a builder-like API:
+///
+/// ```
+/// # use rtow::camera::{Camera, Lens, Orientation};
+/// # use rtow::material::Material;
+/// # use rtow::scene::Scene;
+/// # use rtow::sphere::Sphere;
+/// # use nalgebra::{Point3, Vector3};
+/// let orientation = Orientation {
+///     look_from: Point3::new(0., 0., 0.),
+///     look_at: Point3::new(0., 0., -1.),
+///     upwards: Vector3::new(0., 1., 0.),
+/// };
+/// let lens = Lens {
+///     aperture: 0.,
+///     diaphragm: Default::default(),
+///     focal_length: 1.,
+///     vertical_fov: 60.,
+///     aspect_ratio: 1.,
+/// };
+/// let scene = Scene::new(Camera::new(orientation, lens)).with_object(Sphere::new(
+///     Point3::new(0., 0., -1.),
+///     0.5,
+///     Material::Lambertian {
+///         attenuation: Vector3::new(0.5, 0.5, 0.5),
+///     },
+/// ));
+/// ```
+#[derive(Clone)]
+pub struct Scene {
+    objects: Vec<Sphere>,
+    meshes: Vec<Mesh>,
+    camera: Arc<dyn CameraModel>,
+    /// The hierarchy of the bounding boxes of the spheres and of the meshes (in this order),
+    /// built by the first ray which is traced through the scene.
+    bvh: OnceLock<Bvh>,
+}
+
+impl Scene {
+    /// Creates an empty scene, observed through the given camera.
+    pub fn new<C: CameraModel + 'static>(camera: C) -> Scene {
+        Scene {
+            objects: vec![],
+            meshes: vec![],
+            camera: Arc::new(camera),
+            bvh: OnceLock::new(),
+        }
+    }
+
+    /// Adds an object to the scene and returns the scene, for chaining.
+    pub fn with_object(mut self, object: Sphere) -> Scene {
+        self.add(object);
+        self
+    }
+
+    /// Adds an object to the scene.
+    pub fn add(&mut self, object: Sphere) {
+        self.objects.push(object);
+        self.bvh = OnceLock::new();
+    }
+
+    /// Adds a triangle mesh to the scene.
+    pub fn add_mesh(&mut self, mesh: Mesh) {
+        self.meshes.push(mesh);
+        self.bvh = OnceLock::new();
+    }
+
+    pub fn objects(&self) -> &Vec<Sphere> {
+        &self.objects
+    }
+
+    pub fn meshes(&self) -> &Vec<Mesh> {
+        &self.meshes
+    }
+
+    pub fn camera(&self) -> &dyn CameraModel {
+        self.camera.as_ref()
+    }
+
+    /// Builds the bounding volume hierarchy of the scene, which is otherwise built when the
+    /// first ray is traced (e.g. in order to time the two separately).
+    pub fn prepare(&self) {
+        self.bvh();
+    }
+
+    fn bvh(&self) -> &Bvh {
+        self.bvh.get_or_init(|| {
+            let bounds: Vec<Aabb> = self
+                .objects
+                .iter()
+                .map(Bounded::bounds)
+                .chain(self.meshes.iter().map(Bounded::bounds))
+                .collect();
+            Bvh::build(&bounds)
+        })
+    }
+
+    /// Generates the hardcoded demo world: a ground sphere the size of the earth (smaller in
+    /// single precision), three large spheres (glass, matte and metal) and `object_count` small
+    /// spheres of random materials that do not intersect each other. The same `seed` always gives
+    /// the same world. The world is observed through a camera built out of the given
+    /// `CameraSettings`.
+    pub fn random(
+        object_count: u32,
+        seed: u64,
+        camera: &CameraSettings,
+        aspect_ratio: Float,
+    ) -> Result<Scene> {
+        let objects = random_world(object_count, &mut StdRng::seed_from_u64(seed));
+        let camera = camera.build(&objects, aspect_ratio)?;
+        Ok(Scene {
+            objects,
+            meshes: vec![],
+            camera,
+            bvh: OnceLock::new(),
+        })
+    }
+}
+
+impl Hitable for Scene {
+    /// Returns the closest hitpoint among the spheres and the meshes, which are looked up
+    /// through a bounding volume hierarchy.
+    fn hit(&self, ray: &Ray, t_min: Float, t_max: Float) -> Option<HitPoint<'_>> {
+        let mut closest = None;
+        self.bvh().traverse(ray, t_min, t_max, |index, t_max| {
+            let hitpoint = match self.objects.get(index) {
+                Some(sphere) => sphere.hit(ray, t_min, t_max),
+                None => self.meshes[index - self.objects.len()].hit(ray, t_min, t_max),
+            }?;
+            let t = hitpoint.t;
+            closest = Some(hitpoint);
+            Some(t)
+        });
+        closest
+    }
+
+    fn hit_packet(
+        &self,
+        packet: &RayPacket,
+        t_min: Float,
+        t_max: &Lanes<WIDTH>,
+    ) -> [Option<HitPoint<'_>>; WIDTH] {
+        let mut closest = [None; WIDTH];
+        self.bvh()
+            .traverse_packet(packet, t_min, t_max, |index, packet, t_max| {
+                let hitpoints = match self.objects.get(index) {
+                    Some(sphere) => sphere.hit_packet(packet, t_min, t_max),
+                    None => {
+                        self.meshes[index - self.objects.len()].hit_packet(packet, t_min, t_max)
+                    }
+                };
+                std::array::from_fn(|lane| {
+                    let hitpoint = hitpoints[lane]?;
+                    closest[lane] = Some(hitpoint);
+                    Some(hitpoint.t)
+                })
+            });
+        closest
+    }
+}
+
+fn random_world<R: Rng>(object_count: u32, rng: &mut R) -> Vec<Sphere> {
+    let ground = Sphere::new(
+        Point3::new(0., -GROUND_RADIUS, 0.),
+        GROUND_RADIUS,
+        Lambertian {
+            attenuation: Vector3::new(0.5, 0.5, 0.5),
+        },
+    );
+
+    let metal_sphere = Sphere::new(
+        Point3::new(0., 1., -4.),
+        1.,
+        Metal {
+            attenuation: Vector3::new(0.7, 0.6, 0.5),
+            fuzziness: 0.,
+            conductor: None,
+        },
+    );
+    let glass_sphere = Sphere::new(
+        Point3::new(0., 1., -8.),
+        1.,
+        Material::random_dielectric(rng),
+    );
+    let matte_sphere = Sphere::new(
+        Point3::new(0., 1., -12.),
+        1.,
+        Material::random_lambertian(rng),
+    );
+
+    let mut world = vec![ground.clone(), glass_sphere, matte_sphere, metal_sphere];
+
+    for _ in 0..object_count {
+        loop {
+            let x = float::random(rng, -30.0..30.0);
+            let z = float::random(rng, -60.0..5.0);
+            let radius = float::random(rng, 0.1..0.5);
+
+            // Account for the curvature of the earth.
+            let sea_level =
+                (ground.radius().powf(2.) - (x - ground.x()).powf(2.) - (z - ground.z()).powf(2.))
+                    .sqrt()
+                    + ground.y();
+            debug!("sea level: {}", sea_level);
+
+            // Rest the sphere on the ground along its normal, since straight above the ground
+            // it would sink into the curved surface.
+            let on_ground = Point3::new(x, sea_level, z);
+            let center = ground.center()
+                + (on_ground - ground.center()) * ((ground.radius() + radius) / ground.radius());
+            let sphere = Sphere::new(center, radius, Material::random_material(rng));
+
+            // The spheres rest on the ground, so only the other spheres can be in the way.
+            if world[1..]
+                .iter()
+                .any(|existing| existing.intersects(&sphere))
+            {
+                continue;
+            } else {
+                world.push(sphere);
+                break;
+            }
+        }
+    }
+
+    for sphere in &world {
+        info!("Sphere: {}", sphere.center());
+    }
+
+    world
+}
+
+/// The contents of a TOML scene file: the objects, the default camera, any number of named
+/// cameras ("bookmarks") and, optionally, their animation (see `Animation`). Spheres and meshes
+/// can be given a `name`, which the animation uses for referring to them. Triangle meshes are
+/// loaded from OBJ files (see `MeshEntry`).
+///
+/// ```toml
+/// [camera]
+/// look_from = [-2, 1.7, 0]
+/// look_at = [0, 1, -4]
+///
+/// [cameras.top]
+/// look_from = [0, 10, -4]
+/// up = [0, 0, -1]
+///
+/// [[spheres]]
+/// name = "ball"
+/// center = [0, 1, -4]
+/// radius = 1
+/// material = { type = "metal", attenuation = [0.7, 0.6, 0.5], fuzziness = 0 }
+///
+/// [[meshes]]
+/// path = "teapot.obj"
+/// translation = [2, 0, -4]
+/// material = { type = "lambertian", attenuation = [0.8, 0.8, 0.8] }
+/// ```
+#[derive(Clone, Debug, Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct SceneFile {
+    #[serde(default)]
+    pub camera: CameraSettings,
+    #[serde(default)]
+    pub cameras: HashMap<String, CameraSettings>,
+    #[serde(default)]
+    pub spheres: Vec<SphereEntry>,
+    #[serde(default)]
+    pub meshes: Vec<MeshEntry>,
+    #[serde(default)]
+    pub animation: Animation,
+}
+
+/// A sphere of a scene file.
+#[derive(Clone, Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct SphereEntry {
+    pub name: Option<String>,
+    pub center: Point3<Float>,
+    pub radius: Float,
+    pub material: Material,
+}
+
+impl SphereEntry {
+    pub fn to_sphere(&self) -> Sphere {
+        Sphere::new(self.center, self.radius, self.material.clone())
+    }
+}
+
+/// A triangle mesh of a scene file, loaded from the OBJ file at `path` (relative to the scene
+/// file). The model is scaled by `scale` and then moved by `translation`. An optional
+/// `displacement` moves the vertices along their normal vectors by the values of a texture, e.g.
+/// `displacement = { height = { type = "image", path = "bumps.png" }, scale = 0.05 }`.
+#[derive(Clone, Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct MeshEntry {
+    pub name: Option<String>,
+    pub path: PathBuf,
+    #[serde(default = "Vector3::zeros")]
+    pub translation: Vector3<Float>,
+    #[serde(default = "one")]
+    pub scale: Float,
+    pub displacement: Option<Displacement>,
+    pub material: Material,
+    /// The rotation of an animated mesh at the current frame, around the origin of the model.
+    #[serde(skip)]
+    rotation: Option<UnitQuaternion<Float>>,
+    /// The model as read from the OBJ file, which the frames of an animation transform anew.
+    #[serde(skip)]
+    model: Option<Arc<Geometry>>,
+    /// The geometry, once loaded.
+    #[serde(skip)]
+    geometry: Option<Arc<Geometry>>,
+}
+
+/// Displacement of the vertices of a mesh, applied when the mesh is loaded.
+#[derive(Clone, Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Displacement {
+    pub height: Texture,
+    pub scale: Float,
+}
+
+fn one() -> Float {
+    1.
+}
+
+impl MeshEntry {
+    /// Loads, transforms and displaces the geometry, unless that was already done.
+    pub fn load(&mut self) -> Result<()> {
+        if self.geometry.is_none() {
+            let model = match &self.model {
+                Some(model) => Arc::clone(model),
+                None => Arc::new(Geometry::load_obj(&self.path)?),
+            };
+            let mut geometry = Geometry::clone(&model);
+            self.model = Some(model);
+            if let Some(rotation) = &self.rotation {
+                geometry.rotate(rotation);
+            }
+            geometry.transform(self.scale, &self.translation);
+            if let Some(displacement) = &self.displacement {
+                geometry.displace(&displacement.height, displacement.scale);
+            }
+            self.geometry = Some(Arc::new(geometry));
+        }
+        Ok(())
+    }
+
+    /// The mesh moved, scaled and rotated by an animation. The geometry is transformed again
+    /// when it is loaded, from the model which was already read.
+    pub fn animated(&self, transform: &Transform) -> MeshEntry {
+        MeshEntry {
+            translation: self.translation + transform.translation,
+            scale: self.scale * transform.scale,
+            rotation: Some(transform.rotation),
+            geometry: None,
+            ..self.clone()
+        }
+    }
+
+    pub fn to_mesh(&self) -> Result<Mesh> {
+        let mut entry = self.clone();
+        entry.load()?;
+        Ok(Mesh::new(
+            entry.geometry.expect("the geometry was just loaded"),
+            self.material.clone(),
+        ))
+    }
+}
+
+impl SceneFile {
+    /// Reads and checks a scene file and loads its meshes. Relative paths of the meshes, images
+    /// and aperture masks are relative to the directory of the scene file.
+    pub fn load<P: AsRef<Path>>(path: P) -> Result<SceneFile> {
+        let path = path.as_ref();
+        let contents = fs::read_to_string(path).map_err(|err| Error::from(err).with_path(path))?;
+        let directory = path.parent().unwrap_or_else(|| Path::new(""));
+        let mut file: SceneFile = toml::from_str(&contents)
+            .map_err(|err| Error::Scene(format!("{}: {}", path.display(), err)))?;
+        let cameras = std::iter::once(&mut file.camera).chain(file.cameras.values_mut());
+        for mask in cameras.filter_map(|camera| camera.aperture_mask.as_mut()) {
+            *mask = directory.join(&*mask);
+        }
+        for sphere in &mut file.spheres {
+            sphere.material.load_images(directory)?;
+        }
+        for mesh in &mut file.meshes {
+            mesh.path = directory.join(&mesh.path);
+            mesh.material.load_images(directory)?;
+            if let Some(displacement) = &mut mesh.displacement {
+                displacement.height.load_images(directory)?;
+            }
+            mesh.load()?;
+        }
+
+        file.animation
+            .check_objects(file.object_names())
+            .map_err(|err| Error::Scene(format!("{}: {}", path.display(), err)))?;
+        Ok(file)
+    }
+
+    /// The scene as it looks at the given frame of its animation: the animated objects are moved,
+    /// scaled and (for meshes) rotated, and the animated camera values override the ones of the
+    /// default camera and of every bookmark.
+    pub fn at_frame(&self, frame: Float) -> SceneFile {
+        let animated_camera = self.animation.camera_at(frame);
+        let spheres = self
+            .spheres
+            .iter()
+            .map(|entry| {
+                let transform = entry
+                    .name
+                    .as_deref()
+                    .and_then(|name| self.animation.transform_at(name, frame));
+                match transform {
+                    Some(transform) => SphereEntry {
+                        center: entry.center + transform.translation,
+                        radius: entry.radius * transform.scale,
+                        ..entry.clone()
+                    },
+                    None => entry.clone(),
+                }
+            })
+            .collect();
+        let meshes = self
+            .meshes
+            .iter()
+            .map(|entry| {
+                let transform = entry
+                    .name
+                    .as_deref()
+                    .and_then(|name| self.animation.transform_at(name, frame));
+                match transform {
+                    Some(transform) => entry.animated(&transform),
+                    None => entry.clone(),
+                }
+            })
+            .collect();
+
+        SceneFile {
+            camera: animated_camera.clone().or(self.camera.clone()),
+            cameras: self
+                .cameras
+                .iter()
+                .map(|(name, camera)| (name.clone(), animated_camera.clone().or(camera.clone())))
+                .collect(),
+            spheres,
+            meshes,
+            animation: self.animation.clone(),
+        }
+    }
+
+    /// The names of the spheres and the meshes, which the animation refers to.
+    fn object_names(&self) -> impl Iterator<Item = &str> + Clone {
+        let spheres = self.spheres.iter().filter_map(|s| s.name.as_deref());
+        spheres.chain(self.meshes.iter().filter_map(|m| m.name.as_deref()))
+    }
+
+    /// How messages refer to an object of the scene built out of the file: by its number and,
+    /// for named spheres, by its name (e.g. `sphere #3 ("ball")`) or, for meshes, by the path of
+    /// the model (e.g. `mesh #0 (teapot.obj)`).
+    pub fn describe(&self, object: &Object) -> String {
+        let detail = match *object {
+            Object::Sphere(index) => self
+                .spheres
+                .get(index)
+                .and_then(|sphere| sphere.name.as_ref())
+                .map(|name| format!("{:?}", name)),
+            Object::Mesh(index) => self
+                .meshes
+                .get(index)
+                .map(|mesh| mesh.path.display().to_string()),
+            Object::Camera => None,
+        };
+        match detail {
+            Some(detail) => format!("{} ({})", object, detail),
+            None => object.to_string(),
+        }
+    }
+
+    /// The settings of the camera bookmark called `name`, layered over the default camera of the
+    /// scene.
+    pub fn bookmark(&self, name: &str) -> Result<CameraSettings> {
+        match self.cameras.get(name) {
+            Some(camera) => Ok(camera.clone().or(self.camera.clone())),
+            None => {
+                let mut known: Vec<&String> = self.cameras.keys().collect();
+                known.sort();
+                Err(Error::Scene(format!(
+                    "unknown camera `{}` (available: {:?})",
+                    name, known
+                )))
+            }
+        }
+    }
+
+    /// Builds the scene, looking through the camera bookmark called `bookmark` (or through the
+    /// default camera), after applying the `overrides`.
+    pub fn into_scene(
+        self,
+        bookmark: Option<&str>,
+        overrides: &CameraSettings,
+        aspect_ratio: Float,
+    ) -> Result<Scene> {
+        let camera = match bookmark {
+            Some(name) => self.bookmark(name)?,
+            None => self.camera.clone(),
+        };
+        let objects: Vec<Sphere> = self.spheres.iter().map(SphereEntry::to_sphere).collect();
+        let meshes = self
+            .meshes
+            .iter()
+            .map(MeshEntry::to_mesh)
+            .collect::<Result<Vec<Mesh>>>()?;
+        let camera = overrides
+            .clone()
+            .or(camera)
+            .build(&(&objects, &meshes), aspect_ratio)?;
+
+        Ok(Scene {
+            objects,
+            meshes,
+            camera,
+            bvh: OnceLock::new(),
+        })
+    }
+}
@@ -0,0 +1,492 @@
+use crate::color::{self, ColorSpace};
+use crate::float::{consts, Float};
+use nalgebra::{Complex, ComplexField, Matrix3, Vector3};
+use serde::Deserialize;
+use std::ops::{Add, AddAssign, Div, Index, Mul, MulAssign, Sub};
+use std::sync::OnceLock;
+
+/// Shortest wavelength (in nanometers) that is traced in spectral mode.
+pub const LAMBDA_MIN: Float = 360.;
+/// Longest wavelength (in nanometers) that is traced in spectral mode.
+pub const LAMBDA_MAX: Float = 830.;
+/// Number of wavelengths carried by each ray.
+pub const SAMPLES: usize = 4;
+
+/// Wavelengths at which the red, green and blue channels are evaluated in RGB mode, e.g. for the
+/// measured refractive indices of conductors.
+const RGB_WAVELENGTHS: [Float; 3] = [630., 532., 465.];
+
+/// The amount of light (or the fraction of it which survives a scattering event) at each of the
+/// wavelengths carried by a ray. In RGB mode, the first three values are the red, green and blue
+/// channels and the last one is unused.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct SampledSpectrum(pub [Float; SAMPLES]);
+
+impl SampledSpectrum {
+    pub fn constant(value: Float) -> SampledSpectrum {
+        SampledSpectrum([value; SAMPLES])
+    }
+
+    pub fn zeros() -> SampledSpectrum {
+        SampledSpectrum::constant(0.)
+    }
+
+    pub fn ones() -> SampledSpectrum {
+        SampledSpectrum::constant(1.)
+    }
+
+    pub fn is_black(&self) -> bool {
+        self.0.iter().all(|&value| value == 0.)
+    }
+
+    pub fn max(&self) -> Float {
+        self.0.iter().copied().fold(Float::NEG_INFINITY, Float::max)
+    }
+}
+
+impl Index<usize> for SampledSpectrum {
+    type Output = Float;
+
+    fn index(&self, index: usize) -> &Float {
+        &self.0[index]
+    }
+}
+
+impl Add for SampledSpectrum {
+    type Output = SampledSpectrum;
+
+    fn add(mut self, rhs: SampledSpectrum) -> SampledSpectrum {
+        self += rhs;
+        self
+    }
+}
+
+impl Sub for SampledSpectrum {
+    type Output = SampledSpectrum;
+
+    fn sub(mut self, rhs: SampledSpectrum) -> SampledSpectrum {
+        self.0.iter_mut().zip(rhs.0).for_each(|(a, b)| *a -= b);
+        self
+    }
+}
+
+impl AddAssign for SampledSpectrum {
+    fn add_assign(&mut self, rhs: SampledSpectrum) {
+        self.0.iter_mut().zip(rhs.0).for_each(|(a, b)| *a += b);
+    }
+}
+
+impl Mul for SampledSpectrum {
+    type Output = SampledSpectrum;
+
+    fn mul(mut self, rhs: SampledSpectrum) -> SampledSpectrum {
+        self *= rhs;
+        self
+    }
+}
+
+impl MulAssign for SampledSpectrum {
+    fn mul_assign(&mut self, rhs: SampledSpectrum) {
+        self.0.iter_mut().zip(rhs.0).for_each(|(a, b)| *a *= b);
+    }
+}
+
+impl MulAssign<Float> for SampledSpectrum {
+    fn mul_assign(&mut self, rhs: Float) {
+        self.0.iter_mut().for_each(|a| *a *= rhs);
+    }
+}
+
+impl Mul<Float> for SampledSpectrum {
+    type Output = SampledSpectrum;
+
+    fn mul(self, rhs: Float) -> SampledSpectrum {
+        SampledSpectrum(self.0.map(|value| value * rhs))
+    }
+}
+
+impl Div<Float> for SampledSpectrum {
+    type Output = SampledSpectrum;
+
+    fn div(self, rhs: Float) -> SampledSpectrum {
+        SampledSpectrum(self.0.map(|value| value / rhs))
+    }
+}
+
+/// What the values of a `SampledSpectrum` stand for.
+///
+/// * `Rgb`: the red, green and blue channels of the working color `space`, as in the classic
+///   renderer.
+/// * `Spectral`: `SAMPLES` wavelengths in nanometers, using hero wavelength sampling: the first
+///   one (the "hero") is chosen at random and the others are spread evenly after it, wrapping
+///   around the visible range. When a wavelength dependent event such as dispersion happens, only
+///   the hero wavelength can be followed; the other ones are then terminated.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum Wavelengths {
+    Rgb {
+        space: ColorSpace,
+    },
+    Spectral {
+        lambda: [Float; SAMPLES],
+        secondary_terminated: bool,
+        space: ColorSpace,
+    },
+}
+
+impl Wavelengths {
+    /// Picks the wavelengths of a spectral ray, given a uniform random number `u` in `[0, 1)`.
+    /// Colors are converted from and into the working color `space`.
+    pub fn sample(u: Float, space: ColorSpace) -> Wavelengths {
+        let range = LAMBDA_MAX - LAMBDA_MIN;
+        let lambda = std::array::from_fn(|i| {
+            let offset = (u + i as Float / SAMPLES as Float).fract();
+            LAMBDA_MIN + offset * range
+        });
+        Wavelengths::Spectral {
+            lambda,
+            secondary_terminated: false,
+            space,
+        }
+    }
+
+    /// The working color space of the colors carried by the ray.
+    pub fn space(&self) -> ColorSpace {
+        match self {
+            Wavelengths::Rgb { space } | Wavelengths::Spectral { space, .. } => *space,
+        }
+    }
+
+    /// The hero wavelength, or `None` in RGB mode.
+    pub fn hero(&self) -> Option<Float> {
+        match self {
+            Wavelengths::Rgb { .. } => None,
+            Wavelengths::Spectral { lambda, .. } => Some(lambda[0]),
+        }
+    }
+
+    /// The number of values of a `SampledSpectrum` which are in use: the three channels in RGB
+    /// mode, or the wavelengths which are still followed in spectral mode.
+    pub fn lanes(&self) -> usize {
+        match self {
+            Wavelengths::Rgb { .. } => 3,
+            Wavelengths::Spectral {
+                secondary_terminated: true,
+                ..
+            } => 1,
+            Wavelengths::Spectral { .. } => SAMPLES,
+        }
+    }
+
+    /// Stops following every wavelength except the hero, e.g. because the ray was refracted by a
+    /// dispersive material and the wavelengths went separate ways.
+    pub fn terminate_secondary(&mut self) {
+        if let Wavelengths::Spectral {
+            secondary_terminated,
+            ..
+        } = self
+        {
+            *secondary_terminated = true;
+        }
+    }
+
+    /// Evaluates a function of the wavelength (in nanometers) at each wavelength of the ray. In RGB
+    /// mode, each channel is evaluated at a representative wavelength.
+    pub fn map<F: Fn(Float) -> Float>(&self, f: F) -> SampledSpectrum {
+        match self {
+            Wavelengths::Rgb { .. } => {
+                let [r, g, b] = RGB_WAVELENGTHS.map(&f);
+                SampledSpectrum([r, g, b, 0.])
+            }
+            Wavelengths::Spectral { lambda, .. } => SampledSpectrum(lambda.map(f)),
+        }
+    }
+
+    /// Converts a linear RGB color (e.g. the attenuation of a material) into a spectrum, using
+    /// Smits' method in spectral mode.
+    pub fn upsample(&self, rgb: &Vector3<Float>) -> SampledSpectrum {
+        match self {
+            Wavelengths::Rgb { .. } => SampledSpectrum([rgb.x, rgb.y, rgb.z, 0.]),
+            Wavelengths::Spectral { lambda, space, .. } => {
+                // Smits' spectra are made for the primaries of sRGB.
+                let rgb = constants(*space).to_srgb * rgb;
+                SampledSpectrum(lambda.map(|lambda| smits(&rgb, lambda)))
+            }
+        }
+    }
+
+    /// Converts the light carried by a ray into a linear color of the working space. In spectral
+    /// mode, the light is projected onto the CIE 1931 color matching functions and white balanced
+    /// so that a constant spectrum becomes a neutral gray.
+    pub fn to_rgb(&self, spectrum: &SampledSpectrum) -> Vector3<Float> {
+        match self {
+            Wavelengths::Rgb { .. } => Vector3::new(spectrum[0], spectrum[1], spectrum[2]),
+            Wavelengths::Spectral { lambda, space, .. } => {
+                let constants = constants(*space);
+                let count = self.lanes();
+                let xyz: Vector3<Float> = lambda
+                    .iter()
+                    .zip(spectrum.0)
+                    .take(count)
+                    .map(|(&lambda, value)| cie_xyz(lambda) * value)
+                    .sum();
+
+                // Monte Carlo estimate of the integral, with a uniform pdf over the range.
+                let xyz = xyz * (LAMBDA_MAX - LAMBDA_MIN) / (count as Float * constants.y_integral);
+                (constants.xyz_to_rgb * xyz).component_mul(&constants.white_balance)
+            }
+        }
+    }
+}
+
+/// The CIE 1931 color matching functions, using the multi-lobe Gaussian fit of Wyman, Sloan and
+/// Shirley (2013).
+pub fn cie_xyz(lambda: Float) -> Vector3<Float> {
+    let g = |mu: Float, sigma_below: Float, sigma_above: Float| {
+        let sigma = if lambda < mu {
+            sigma_below
+        } else {
+            sigma_above
+        };
+        (-0.5 * ((lambda - mu) / sigma).powi(2)).exp()
+    };
+
+    Vector3::new(
+        1.056 * g(599.8, 37.9, 31.0) + 0.362 * g(442.0, 16.0, 26.7) - 0.065 * g(501.1, 20.4, 26.2),
+        0.821 * g(568.8, 46.9, 40.5) + 0.286 * g(530.9, 16.3, 31.1),
+        1.217 * g(437.0, 11.8, 36.0) + 0.681 * g(459.0, 26.0, 13.8),
+    )
+}
+
+struct Constants {
+    y_integral: Float,
+    xyz_to_rgb: Matrix3<Float>,
+    to_srgb: Matrix3<Float>,
+    white_balance: Vector3<Float>,
+}
+
+/// Values derived from the color matching functions and the working color space, which are
+/// computed once per color space.
+fn constants(space: ColorSpace) -> &'static Constants {
+    static CONSTANTS: [OnceLock<Constants>; 3] =
+        [OnceLock::new(), OnceLock::new(), OnceLock::new()];
+    let index = match space {
+        ColorSpace::LinearSrgb => 0,
+        ColorSpace::Acescg => 1,
+        ColorSpace::Rec2020 => 2,
+    };
+
+    CONSTANTS[index].get_or_init(|| {
+        let primaries = space.primaries();
+        let xyz_to_rgb = primaries
+            .rgb_to_xyz()
+            .try_inverse()
+            .expect("the primaries of a color space are linearly independent");
+
+        let integral: Vector3<Float> = (LAMBDA_MIN as u32..LAMBDA_MAX as u32)
+            .map(|lambda| cie_xyz(lambda as Float + 0.5))
+            .sum();
+        let white = xyz_to_rgb * (integral / integral.y);
+
+        Constants {
+            y_integral: integral.y,
+            xyz_to_rgb,
+            to_srgb: primaries.conversion_to(&color::REC709),
+            white_balance: white.map(|channel| 1. / channel),
+        }
+    })
+}
+
+/// The spectra of Smits (1999), "An RGB to Spectrum Conversion for Reflectances", in 10 bins
+/// spanning 380 to 720 nanometers.
+const SMITS_WHITE: [Float; 10] = [1.0, 1.0, 0.9999, 0.9993, 0.9992, 0.9998, 1.0, 1.0, 1.0, 1.0];
+const SMITS_CYAN: [Float; 10] = [
+    0.9710, 0.9426, 1.0007, 1.0007, 1.0007, 1.0007, 0.1564, 0., 0., 0.,
+];
+const SMITS_MAGENTA: [Float; 10] = [
+    1.0, 1.0, 0.9685, 0.2229, 0., 0.0458, 0.8369, 1.0, 1.0, 0.9959,
+];
+const SMITS_YELLOW: [Float; 10] = [
+    0.0001, 0., 0.1088, 0.6651, 1.0, 1.0, 0.9996, 0.9586, 0.9685, 0.9840,
+];
+const SMITS_RED: [Float; 10] = [
+    0.1012, 0.0515, 0., 0., 0., 0., 0.8325, 1.0149, 1.0149, 1.0149,
+];
+const SMITS_GREEN: [Float; 10] = [0., 0., 0.0273, 0.7937, 1.0, 0.9418, 0.1719, 0., 0., 0.0025];
+const SMITS_BLUE: [Float; 10] = [
+    1.0, 1.0, 0.8916, 0.3323, 0., 0., 0.0003, 0.0369, 0.0483, 0.0496,
+];
+
+/// The value at `lambda` of Smits' spectrum for the given color. The bins are interpolated
+/// linearly between their centers and extended with their first and last values.
+fn smits(rgb: &Vector3<Float>, lambda: Float) -> Float {
+    let bin = ((lambda - 380.) / 34. - 0.5).clamp(0., 9.);
+    let (index, t) = (bin.floor() as usize, bin.fract());
+    let at = |spectrum: &[Float; 10]| {
+        let next = spectrum[(index + 1).min(9)];
+        spectrum[index] * (1. - t) + next * t
+    };
+    let (r, g, b) = (rgb.x, rgb.y, rgb.z);
+
+    // The smallest channel is made of white; the rest is made of a secondary color (the two
+    // smaller channels) and a primary color (the largest channel).
+    if r <= g && r <= b {
+        r * at(&SMITS_WHITE)
+            + if g <= b {
+                (g - r) * at(&SMITS_CYAN) + (b - g) * at(&SMITS_BLUE)
+            } else {
+                (b - r) * at(&SMITS_CYAN) + (g - b) * at(&SMITS_GREEN)
+            }
+    } else if g <= r && g <= b {
+        g * at(&SMITS_WHITE)
+            + if r <= b {
+                (r - g) * at(&SMITS_MAGENTA) + (b - r) * at(&SMITS_BLUE)
+            } else {
+                (b - g) * at(&SMITS_MAGENTA) + (r - b) * at(&SMITS_RED)
+            }
+    } else {
+        b * at(&SMITS_WHITE)
+            + if r <= g {
+                (r - b) * at(&SMITS_YELLOW) + (g - r) * at(&SMITS_GREEN)
+            } else {
+                (g - b) * at(&SMITS_YELLOW) + (r - g) * at(&SMITS_RED)
+            }
+    }
+}
+
+/// Metals whose measured complex refractive index is known.
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Conductor {
+    Gold,
+    Copper,
+    Silver,
+    #[serde(alias = "aluminum")]
+    Aluminium,
+}
+
+/// `(wavelength, n, k)` samples of the complex refractive indices, every 50 nanometers. Gold,
+/// copper and silver after Johnson and Christy (1972), aluminium after Rakić (1995).
+#[rustfmt::skip]
+const GOLD: [(Float, Float, Float); 8] = [
+    (400., 1.658, 1.956), (450., 1.510, 1.880), (500., 0.970, 1.870), (550., 0.430, 2.455),
+    (600., 0.250, 2.980), (650., 0.170, 3.440), (700., 0.160, 3.950), (750., 0.170, 4.400),
+];
+#[rustfmt::skip]
+const COPPER: [(Float, Float, Float); 8] = [
+    (400., 1.180, 2.210), (450., 1.170, 2.370), (500., 1.130, 2.560), (550., 0.950, 2.580),
+    (600., 0.270, 3.410), (650., 0.210, 3.670), (700., 0.210, 4.050), (750., 0.240, 4.430),
+];
+#[rustfmt::skip]
+const SILVER: [(Float, Float, Float); 8] = [
+    (400., 0.050, 2.100), (450., 0.040, 2.650), (500., 0.050, 3.100), (550., 0.060, 3.590),
+    (600., 0.060, 4.000), (650., 0.050, 4.480), (700., 0.040, 4.800), (750., 0.030, 5.200),
+];
+#[rustfmt::skip]
+const ALUMINIUM: [(Float, Float, Float); 8] = [
+    (400., 0.490, 4.860), (450., 0.620, 5.470), (500., 0.770, 6.080), (550., 0.960, 6.690),
+    (600., 1.200, 7.260), (650., 1.470, 7.790), (700., 1.830, 8.310), (750., 2.400, 8.620),
+];
+
+impl Conductor {
+    /// The complex refractive index `(n, k)` at `lambda`, interpolated linearly between the
+    /// measurements.
+    pub fn refractive_index(&self, lambda: Float) -> (Float, Float) {
+        let table = match self {
+            Conductor::Gold => &GOLD,
+            Conductor::Copper => &COPPER,
+            Conductor::Silver => &SILVER,
+            Conductor::Aluminium => &ALUMINIUM,
+        };
+
+        let next = table
+            .partition_point(|&(l, _, _)| l < lambda)
+            .clamp(1, table.len() - 1);
+        let (l0, n0, k0) = table[next - 1];
+        let (l1, n1, k1) = table[next];
+        let t = ((lambda - l0) / (l1 - l0)).clamp(0., 1.);
+        (n0 + (n1 - n0) * t, k0 + (k1 - k0) * t)
+    }
+
+    /// The fraction of unpolarized light of wavelength `lambda` which is reflected, coming from
+    /// the air at an angle whose cosine is `cosine` (the Fresnel equations for conductors).
+    pub fn reflectance(&self, cosine: Float, lambda: Float) -> Float {
+        let (n, k) = self.refractive_index(lambda);
+        let cos2 = cosine.clamp(0., 1.).powi(2);
+        let sin2 = 1. - cos2;
+
+        let t0 = n * n - k * k - sin2;
+        let a2_plus_b2 = (t0 * t0 + 4. * n * n * k * k).sqrt();
+        let a = (0.5 * (a2_plus_b2 + t0)).max(0.).sqrt();
+
+        let t1 = a2_plus_b2 + cos2;
+        let t2 = 2. * cosine.clamp(0., 1.) * a;
+        let rs = (t1 - t2) / (t1 + t2);
+
+        let t3 = cos2 * a2_plus_b2 + sin2 * sin2;
+        let t4 = t2 * sin2;
+        let rp = rs * (t3 - t4) / (t3 + t4);
+
+        0.5 * (rs + rp)
+    }
+}
+
+/// A transparent film a few hundred nanometers thick on top of a surface, e.g. soap, oil or an
+/// oxide layer. The light reflected by the top and the bottom of the film interferes, which
+/// colors the reflections depending on the wavelength and the angle (iridescence).
+#[derive(Copy, Clone, Debug, PartialEq, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct ThinFilm {
+    /// In nanometers.
+    pub thickness: Float,
+    pub refractive_index: Float,
+}
+
+impl ThinFilm {
+    /// The fraction of unpolarized light of wavelength `lambda` which is reflected, coming from
+    /// the air at an angle whose cosine is `cosine`, when the film lies on a substrate with the
+    /// complex refractive index `(n, k)` (`k = 0` for dielectrics). The light bounces between the
+    /// two interfaces of the film any number of times (the Airy summation).
+    pub fn reflectance(&self, cosine: Float, lambda: Float, substrate: (Float, Float)) -> Float {
+        let one = Complex::new(1., 0.);
+        let n1 = one;
+        let n2 = Complex::new(self.refractive_index, 0.);
+        let n3 = Complex::new(substrate.0, substrate.1);
+
+        // Snell's law gives the (possibly complex) cosines inside the film and the substrate.
+        let cosine = cosine.clamp(0., 1.);
+        let sin2 = 1. - cosine * cosine;
+        let cos1 = Complex::new(cosine, 0.);
+        let cos2 = (one - sin2 / (n2 * n2)).sqrt();
+        let cos3 = (one - sin2 / (n3 * n3)).sqrt();
+
+        // The phase difference between consecutive bounces.
+        let delta = 4. * consts::PI * self.thickness / lambda * n2 * cos2;
+        let phase = (Complex::<Float>::i() * delta).exp();
+        let airy = |r12: Complex<Float>, r23: Complex<Float>| {
+            ((r12 + r23 * phase) / (one + r12 * r23 * phase)).norm_sqr()
+        };
+
+        let s = |ni: Complex<Float>, ci: Complex<Float>, nj: Complex<Float>, cj: Complex<Float>| {
+            (ni * ci - nj * cj) / (ni * ci + nj * cj)
+        };
+        let p = |ni: Complex<Float>, ci: Complex<Float>, nj: Complex<Float>, cj: Complex<Float>| {
+            (nj * ci - ni * cj) / (nj * ci + ni * cj)
+        };
+        let rs = airy(s(n1, cos1, n2, cos2), s(n2, cos2, n3, cos3));
+        let rp = airy(p(n1, cos1, n2, cos2), p(n2, cos2, n3, cos3));
+        (0.5 * (rs + rp)).clamp(0., 1.)
+    }
+}
+
+/// The refractive index at `lambda` of a glass with refractive index `refractive_index` at the
+/// helium d line (587.6 nm) and the given [Abbe number](https://en.wikipedia.org/wiki/Abbe_number),
+/// using Cauchy's equation. Lower Abbe numbers mean stronger dispersion.
+pub fn cauchy(refractive_index: Float, abbe_number: Float, lambda: Float) -> Float {
+    const D: Float = 587.6;
+    const F: Float = 486.1;
+    const C: Float = 656.3;
+
+    let b = (refractive_index - 1.) / (abbe_number * (1. / (F * F) - 1. / (C * C)));
+    let a = refractive_index - b / (D * D);
+    a + b / (lambda * lambda)
+}
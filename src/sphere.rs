@@ -1,21 +1,23 @@
-use crate::hitable::{HitPoint, Hitable};
+use crate::bvh::{Aabb, Bounded};
+use crate::float::{consts, Float};
+use crate::hitable::{self, HitPoint, Hitable};
 use crate::material::Material;
+use crate::packet::{self, Lanes, RayPacket, WIDTH};
 use crate::ray::Ray;
+use crate::stats;
 use crate::util;
-use nalgebra::{Point3, Unit, Vector3};
-use rand::{thread_rng, Rng};
-use std::cmp::Ordering;
+use nalgebra::{Point3, Unit, Vector2, Vector3};
 
 /// A 3D sphere.
 #[derive(Clone, Debug)]
 pub struct Sphere {
-    center: Point3<f64>,
-    radius: f64,
+    center: Point3<Float>,
+    radius: Float,
     material: Material,
 }
 
 impl Sphere {
-    pub fn new(center: Point3<f64>, radius: f64, material: Material) -> Sphere {
+    pub fn new(center: Point3<Float>, radius: Float, material: Material) -> Sphere {
         Sphere {
             center,
             radius,
@@ -23,56 +25,51 @@ impl Sphere {
         }
     }
 
-    pub fn center(&self) -> &Point3<f64> {
+    pub fn center(&self) -> &Point3<Float> {
         &self.center
     }
 
-    pub fn x(&self) -> f64 {
+    pub fn x(&self) -> Float {
         self.center.x
     }
 
-    pub fn y(&self) -> f64 {
+    pub fn y(&self) -> Float {
         self.center.y
     }
 
-    pub fn z(&self) -> f64 {
+    pub fn z(&self) -> Float {
         self.center.z
     }
 
-    pub fn radius(&self) -> f64 {
+    pub fn radius(&self) -> Float {
         self.radius
     }
 
-    /// Returns a random 3D Point situated inside a sphere of radius 1, located in the origin.
-    /// The point is obtained by sequentially generating points in the unit square and selecting
-    /// the first one that happens to also be inside the unit sphere. Approximately 52.35% chance of
-    /// getting a valid point on each trial.
-    pub fn random_point_in_unit_sphere() -> Vector3<f64> {
-        let mut rng = thread_rng();
-        (0..)
-            .into_iter()
-            .map(|_| {
-                Vector3::new(
-                    rng.gen_range(-1.0..1.0),
-                    rng.gen_range(-1.0..1.0),
-                    rng.gen_range(-1.0..1.0),
-                )
-            })
-            .filter(|point| point.dot(point) < 1.)
-            .take(1)
-            .next()
-            .unwrap()
-    }
-
-    pub fn random_point_in_unit_disk() -> Vector3<f64> {
-        let mut rng = thread_rng();
-        (0..)
-            .into_iter()
-            .map(|_| Vector3::new(rng.gen_range(-1.0..1.0), rng.gen_range(-1.0..1.0), 0.))
-            .filter(|point| point.dot(point) < 1.)
-            .take(1)
-            .next()
-            .unwrap()
+    pub fn material(&self) -> &Material {
+        &self.material
+    }
+
+    /// The texture coordinates of a point of the unit sphere: `u` goes around the vertical axis
+    /// and `v` goes from the bottom (`0`) to the top (`1`).
+    fn uv(point: &Vector3<Float>) -> Vector2<Float> {
+        let theta = (-point.y).clamp(-1., 1.).acos();
+        let phi = (-point.z).atan2(point.x) + consts::PI;
+        Vector2::new(phi / (2. * consts::PI), theta / consts::PI)
+    }
+
+    /// The partial derivative of a point (relative to the center) with respect to `u`.
+    fn dpdu(local: &Vector3<Float>) -> Vector3<Float> {
+        2. * consts::PI * Vector3::new(local.z, 0., -local.x)
+    }
+
+    /// The partial derivative of a point (relative to the center) with respect to `v`. It
+    /// vanishes at the poles, where the tangent frame falls back to an arbitrary one.
+    fn dpdv(local: &Vector3<Float>) -> Vector3<Float> {
+        let rho = (local.x * local.x + local.z * local.z).sqrt();
+        if rho == 0. {
+            return Vector3::zeros();
+        }
+        consts::PI * Vector3::new(-local.x * local.y / rho, rho, -local.y * local.z / rho)
     }
 
     pub fn intersects(&self, other: &Sphere) -> bool {
@@ -83,33 +80,149 @@ impl Sphere {
 
 impl Hitable for Sphere {
     /// There can be 0, 1 or 2 hitpoints for a given ray and a sphere. If there is more than
-    /// one hitpoint, the closest one (smallest `t`) is chosen.
-    fn hit(&self, ray: &Ray, t_min: f64, tmax: f64) -> Option<HitPoint> {
-        let oc = ray.origin() - self.center();
-
-        let a = ray.direction().dot(ray.direction());
-        let b = oc.dot(ray.direction());
-        let c = oc.dot(&oc) - self.radius().powf(2.);
-
-        let delta = b.powf(2.) - a * c;
-
-        match delta.partial_cmp(&0.) {
-            Some(Ordering::Greater) => {
-                for t in [(-b - delta.sqrt()) / a, (-b + delta.sqrt()) / a] {
-                    if t_min < t && t < tmax {
-                        let p = ray.point_at_parameter(t);
-                        let normal = (p - self.center()) / self.radius();
-                        return Some(HitPoint {
-                            t,
-                            p,
-                            normal: Unit::new_normalize(normal),
-                            material: self.material,
-                        });
+    /// one hitpoint, the closest one (smallest `t`) is chosen, unless it is transparent (see
+    /// `Material::Cutout`).
+    fn hit(&self, ray: &Ray, t_min: Float, t_max: Float) -> Option<HitPoint<'_>> {
+        stats::count(|c| c.primitive_tests += 1);
+        let roots = self.roots(&RayPacket::single(ray))?;
+        self.closest_root(ray, &roots, 0, t_min, t_max)
+    }
+
+    fn hit_packet(
+        &self,
+        packet: &RayPacket,
+        t_min: Float,
+        t_max: &Lanes<WIDTH>,
+    ) -> [Option<HitPoint<'_>>; WIDTH] {
+        stats::count(|c| c.primitive_tests += 1);
+        match self.roots(packet) {
+            Some(roots) => std::array::from_fn(|lane| {
+                if roots.hit[lane] {
+                    self.closest_root(&packet.ray(lane), &roots, lane, t_min, t_max[lane])
+                } else {
+                    None
+                }
+            }),
+            None => [None; WIDTH],
+        }
+    }
+}
+
+/// The distances at which the rays of a packet enter and leave a sphere, with bounds on their
+/// rounding errors. Only the lanes which `hit` the sphere are meaningful.
+struct Roots<const N: usize> {
+    hit: [bool; N],
+    near: Lanes<N>,
+    near_error: Lanes<N>,
+    far: Lanes<N>,
+    far_error: Lanes<N>,
+}
+
+impl Sphere {
+    /// Intersects the rays of a packet with the sphere, all lanes at once. Returns `None` if no
+    /// ray hits it.
+    fn roots<const N: usize>(&self, packet: &RayPacket<N>) -> Option<Roots<N>> {
+        let radius = self.radius().abs();
+        let direction = packet.direction();
+        let oc = [0, 1, 2].map(|axis| packet.origin()[axis].map(|o| o - self.center[axis]));
+
+        let a = packet::dot(direction, direction);
+        let b = packet::dot(&oc, direction);
+        let oc_squared = packet::dot(&oc, &oc);
+
+        // The discriminant `b² - a c`, computed from the distance between the center and the
+        // line of the ray, so that it does not cancel out for small spheres far away.
+        let distance: Lanes<N> = packet::lanes(|i| {
+            let s = b[i] / a[i];
+            let [x, y, z] = [0, 1, 2].map(|axis| oc[axis][i] - s * direction[axis][i]);
+            (x * x + y * y + z * z).sqrt()
+        });
+        let delta: Lanes<N> =
+            packet::lanes(|i| a[i] * (radius - distance[i]) * (radius + distance[i]));
+        let hit: [bool; N] = std::array::from_fn(|i| packet.is_active(i) && delta[i] > 0.);
+        if !packet::any(&hit) {
+            return None;
+        }
+
+        // The roots, computed without cancellation, and a first order bound on their errors
+        // from the rounding of the coefficients, which grows as the ray grazes the sphere.
+        let sqrt_delta = delta.map(Float::sqrt);
+        let q: Lanes<N> = packet::lanes(|i| -(b[i] + sqrt_delta[i].copysign(b[i])));
+        let t0: Lanes<N> = packet::lanes(|i| q[i] / a[i]);
+        let t1: Lanes<N> = packet::lanes(|i| (oc_squared[i] - radius.powf(2.)) / q[i]);
+        let b_bound = packet::dot(&packet::abs(&oc), &packet::abs(direction));
+        let t_error = |i: usize, t: Float| {
+            let (a_error, b_error, c_error) = (
+                util::gamma(3) * a[i],
+                util::gamma(4) * b_bound[i],
+                util::gamma(5) * (oc_squared[i] + radius.powf(2.)),
+            );
+            (a_error * t * t + 2. * b_error * t.abs() + c_error) / (2. * sqrt_delta[i])
+                + util::gamma(7) * t.abs()
+        };
+
+        let near: Lanes<N> = packet::lanes(|i| t0[i].min(t1[i]));
+        let far: Lanes<N> = packet::lanes(|i| t0[i].max(t1[i]));
+        Some(Roots {
+            hit,
+            near,
+            near_error: packet::lanes(|i| t_error(i, near[i])),
+            far,
+            far_error: packet::lanes(|i| t_error(i, far[i])),
+        })
+    }
+
+    /// The hit point of the closest root of a lane in `(t_min, t_max)` which is not transparent.
+    fn closest_root<const N: usize>(
+        &self,
+        ray: &Ray,
+        roots: &Roots<N>,
+        lane: usize,
+        t_min: Float,
+        t_max: Float,
+    ) -> Option<HitPoint<'_>> {
+        let radius = self.radius().abs();
+        for (t, t_error) in [
+            (roots.near[lane], roots.near_error[lane]),
+            (roots.far[lane], roots.far_error[lane]),
+        ] {
+            if t_min < t - t_error && t < t_max {
+                // Project the point back onto the sphere, which bounds its error independently
+                // of `t`.
+                let local = ray.point_at_parameter(t) - self.center();
+                let local = local * (radius / local.norm());
+                let p = self.center() + local;
+                let normal = local / self.radius();
+                let uv = Sphere::uv(&normal);
+                if let Some(opacity) = self.material.opacity() {
+                    if hitable::passes_through(opacity, &uv, ray, t) {
+                        continue;
                     }
                 }
-                None
+                let normal = Unit::new_normalize(normal);
+                return Some(HitPoint {
+                    t,
+                    p,
+                    p_error: util::gamma(5) * local.abs() + util::gamma(1) * p.coords.abs(),
+                    normal,
+                    geometric_normal: normal,
+                    uv,
+                    dpdu: Sphere::dpdu(&local),
+                    dpdv: Sphere::dpdv(&local),
+                    material: &self.material,
+                });
             }
-            _ => None,
+        }
+        None
+    }
+}
+
+impl Bounded for Sphere {
+    fn bounds(&self) -> Aabb {
+        let radius = Vector3::new(self.radius, self.radius, self.radius).abs();
+        Aabb {
+            min: self.center - radius,
+            max: self.center + radius,
         }
     }
 }
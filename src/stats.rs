@@ -0,0 +1,207 @@
+use crate::error::{Error, Result};
+use serde::Serialize;
+use std::cell::Cell;
+use std::fmt;
+use std::fs;
+use std::ops::AddAssign;
+use std::path::Path;
+use std::time::Duration;
+
+/// The work done while tracing rays. Each thread counts its own work (see `count` and `take`),
+/// so that counting does not slow the rendering threads down.
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq)]
+pub struct Counters {
+    /// Rays sent from the camera.
+    pub primary_rays: u64,
+    /// Rays scattered by the surfaces and the media.
+    pub secondary_rays: u64,
+    /// Intersection tests with spheres and triangles. Testing a packet of rays at once counts as
+    /// one test.
+    pub primitive_tests: u64,
+    /// Intersection tests with the bounding boxes of acceleration structures, counted like the
+    /// tests with primitives.
+    pub box_tests: u64,
+}
+
+const ZERO: Counters = Counters {
+    primary_rays: 0,
+    secondary_rays: 0,
+    primitive_tests: 0,
+    box_tests: 0,
+};
+
+thread_local! {
+    static COUNTERS: Cell<Counters> = const { Cell::new(ZERO) };
+}
+
+impl Counters {
+    pub fn rays(&self) -> u64 {
+        self.primary_rays + self.secondary_rays
+    }
+}
+
+impl AddAssign for Counters {
+    fn add_assign(&mut self, rhs: Counters) {
+        self.primary_rays += rhs.primary_rays;
+        self.secondary_rays += rhs.secondary_rays;
+        self.primitive_tests += rhs.primitive_tests;
+        self.box_tests += rhs.box_tests;
+    }
+}
+
+/// Updates the counters of the current thread, e.g. `stats::count(|c| c.box_tests += 1)`.
+pub fn count(update: fn(&mut Counters)) {
+    COUNTERS.with(|counters| {
+        let mut value = counters.get();
+        update(&mut value);
+        counters.set(value);
+    });
+}
+
+/// The counters of the current thread, which are reset to zero.
+pub fn take() -> Counters {
+    COUNTERS.with(|counters| counters.replace(ZERO))
+}
+
+/// A summary of the rendering of one or more frames, printed after the render and optionally
+/// saved as JSON (see `Config::stats`).
+#[derive(Clone, Debug, Default)]
+pub struct Stats {
+    pub frames: u32,
+    pub counters: Counters,
+    /// Loading the scene file and building the scene of each frame, including its acceleration
+    /// structures.
+    pub scene_build: Duration,
+    pub render: Duration,
+    /// Encoding and saving the images.
+    pub write: Duration,
+}
+
+impl Stats {
+    pub fn rays_per_second(&self) -> f64 {
+        ratio(self.counters.rays(), self.render.as_secs_f64())
+    }
+
+    /// The average number of rays in the path of each primary ray, including itself.
+    pub fn average_path_length(&self) -> f64 {
+        ratio(self.counters.rays(), self.counters.primary_rays as f64)
+    }
+
+    pub fn primitive_tests_per_ray(&self) -> f64 {
+        ratio(self.counters.primitive_tests, self.counters.rays() as f64)
+    }
+
+    pub fn box_tests_per_ray(&self) -> f64 {
+        ratio(self.counters.box_tests, self.counters.rays() as f64)
+    }
+
+    /// The statistics as a JSON object. Durations are in seconds and the memory in bytes.
+    pub fn to_json(&self) -> String {
+        let report = Report {
+            frames: self.frames,
+            rays: self.counters.rays(),
+            primary_rays: self.counters.primary_rays,
+            secondary_rays: self.counters.secondary_rays,
+            rays_per_second: self.rays_per_second(),
+            average_path_length: self.average_path_length(),
+            primitive_tests: self.counters.primitive_tests,
+            box_tests: self.counters.box_tests,
+            primitive_tests_per_ray: self.primitive_tests_per_ray(),
+            box_tests_per_ray: self.box_tests_per_ray(),
+            scene_build_seconds: self.scene_build.as_secs_f64(),
+            render_seconds: self.render.as_secs_f64(),
+            write_seconds: self.write.as_secs_f64(),
+            peak_memory_bytes: peak_memory(),
+        };
+        serde_json::to_string_pretty(&report).expect("the report only contains numbers")
+    }
+
+    pub fn save<P: AsRef<Path>>(&self, path: P) -> Result<()> {
+        let path = path.as_ref();
+        let json = self.to_json();
+        fs::write(path, json + "\n").map_err(|err| Error::from(err).with_path(path))
+    }
+}
+
+/// The fields of the JSON report, in order.
+#[derive(Serialize)]
+struct Report {
+    frames: u32,
+    rays: u64,
+    primary_rays: u64,
+    secondary_rays: u64,
+    rays_per_second: f64,
+    average_path_length: f64,
+    primitive_tests: u64,
+    box_tests: u64,
+    primitive_tests_per_ray: f64,
+    box_tests_per_ray: f64,
+    scene_build_seconds: f64,
+    render_seconds: f64,
+    write_seconds: f64,
+    peak_memory_bytes: Option<u64>,
+}
+
+impl fmt::Display for Stats {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let counters = &self.counters;
+        writeln!(f, "frames:              {}", self.frames)?;
+        writeln!(
+            f,
+            "time:                {:.3} s scene build, {:.3} s render, {:.3} s write",
+            self.scene_build.as_secs_f64(),
+            self.render.as_secs_f64(),
+            self.write.as_secs_f64()
+        )?;
+        writeln!(
+            f,
+            "rays:                {} ({} primary, {} secondary), {:.0} per second",
+            counters.rays(),
+            counters.primary_rays,
+            counters.secondary_rays,
+            self.rays_per_second()
+        )?;
+        writeln!(
+            f,
+            "average path length: {:.2} rays",
+            self.average_path_length()
+        )?;
+        writeln!(
+            f,
+            "intersection tests:  {:.2} primitives and {:.2} boxes per ray",
+            self.primitive_tests_per_ray(),
+            self.box_tests_per_ray()
+        )?;
+        match peak_memory() {
+            Some(bytes) => write!(
+                f,
+                "peak memory:         {:.1} MiB",
+                bytes as f64 / (1 << 20) as f64
+            ),
+            None => write!(f, "peak memory:         unknown"),
+        }
+    }
+}
+
+fn ratio(numerator: u64, denominator: f64) -> f64 {
+    if denominator > 0. {
+        numerator as f64 / denominator
+    } else {
+        0.
+    }
+}
+
+/// The largest amount of physical memory used by the process so far, in bytes. Only known on
+/// Linux.
+pub fn peak_memory() -> Option<u64> {
+    let status = fs::read_to_string("/proc/self/status").ok()?;
+    let line = status.lines().find(|line| line.starts_with("VmHWM:"))?;
+    let kilobytes: u64 = line
+        .trim_start_matches("VmHWM:")
+        .trim()
+        .trim_end_matches("kB")
+        .trim()
+        .parse()
+        .ok()?;
+    Some(kilobytes * 1024)
+}
@@ -0,0 +1,193 @@
+use crate::color::{ColorSpace, Encoding};
+use crate::error::{Error, Result};
+use crate::float::Float;
+use image::RgbImage;
+use nalgebra::{Vector2, Vector3};
+use serde::de::{self, MapAccess, Visitor};
+use serde::{Deserialize, Deserializer};
+use std::fmt;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+
+/// A scalar which varies over the surface of an object, e.g. the weight with which a `Mix`
+/// material blends its two materials.
+///
+/// In scene files, a texture is either a number (e.g. `weight = 0.3`) or a table tagged by its
+/// `type`:
+///
+/// * `{ type = "checker", scale = 10 }`: alternates between `0` and `1` in a checkerboard of
+///   `scale` by `scale` squares (in texture coordinates).
+/// * `{ type = "image", path = "label.png" }`: the brightness of an image, which is wrapped
+///   around the object. The texels are taken as stored, without color decoding.
+#[derive(Clone, Debug)]
+pub enum Texture {
+    Constant(Float),
+    Pattern(Pattern),
+}
+
+/// The textures which are not constant.
+#[derive(Clone, Debug, Deserialize)]
+#[serde(tag = "type", rename_all = "lowercase", deny_unknown_fields)]
+pub enum Pattern {
+    Checker {
+        scale: Float,
+    },
+    Image {
+        #[serde(rename = "path")]
+        image: ImageTexture,
+    },
+}
+
+impl Texture {
+    /// The value of the texture at the texture coordinates `uv` (e.g. those of a hit point).
+    pub fn value(&self, uv: &Vector2<Float>) -> Float {
+        match self {
+            Texture::Constant(value) => *value,
+            Texture::Pattern(Pattern::Checker { scale }) => {
+                let u = (uv.x * scale).floor() as i64;
+                let v = (uv.y * scale).floor() as i64;
+                (u + v).rem_euclid(2) as Float
+            }
+            Texture::Pattern(Pattern::Image { image }) => image.value(uv),
+        }
+    }
+
+    /// Loads the image of an image texture, with its path relative to `directory`.
+    pub(crate) fn load_images(&mut self, directory: &Path) -> Result<()> {
+        match self {
+            Texture::Pattern(Pattern::Image { image }) => image.load_in(directory),
+            _ => Ok(()),
+        }
+    }
+}
+
+/// An image which is looked up by texture coordinates. Scene files only give its path; the
+/// image is loaded by `SceneFile::load`, and shared between the frames of an animation.
+#[derive(Clone)]
+pub struct ImageTexture {
+    path: PathBuf,
+    texels: Option<Arc<RgbImage>>,
+}
+
+impl ImageTexture {
+    pub fn load<P: AsRef<Path>>(path: P) -> Result<ImageTexture> {
+        let path = path.as_ref();
+        let texels = image::open(path)
+            .map_err(|err| Error::from(err).with_path(path))?
+            .into_rgb8();
+        Ok(ImageTexture {
+            path: path.to_path_buf(),
+            texels: Some(Arc::new(texels)),
+        })
+    }
+
+    /// Loads the image, with its path relative to `directory`, unless it was already loaded.
+    pub(crate) fn load_in(&mut self, directory: &Path) -> Result<()> {
+        if self.texels.is_none() {
+            *self = ImageTexture::load(directory.join(&self.path))?;
+        }
+        Ok(())
+    }
+
+    /// The brightness of the texel at `uv`, in `[0, 1]`.
+    pub fn value(&self, uv: &Vector2<Float>) -> Float {
+        let texel = self.texel(uv);
+        0.2126 * texel.x + 0.7152 * texel.y + 0.0722 * texel.z
+    }
+
+    /// The texel at `uv`, with each channel in `[0, 1]`. The values are taken as they are, with
+    /// no color decoding, since such textures hold data (e.g. normal vectors or heights) rather
+    /// than colors.
+    pub fn texel(&self, uv: &Vector2<Float>) -> Vector3<Float> {
+        let texel = self.rgb8(uv);
+        Vector3::new(texel[0], texel[1], texel[2]).map(|c| c as Float / 255.)
+    }
+
+    /// The color of the texel at `uv`, decoded from the `encoding` of the image into the
+    /// working color `space`.
+    pub fn color(
+        &self,
+        uv: &Vector2<Float>,
+        space: ColorSpace,
+        encoding: Encoding,
+    ) -> Vector3<Float> {
+        space.decode_texel(self.rgb8(uv), encoding)
+    }
+
+    /// The stored channels of the texel at `uv`. The coordinates wrap around, and `v` goes
+    /// upwards, from the bottom row of the image.
+    fn rgb8(&self, uv: &Vector2<Float>) -> [u8; 3] {
+        let texels = self
+            .texels
+            .as_ref()
+            .expect("the image is loaded with the scene file");
+        let (width, height) = texels.dimensions();
+        let x = (uv.x.rem_euclid(1.) * width as Float) as u32;
+        let y = ((1. - uv.y.rem_euclid(1.)) * height as Float) as u32;
+        texels.get_pixel(x.min(width - 1), y.min(height - 1)).0
+    }
+}
+
+impl fmt::Debug for ImageTexture {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("ImageTexture")
+            .field("path", &self.path)
+            .field(
+                "dimensions",
+                &self.texels.as_ref().map(|texels| texels.dimensions()),
+            )
+            .finish()
+    }
+}
+
+impl<'de> Deserialize<'de> for ImageTexture {
+    fn deserialize<D>(deserializer: D) -> std::result::Result<Self, D::Error>
+    where
+        D: Deserializer<'de>,
+    {
+        Ok(ImageTexture {
+            path: PathBuf::deserialize(deserializer)?,
+            texels: None,
+        })
+    }
+}
+
+impl<'de> Deserialize<'de> for Texture {
+    fn deserialize<D>(deserializer: D) -> std::result::Result<Self, D::Error>
+    where
+        D: Deserializer<'de>,
+    {
+        // Not an untagged enum, so that the errors of patterns (e.g. a missing image) are kept.
+        struct TextureVisitor;
+
+        impl<'de> Visitor<'de> for TextureVisitor {
+            type Value = Texture;
+
+            fn expecting(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+                f.write_str("a number or a texture table")
+            }
+
+            fn visit_f64<E: de::Error>(self, value: f64) -> std::result::Result<Texture, E> {
+                Ok(Texture::Constant(value as Float))
+            }
+
+            fn visit_i64<E: de::Error>(self, value: i64) -> std::result::Result<Texture, E> {
+                Ok(Texture::Constant(value as Float))
+            }
+
+            fn visit_u64<E: de::Error>(self, value: u64) -> std::result::Result<Texture, E> {
+                Ok(Texture::Constant(value as Float))
+            }
+
+            fn visit_map<M: MapAccess<'de>>(
+                self,
+                map: M,
+            ) -> std::result::Result<Texture, M::Error> {
+                Pattern::deserialize(de::value::MapAccessDeserializer::new(map))
+                    .map(Texture::Pattern)
+            }
+        }
+
+        deserializer.deserialize_any(TextureVisitor)
+    }
+}
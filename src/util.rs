@@ -1,22 +1,23 @@
-use nalgebra::Vector3;
+use crate::float::{consts, Float};
+use nalgebra::{Point3, Vector2, Vector3};
 use std::cmp::Ordering;
 
 /// Length of a vector.
-pub fn length(v: &Vector3<f64>) -> f64 {
+pub fn length(v: &Vector3<Float>) -> Float {
     v.dot(v).sqrt()
 }
 
 /// [Reflection](https://upload.wikimedia.org/wikipedia/commons/1/10/Reflection_angles.svg)
-pub fn reflection(v: &Vector3<f64>, normal: &Vector3<f64>) -> Vector3<f64> {
+pub fn reflection(v: &Vector3<Float>, normal: &Vector3<Float>) -> Vector3<Float> {
     v - 2. * v.dot(normal) * normal
 }
 
 /// [Refraction](https://en.wikipedia.org/wiki/Refraction#/media/File:RefractionReflextion.svg)
 pub fn refraction(
-    v: &Vector3<f64>,
-    normal: &Vector3<f64>,
-    refractive_index_ratio: f64,
-) -> Option<Vector3<f64>> {
+    v: &Vector3<Float>,
+    normal: &Vector3<Float>,
+    refractive_index_ratio: Float,
+) -> Option<Vector3<Float>> {
     let dt = v.normalize().dot(normal);
     let discriminant = 1. - refractive_index_ratio.powf(2.) * (1. - dt.powf(2.));
 
@@ -31,7 +32,89 @@ pub fn refraction(
 }
 
 /// [Schlick's approximation](https://en.wikipedia.org/wiki/Schlick%27s_approximation)
-pub fn schlick(cosine: f64, refractive_index: f64) -> f64 {
+pub fn schlick(cosine: Float, refractive_index: Float) -> Float {
     let r = ((1. - refractive_index) / (1. + refractive_index)).powf(2.);
     r + (1. - r) * (1. - cosine).powf(5.)
 }
+
+/// A bound on the relative error of the result of `n` floating point operations (Higham's
+/// `γₙ`), with which the intersection routines bound the errors of their hit points.
+pub fn gamma(n: u32) -> Float {
+    let e = n as Float * Float::EPSILON * 0.5;
+    e / (1. - e)
+}
+
+/// The origin of a ray which leaves the point `p` of a surface towards `direction`, when each
+/// coordinate of `p` may be off by up to `p_error`. The origin is moved along the `normal` of the
+/// surface, to the side which the ray goes to, just out of the box of possible positions of `p`,
+/// so that the ray does not hit the surface again, however large or small the scene is.
+pub fn offset_ray_origin(
+    p: &Point3<Float>,
+    p_error: &Vector3<Float>,
+    normal: &Vector3<Float>,
+    direction: &Vector3<Float>,
+) -> Point3<Float> {
+    let mut offset = normal.abs().dot(p_error) * normal;
+    if direction.dot(normal) < 0. {
+        offset = -offset;
+    }
+    let mut origin = p + offset;
+    // Round away from `p`, so that rounding the sum does not undo the offset.
+    for axis in 0..3 {
+        if offset[axis] > 0. {
+            origin[axis] = origin[axis].next_up();
+        } else if offset[axis] < 0. {
+            origin[axis] = origin[axis].next_down();
+        }
+    }
+    origin
+}
+
+/// Maps a point of the unit square onto the unit disk, keeping the area uniform and the
+/// neighbourhoods intact (Shirley and Chiu's concentric mapping), so that well distributed
+/// samples stay well distributed.
+pub fn concentric_disk(u: Vector2<Float>) -> Vector2<Float> {
+    let offset = 2. * u - Vector2::new(1., 1.);
+    if offset.x == 0. && offset.y == 0. {
+        return Vector2::zeros();
+    }
+
+    let (r, theta) = if offset.x.abs() > offset.y.abs() {
+        (offset.x, consts::FRAC_PI_4 * (offset.y / offset.x))
+    } else {
+        (
+            offset.y,
+            consts::FRAC_PI_2 - consts::FRAC_PI_4 * (offset.x / offset.y),
+        )
+    };
+    r * Vector2::new(theta.cos(), theta.sin())
+}
+
+/// Maps a point of the unit square and a value in `[0, 1)` onto a uniformly distributed point
+/// inside the unit sphere: the square gives the direction and `w` the distance from the center.
+pub fn uniform_ball(u: Vector2<Float>, w: Float) -> Vector3<Float> {
+    let z = 1. - 2. * u.x;
+    let r = (1. - z * z).max(0.).sqrt();
+    let phi = 2. * consts::PI * u.y;
+    w.cbrt() * Vector3::new(r * phi.cos(), r * phi.sin(), z)
+}
+
+/// Two unit vectors which, together with the unit vector `n`, form an orthonormal basis
+/// (Duff et al.'s branchless construction).
+pub fn orthonormal_basis(n: &Vector3<Float>) -> (Vector3<Float>, Vector3<Float>) {
+    let sign = (1. as Float).copysign(n.z);
+    let a = -1. / (sign + n.z);
+    let b = n.x * n.y * a;
+    (
+        Vector3::new(1. + sign * n.x * n.x * a, sign * b, -sign * n.x),
+        Vector3::new(b, sign + n.y * n.y * a, -n.y),
+    )
+}
+
+/// Maps a point of the unit square onto the hemisphere around `+z`, with a density
+/// proportional to the cosine of the angle to `+z` (Malley's method).
+pub fn cosine_hemisphere(u: Vector2<Float>) -> Vector3<Float> {
+    let d = concentric_disk(u);
+    let z = (1. - d.x * d.x - d.y * d.y).max(0.).sqrt();
+    Vector3::new(d.x, d.y, z)
+}
@@ -0,0 +1,375 @@
+//! Checks of scenes for mistakes which would spoil the image without stopping the render, e.g.
+//! spheres sinking into each other or materials which create light out of nothing.
+
+use crate::float::Float;
+use crate::hitable::Hitable;
+use crate::material::Material;
+use crate::mesh::Mesh;
+use crate::ray::Ray;
+use crate::sampler::SamplerKind;
+use crate::scene::Scene;
+use crate::sphere::Sphere;
+use crate::util;
+use nalgebra::{Point3, Vector3};
+use std::fmt;
+
+/// How bad an issue is. Warnings point at likely mistakes; errors make the render meaningless,
+/// so scenes with errors are not rendered.
+#[derive(Copy, Clone, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Severity {
+    Warning,
+    Error,
+}
+
+/// The part of a scene which an issue is about. Spheres and meshes are numbered from `0`, in the
+/// order in which they were added to the scene (i.e. the order of the `[[spheres]]` and the
+/// `[[meshes]]` of a scene file; see `SceneFile::describe`).
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum Object {
+    Camera,
+    Sphere(usize),
+    Mesh(usize),
+}
+
+/// A mistake found in a scene.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Issue {
+    pub severity: Severity,
+    pub object: Object,
+    pub message: String,
+}
+
+/// Checks the scene for:
+///
+/// * spheres whose radius is not a number or infinite (errors), negative (which turns them inside
+///   out) or `0`;
+/// * spheres which overlap or lie inside each other; spheres with negative radii are left out,
+///   since they are meant to be put inside others (e.g. hollow glass);
+/// * triangles with corners which are not finite (errors) or without area;
+/// * materials which reflect more than all of the light (attenuations above `1`) or whose
+///   refractive index is below `1`; negative attenuations and refractive indices which are not
+///   positive are errors;
+/// * a camera inside a sphere or a closed mesh.
+///
+/// The issues are ordered by object: spheres, meshes and then the camera.
+pub fn validate(scene: &Scene) -> Vec<Issue> {
+    let mut issues = vec![];
+    for (index, sphere) in scene.objects().iter().enumerate() {
+        check_sphere(sphere, &mut |severity, message| {
+            issues.push(Issue {
+                severity,
+                object: Object::Sphere(index),
+                message,
+            })
+        });
+    }
+    for (first, second, message) in overlaps(scene.objects()) {
+        issues.push(Issue {
+            severity: Severity::Warning,
+            object: Object::Sphere(first),
+            message: format!("{} sphere #{}", message, second),
+        });
+    }
+    for (index, mesh) in scene.meshes().iter().enumerate() {
+        check_mesh(mesh, &mut |severity, message| {
+            issues.push(Issue {
+                severity,
+                object: Object::Mesh(index),
+                message,
+            })
+        });
+    }
+    check_camera(scene, &mut issues);
+    issues.sort_by_key(|issue| match issue.object {
+        Object::Sphere(index) => (0, index),
+        Object::Mesh(index) => (1, index),
+        Object::Camera => (2, 0),
+    });
+    issues
+}
+
+fn check_sphere(sphere: &Sphere, report: &mut dyn FnMut(Severity, String)) {
+    let radius = sphere.radius();
+    if !sphere.center().iter().all(|x| x.is_finite()) {
+        report(
+            Severity::Error,
+            format!("the center {} is not finite", point(sphere.center())),
+        );
+    }
+    if !radius.is_finite() {
+        report(
+            Severity::Error,
+            format!("the radius {} is not a finite number", radius),
+        );
+    } else if radius < 0. {
+        report(
+            Severity::Warning,
+            format!(
+                "the radius {} is negative, which turns the sphere inside out",
+                radius
+            ),
+        );
+    } else if radius == 0. {
+        report(
+            Severity::Warning,
+            String::from("the radius is 0, so the sphere cannot be seen"),
+        );
+    }
+    check_material(sphere.material(), "material", report);
+}
+
+/// The pairs of spheres with positive radii which overlap, by increasing first index, with a
+/// description of how the first relates to the second. Spheres which merely touch (e.g. the
+/// spheres resting on the ground of the random scene) do not overlap.
+fn overlaps(spheres: &[Sphere]) -> Vec<(usize, usize, String)> {
+    let valid = |sphere: &Sphere| {
+        sphere.radius() > 0.
+            && sphere.radius().is_finite()
+            && sphere.center().iter().all(|x| x.is_finite())
+    };
+    // Sweep along the x axis, so that only the spheres whose extents overlap on it are compared.
+    let mut order: Vec<usize> = (0..spheres.len()).filter(|&i| valid(&spheres[i])).collect();
+    order.sort_by(|&i, &j| {
+        let start = |i: usize| spheres[i].x() - spheres[i].radius();
+        start(i).total_cmp(&start(j))
+    });
+
+    let mut overlaps = vec![];
+    for (k, &i) in order.iter().enumerate() {
+        let a = &spheres[i];
+        for &j in &order[k + 1..] {
+            let b = &spheres[j];
+            if b.x() - b.radius() > a.x() + a.radius() {
+                break;
+            }
+            let distance = util::length(&(b.center() - a.center()));
+            let depth = a.radius() + b.radius() - distance;
+            // The rounding errors of the positions of touching spheres.
+            let tolerance = util::gamma(64)
+                * (distance + a.radius() + b.radius() + a.center().coords.abs().max());
+            if depth <= tolerance {
+                continue;
+            }
+            let (first, second) = (i.min(j), i.max(j));
+            let (small, large) = if a.radius() < b.radius() {
+                (i, j)
+            } else {
+                (j, i)
+            };
+            let message = if distance + spheres[small].radius() <= spheres[large].radius() {
+                if small == first {
+                    String::from("lies inside")
+                } else {
+                    String::from("contains")
+                }
+            } else {
+                format!("sinks {} deep into", depth)
+            };
+            overlaps.push((first, second, message));
+        }
+    }
+    overlaps.sort_by_key(|&(first, second, _)| (first, second));
+    overlaps
+}
+
+fn check_mesh(mesh: &Mesh, report: &mut dyn FnMut(Severity, String)) {
+    let geometry = mesh.geometry();
+    let mut degenerate = vec![];
+    for index in 0..geometry.triangle_count() {
+        let [a, b, c] = geometry.triangle(index);
+        if ![a, b, c].iter().all(|p| p.iter().all(|x| x.is_finite())) {
+            report(
+                Severity::Error,
+                format!("triangle #{} has a corner which is not finite", index),
+            );
+        } else if (b - a).cross(&(c - a)) == Vector3::zeros() {
+            degenerate.push(index);
+        }
+    }
+    if let Some(first) = degenerate.first() {
+        report(
+            Severity::Warning,
+            format!(
+                "{} of its {} triangles have no area (the first one is triangle #{})",
+                degenerate.len(),
+                geometry.triangle_count(),
+                first
+            ),
+        );
+    }
+    check_material(mesh.material(), "material", report);
+}
+
+/// Checks the attenuations and the refractive indices of the material and of the materials it
+/// is made of. `path` is the name of the material in the scene file, e.g. `material.base`.
+fn check_material(material: &Material, path: &str, report: &mut dyn FnMut(Severity, String)) {
+    let mut attenuation = |name: &str, value: &Vector3<Float>| {
+        let name = format!("{}.{}", path, name);
+        if value.iter().any(|x| x.is_nan() || *x < 0.) {
+            report(
+                Severity::Error,
+                format!("`{}` {} is negative or not a number", name, vector(value)),
+            );
+        } else if value.iter().any(|x| *x > 1.) {
+            report(
+                Severity::Warning,
+                format!(
+                    "`{}` {} is above 1, so it reflects more light than it receives",
+                    name,
+                    vector(value)
+                ),
+            );
+        }
+    };
+    match material {
+        Material::Lambertian { attenuation: value }
+        | Material::Metal {
+            attenuation: value, ..
+        }
+        | Material::Microfacet {
+            attenuation: value, ..
+        } => attenuation("attenuation", value),
+        Material::Subsurface { albedo, .. } => attenuation("albedo", albedo),
+        _ => {}
+    }
+
+    let mut refractive_index = |name: &str, value: Float| {
+        let name = format!("{}.{}", path, name);
+        if value.partial_cmp(&0.) != Some(std::cmp::Ordering::Greater) {
+            report(
+                Severity::Error,
+                format!("`{}` {} is not positive", name, value),
+            );
+        } else if value < 1. {
+            report(
+                Severity::Warning,
+                format!(
+                    "`{}` {} is below 1, the refractive index of vacuum",
+                    name, value
+                ),
+            );
+        }
+    };
+    match material {
+        Material::Dielectric {
+            refractive_index: value,
+            ..
+        }
+        | Material::Subsurface {
+            refractive_index: value,
+            ..
+        }
+        | Material::ClearCoat {
+            refractive_index: value,
+            ..
+        } => refractive_index("refractive_index", *value),
+        _ => {}
+    }
+    if let Material::Microfacet {
+        thin_film: Some(film),
+        ..
+    }
+    | Material::Dielectric {
+        thin_film: Some(film),
+        ..
+    } = material
+    {
+        refractive_index("thin_film.refractive_index", film.refractive_index);
+    }
+
+    let nested = |name: &str| format!("{}.{}", path, name);
+    match material {
+        Material::Mix { first, second, .. } => {
+            check_material(first, &nested("first"), report);
+            check_material(second, &nested("second"), report);
+        }
+        Material::TwoSided { front, back } => {
+            check_material(front, &nested("front"), report);
+            check_material(back, &nested("back"), report);
+        }
+        Material::ClearCoat { base, .. }
+        | Material::Bump { base, .. }
+        | Material::NormalMap { base, .. }
+        | Material::Cutout { base, .. }
+        | Material::Textured { base, .. } => check_material(base, &nested("base"), report),
+        _ => {}
+    }
+}
+
+/// Reports the spheres and the closed meshes which contain the camera, as seen from the center
+/// of the image. A camera is inside a mesh if the rays along all the axes hit it from behind.
+fn check_camera(scene: &Scene, issues: &mut Vec<Issue>) {
+    let mut sampler = SamplerKind::Independent.create(1, 0);
+    let origin = match scene.camera().get_ray(0.5, 0.5, sampler.as_mut()) {
+        Some(ray) => *ray.origin(),
+        None => return,
+    };
+    if !origin.iter().all(|x| x.is_finite()) {
+        issues.push(Issue {
+            severity: Severity::Error,
+            object: Object::Camera,
+            message: format!("the position {} is not finite", point(&origin)),
+        });
+        return;
+    }
+
+    let inside = String::from("the camera is inside it");
+    for (index, sphere) in scene.objects().iter().enumerate() {
+        if sphere.radius() > 0. && util::length(&(origin - sphere.center())) < sphere.radius() {
+            issues.push(Issue {
+                severity: Severity::Warning,
+                object: Object::Sphere(index),
+                message: inside.clone(),
+            });
+        }
+    }
+
+    let axes = [Vector3::x(), Vector3::y(), Vector3::z()];
+    let directions = axes.iter().flat_map(|axis| [*axis, -axis]);
+    for (index, mesh) in scene.meshes().iter().enumerate() {
+        let hits_from_behind = directions.clone().all(|direction| {
+            mesh.hit(&Ray::new(origin, direction), 0., Float::INFINITY)
+                .is_some_and(|hitpoint| hitpoint.geometric_normal.dot(&direction) > 0.)
+        });
+        if hits_from_behind {
+            issues.push(Issue {
+                severity: Severity::Warning,
+                object: Object::Mesh(index),
+                message: inside.clone(),
+            });
+        }
+    }
+}
+
+fn point(p: &Point3<Float>) -> String {
+    vector(&p.coords)
+}
+
+fn vector(v: &Vector3<Float>) -> String {
+    format!("[{}, {}, {}]", v.x, v.y, v.z)
+}
+
+impl fmt::Display for Severity {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Severity::Warning => write!(f, "warning"),
+            Severity::Error => write!(f, "error"),
+        }
+    }
+}
+
+impl fmt::Display for Object {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Object::Camera => write!(f, "camera"),
+            Object::Sphere(index) => write!(f, "sphere #{}", index),
+            Object::Mesh(index) => write!(f, "mesh #{}", index),
+        }
+    }
+}
+
+impl fmt::Display for Issue {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}: {}: {}", self.severity, self.object, self.message)
+    }
+}
@@ -0,0 +1,85 @@
+//! Named meshes are animated like spheres, and also rotated: their keyframed rotations turn
+//! them around the origin of the model, at constant angular speed.
+
+use nalgebra::{Point3, Vector3};
+use rtow::float::Float;
+use rtow::{Scene, SceneFile};
+use std::fs;
+use std::path::Path;
+
+#[cfg(not(feature = "f32"))]
+const TOLERANCE: Float = 1e-9;
+#[cfg(feature = "f32")]
+const TOLERANCE: Float = 1e-4;
+
+/// A turntable: a cube standing at `[0, 0, -4]` which turns once around the y axis in 48 frames,
+/// moving up by one unit and doubling its size on the way.
+fn turntable() -> SceneFile {
+    let directory = Path::new(env!("CARGO_TARGET_TMPDIR")).join("animation");
+    fs::create_dir_all(&directory).expect("the target directory is writable");
+    fs::copy("tests/scenes/cube.obj", directory.join("cube.obj")).expect("the cube exists");
+    let scene = directory.join("turntable.toml");
+    fs::write(
+        &scene,
+        r#"
+        [[meshes]]
+        name = "box"
+        path = "cube.obj"
+        translation = [0, 0, -4]
+        material = { type = "lambertian", attenuation = [0.5, 0.5, 0.5] }
+
+        [[animation.objects]]
+        object = "box"
+        keys = [
+            { frame = 0, rotation = [0, 0, 0], translation = [0, 0, 0], scale = 1 },
+            { frame = 16, rotation = [0, 120, 0] },
+            { frame = 32, rotation = [0, 240, 0] },
+            { frame = 48, rotation = [0, 360, 0], translation = [0, 1, 0], scale = 2 },
+        ]
+        "#,
+    )
+    .expect("the target directory is writable");
+    SceneFile::load(&scene).unwrap_or_else(|err| panic!("{}", err))
+}
+
+fn scene_at(file: &SceneFile, frame: Float) -> Scene {
+    file.at_frame(frame)
+        .into_scene(None, &Default::default(), 1.)
+        .unwrap()
+}
+
+/// The corners of the first triangle of the cube.
+fn first_triangle(scene: &Scene) -> [Point3<Float>; 3] {
+    scene.meshes()[0].geometry().triangle(0)
+}
+
+#[test]
+fn meshes_follow_their_keyframes() {
+    let file = turntable();
+    let still = first_triangle(&scene_at(&file, 0.));
+    let center = Vector3::new(0., 0., -4.);
+
+    for frame in [0., 6., 16., 24., 40., 48.] {
+        // The rotation turns at constant speed through the keyframes, and a full turn ends where
+        // it started.
+        let angle = (frame * 7.5 as Float).to_radians();
+        let (sin, cos) = angle.sin_cos();
+        let t = frame / 48.;
+        let expected = still.map(|p| {
+            let p = p - center;
+            let turned = Vector3::new(cos * p.x + sin * p.z, p.y, -sin * p.x + cos * p.z);
+            Point3::from(center + Vector3::new(0., t, 0.) + (1. + t) * turned)
+        });
+
+        let actual = first_triangle(&scene_at(&file, frame));
+        for (actual, expected) in actual.iter().zip(&expected) {
+            assert!(
+                (actual - expected).amax() <= TOLERANCE,
+                "frame {}: {} is not at {}",
+                frame,
+                actual,
+                expected
+            );
+        }
+    }
+}
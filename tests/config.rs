@@ -0,0 +1,56 @@
+//! The command line accepts negative numbers wherever values can be negative.
+
+use clap::Parser;
+use nalgebra::{Point3, Vector2, Vector3};
+use rtow::camera::Focus;
+use rtow::config::Args;
+
+fn parse(args: &[&str]) -> Args {
+    Args::try_parse_from(std::iter::once("rtow").chain(args.iter().copied()))
+        .unwrap_or_else(|err| panic!("{:?} cannot be parsed: {}", args, err))
+}
+
+#[test]
+fn camera_vectors_can_start_with_a_negative_coordinate() {
+    let args = parse(&[
+        "--look-from",
+        "-2,1.7,0",
+        "--look-at",
+        "-1,-1,-8",
+        "--up",
+        "-0,1,0",
+        "--samples",
+        "4",
+    ]);
+    assert_eq!(args.look_from, Some(Point3::new(-2., 1.7, 0.)));
+    assert_eq!(args.look_at, Some(Point3::new(-1., -1., -8.)));
+    assert_eq!(args.up, Some(Vector3::new(0., 1., 0.)));
+    assert_eq!(args.samples, Some(4));
+}
+
+#[test]
+fn lens_movements_can_be_negative() {
+    let args = parse(&[
+        "--tilt",
+        "-5",
+        "--shift",
+        "-0.1,0.2",
+        "--blade-rotation",
+        "-30",
+    ]);
+    assert_eq!(args.tilt, Some(-5.));
+    assert_eq!(args.shift, Some(Vector2::new(-0.1, 0.2)));
+    assert_eq!(args.blade_rotation, Some(-30.));
+}
+
+#[test]
+fn focus_points_can_start_with_a_negative_coordinate() {
+    let args = parse(&["--focus", "-2,1,-8"]);
+    assert_eq!(args.focus, Some(Focus::Point(Point3::new(-2., 1., -8.))));
+}
+
+#[test]
+fn malformed_vectors_are_rejected() {
+    let result = Args::try_parse_from(["rtow", "--look-from", "-2,1.7"]);
+    assert!(result.is_err());
+}
@@ -0,0 +1,424 @@
+//! White furnace tests: every material, made as white as it can be, is lit from all directions
+//! by the same light, so it must reflect (and transmit) at most all of it. Lossless materials
+//! must give back exactly all of it. Materials without delta lobes must also sample directions
+//! consistently with the values and densities they report.
+
+use nalgebra::{Point3, Unit, Vector2, Vector3};
+use rtow::color::{ColorSpace, Encoding};
+use rtow::float::{consts, Float};
+use rtow::hitable::HitPoint;
+use rtow::material::{Bsdf, Material};
+use rtow::sampler::{Sampler, SamplerKind};
+use rtow::spectrum::{Conductor, ThinFilm, Wavelengths};
+use rtow::texture::{ImageTexture, Texture};
+use std::collections::HashSet;
+use std::path::Path;
+
+const SAMPLES: usize = 20_000;
+
+/// The statistical error allowed for the estimates of the albedo.
+const TOLERANCE: Float = 0.02;
+
+/// How much of the light a material gives back.
+#[derive(Copy, Clone, Debug)]
+enum Expected {
+    /// All of it.
+    Lossless,
+    /// At most all of it, and at least the given share.
+    AtLeast(Float),
+}
+
+struct Case {
+    name: &'static str,
+    material: Material,
+    expected: Expected,
+    spectral: bool,
+}
+
+fn white() -> Vector3<Float> {
+    Vector3::new(1., 1., 1.)
+}
+
+fn lambertian() -> Box<Material> {
+    Box::new(Material::Lambertian {
+        attenuation: white(),
+    })
+}
+
+/// A normal map which leaves the normal vector as it is.
+fn flat_normal_map() -> ImageTexture {
+    let path = Path::new(env!("CARGO_TARGET_TMPDIR")).join("flat_normal_map.png");
+    image::RgbImage::from_pixel(1, 1, image::Rgb([128, 128, 255]))
+        .save(&path)
+        .expect("the target directory is writable");
+    ImageTexture::load(&path).expect("the normal map was just written")
+}
+
+/// A white image, which leaves the colors of a `Textured` material unchanged.
+fn white_image() -> ImageTexture {
+    let path = Path::new(env!("CARGO_TARGET_TMPDIR")).join("white.png");
+    image::RgbImage::from_pixel(1, 1, image::Rgb([255, 255, 255]))
+        .save(&path)
+        .expect("the target directory is writable");
+    ImageTexture::load(&path).expect("the image was just written")
+}
+
+fn cases() -> Vec<Case> {
+    let case = |name, material, expected| Case {
+        name,
+        material,
+        expected,
+        spectral: false,
+    };
+    vec![
+        case("lambertian", *lambertian(), Expected::Lossless),
+        case(
+            "mirror",
+            Material::Metal {
+                attenuation: white(),
+                fuzziness: 0.,
+                conductor: None,
+            },
+            Expected::Lossless,
+        ),
+        case(
+            "fuzzy metal",
+            Material::Metal {
+                attenuation: white(),
+                fuzziness: 0.5,
+                conductor: None,
+            },
+            Expected::AtLeast(0.5),
+        ),
+        case(
+            "gold",
+            Material::Metal {
+                attenuation: white(),
+                fuzziness: 0.2,
+                conductor: Some(Conductor::Gold),
+            },
+            Expected::AtLeast(0.3),
+        ),
+        case(
+            "smooth microfacet",
+            Material::Microfacet {
+                attenuation: white(),
+                roughness: 0.,
+                anisotropy: 0.,
+                conductor: None,
+                thin_film: None,
+            },
+            Expected::Lossless,
+        ),
+        case(
+            "brushed microfacet",
+            Material::Microfacet {
+                attenuation: white(),
+                roughness: 0.5,
+                anisotropy: 0.8,
+                conductor: None,
+                thin_film: None,
+            },
+            Expected::AtLeast(0.6),
+        ),
+        case(
+            "iridescent microfacet",
+            Material::Microfacet {
+                attenuation: white(),
+                roughness: 0.3,
+                anisotropy: 0.,
+                conductor: Some(Conductor::Silver),
+                thin_film: Some(ThinFilm {
+                    thickness: 300.,
+                    refractive_index: 1.5,
+                }),
+            },
+            Expected::AtLeast(0.3),
+        ),
+        case(
+            "glass",
+            Material::Dielectric {
+                refractive_index: 1.5,
+                abbe_number: None,
+                thin_film: None,
+            },
+            Expected::Lossless,
+        ),
+        Case {
+            name: "dispersive glass",
+            material: Material::Dielectric {
+                refractive_index: 1.6,
+                abbe_number: Some(20.),
+                thin_film: None,
+            },
+            expected: Expected::Lossless,
+            spectral: true,
+        },
+        Case {
+            name: "soap bubble",
+            material: Material::Dielectric {
+                refractive_index: 1.,
+                abbe_number: None,
+                thin_film: Some(ThinFilm {
+                    thickness: 500.,
+                    refractive_index: 1.33,
+                }),
+            },
+            expected: Expected::Lossless,
+            spectral: true,
+        },
+        case(
+            "subsurface",
+            Material::Subsurface {
+                refractive_index: 1.4,
+                mean_free_path: Vector3::new(0.1, 0.1, 0.1),
+                albedo: white(),
+                anisotropy: 0.,
+            },
+            Expected::Lossless,
+        ),
+        case(
+            "mix",
+            Material::Mix {
+                first: lambertian(),
+                second: Box::new(Material::Metal {
+                    attenuation: white(),
+                    fuzziness: 0.,
+                    conductor: None,
+                }),
+                weight: Texture::Constant(0.3),
+            },
+            Expected::Lossless,
+        ),
+        case(
+            "clear coat",
+            Material::ClearCoat {
+                base: lambertian(),
+                refractive_index: 1.5,
+            },
+            Expected::AtLeast(0.8),
+        ),
+        case(
+            "two sided",
+            Material::TwoSided {
+                front: lambertian(),
+                back: lambertian(),
+            },
+            Expected::Lossless,
+        ),
+        case(
+            "bump",
+            Material::Bump {
+                base: lambertian(),
+                height: Texture::Constant(0.5),
+                scale: 1.,
+            },
+            Expected::Lossless,
+        ),
+        case(
+            "normal map",
+            Material::NormalMap {
+                base: lambertian(),
+                map: flat_normal_map(),
+            },
+            Expected::Lossless,
+        ),
+        case(
+            "cutout",
+            Material::Cutout {
+                base: lambertian(),
+                opacity: Texture::Constant(0.5),
+            },
+            Expected::Lossless,
+        ),
+        case(
+            "textured",
+            Material::Textured {
+                base: lambertian(),
+                image: white_image(),
+                encoding: Encoding::Srgb,
+            },
+            Expected::Lossless,
+        ),
+    ]
+}
+
+/// The name of each variant, so that every new variant needs a furnace test.
+fn variant(material: &Material) -> &'static str {
+    match material {
+        Material::Lambertian { .. } => "Lambertian",
+        Material::Metal { .. } => "Metal",
+        Material::Microfacet { .. } => "Microfacet",
+        Material::Dielectric { .. } => "Dielectric",
+        Material::Subsurface { .. } => "Subsurface",
+        Material::Mix { .. } => "Mix",
+        Material::ClearCoat { .. } => "ClearCoat",
+        Material::TwoSided { .. } => "TwoSided",
+        Material::Bump { .. } => "Bump",
+        Material::NormalMap { .. } => "NormalMap",
+        Material::Cutout { .. } => "Cutout",
+        Material::Textured { .. } => "Textured",
+    }
+}
+
+const VARIANTS: usize = 12;
+
+/// A hit point on the plane `z = 0`, seen from above.
+fn hitpoint(material: &Material) -> HitPoint<'_> {
+    HitPoint {
+        t: 1.,
+        p: Point3::origin(),
+        p_error: Vector3::zeros(),
+        normal: Unit::new_unchecked(Vector3::z()),
+        geometric_normal: Unit::new_unchecked(Vector3::z()),
+        uv: Vector2::new(0.3, 0.6),
+        dpdu: Vector3::x(),
+        dpdv: Vector3::y(),
+        material,
+    }
+}
+
+/// The directions towards the viewer, from above the surface and, for two sided materials, also
+/// from below.
+fn viewing_directions(material: &Material) -> Vec<Vector3<Float>> {
+    let below = matches!(material, Material::TwoSided { .. });
+    [0.1, 0.7, 1.3, 2.4]
+        .iter()
+        .filter(|&&theta| below || theta < consts::FRAC_PI_2)
+        .map(|&theta: &Float| Vector3::new(theta.sin(), 0.3 * theta.sin(), theta.cos()).normalize())
+        .collect()
+}
+
+fn wavelengths(spectral: bool, sampler: &mut dyn Sampler) -> Wavelengths {
+    if spectral {
+        Wavelengths::sample(sampler.get_1d(), ColorSpace::default())
+    } else {
+        Wavelengths::Rgb {
+            space: ColorSpace::default(),
+        }
+    }
+}
+
+/// The average of the lanes in use.
+fn average(wavelengths: &Wavelengths, weight: &rtow::spectrum::SampledSpectrum) -> Float {
+    let lanes = wavelengths.lanes();
+    (0..lanes).map(|i| weight[i]).sum::<Float>() / lanes as Float
+}
+
+/// The share of the light arriving from all directions which leaves towards `wo`, estimated by
+/// sampling the BSDF.
+fn sampled_albedo(case: &Case, wo: &Vector3<Float>, sampler: &mut dyn Sampler) -> Float {
+    let hitpoint = hitpoint(&case.material);
+    let mut total = 0.;
+    for _ in 0..SAMPLES {
+        let mut wavelengths = wavelengths(case.spectral, sampler);
+        if let Some(sample) = case
+            .material
+            .sample(wo, &hitpoint, &mut wavelengths, sampler)
+        {
+            total += average(&wavelengths, &sample.weight(&hitpoint.normal));
+        }
+    }
+    total / SAMPLES as Float
+}
+
+/// The same share, estimated by integrating `eval` over the sphere of directions, stratified in
+/// `z` and in the azimuth. Only meaningful for materials without delta lobes.
+fn integrated_albedo(case: &Case, wo: &Vector3<Float>, sampler: &mut dyn Sampler) -> Float {
+    const STRATA: usize = 200;
+    let hitpoint = hitpoint(&case.material);
+    let mut total = 0.;
+    for i in 0..STRATA {
+        for j in 0..STRATA {
+            let u = sampler.get_2d();
+            let z = 1. - 2. * (i as Float + u.x) / STRATA as Float;
+            let r = (1. - z * z).sqrt();
+            let phi = 2. * consts::PI * (j as Float + u.y) / STRATA as Float;
+            let wi = Vector3::new(r * phi.cos(), r * phi.sin(), z);
+            let wavelengths = Wavelengths::Rgb {
+                space: ColorSpace::default(),
+            };
+            let value = case.material.eval(wo, &wi, &hitpoint, &wavelengths);
+            total += average(&wavelengths, &value) * z.abs();
+        }
+    }
+    total * 4. * consts::PI / (STRATA * STRATA) as Float
+}
+
+#[test]
+fn every_variant_is_tested() {
+    let tested: HashSet<&str> = cases().iter().map(|case| variant(&case.material)).collect();
+    assert_eq!(tested.len(), VARIANTS);
+}
+
+#[test]
+fn white_furnace() {
+    let mut sampler = SamplerKind::Independent.create(1, 42);
+    for case in cases() {
+        for wo in viewing_directions(&case.material) {
+            let albedo = sampled_albedo(&case, &wo, sampler.as_mut());
+            match case.expected {
+                Expected::Lossless => assert!(
+                    (albedo - 1.).abs() <= TOLERANCE,
+                    "{} gives back {:.4} of the light seen from {:?}",
+                    case.name,
+                    albedo,
+                    wo
+                ),
+                Expected::AtLeast(minimum) => assert!(
+                    albedo <= 1. + TOLERANCE && albedo >= minimum,
+                    "{} gives back {:.4} of the light seen from {:?}",
+                    case.name,
+                    albedo,
+                    wo
+                ),
+            }
+        }
+    }
+}
+
+#[test]
+fn sampling_matches_eval() {
+    let mut sampler = SamplerKind::Independent.create(1, 43);
+    for case in cases().iter().filter(|case| !case.material.is_delta()) {
+        let hitpoint = hitpoint(&case.material);
+        for wo in viewing_directions(&case.material) {
+            // Delta lobes (e.g. of a clear coat) are sampled but not evaluated.
+            let mut has_delta_lobes = false;
+            for _ in 0..100 {
+                let mut wavelengths = Wavelengths::Rgb {
+                    space: ColorSpace::default(),
+                };
+                if let Some(sample) =
+                    case.material
+                        .sample(&wo, &hitpoint, &mut wavelengths, sampler.as_mut())
+                {
+                    has_delta_lobes |= sample.delta;
+                    if !sample.delta {
+                        let pdf = case.material.pdf(&wo, &sample.direction, &hitpoint);
+                        assert!(
+                            (sample.pdf - pdf).abs() <= 1e-6 * pdf.max(1.),
+                            "{}: sampled pdf {} but pdf {}",
+                            case.name,
+                            sample.pdf,
+                            pdf
+                        );
+                    }
+                }
+            }
+            if has_delta_lobes {
+                continue;
+            }
+
+            let sampled = sampled_albedo(case, &wo, sampler.as_mut());
+            let integrated = integrated_albedo(case, &wo, sampler.as_mut());
+            assert!(
+                (sampled - integrated).abs() <= 2. * TOLERANCE,
+                "{}: sampling gives {:.4} but integrating eval gives {:.4}",
+                case.name,
+                sampled,
+                integrated
+            );
+        }
+    }
+}
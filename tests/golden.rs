@@ -0,0 +1,157 @@
+//! Renders small canonical scenes with a fixed seed and compares them against the reference
+//! images in `tests/golden`. The renders are deterministic, so the tolerance only absorbs
+//! floating point differences between platforms; any change of the rendering shows up.
+//!
+//! When a render differs, the render and an amplified difference image are written into
+//! `target/tmp/golden`. After an intended change, regenerate the references with
+//! `RTOW_BLESS=1 cargo test --test golden`.
+
+use image::RgbImage;
+use rtow::camera::CameraSettings;
+use rtow::color::Encoding;
+use rtow::float::Float;
+use rtow::{Renderer, Resolution, Scene, SceneFile};
+use std::env;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+const WIDTH: u32 = 96;
+const HEIGHT: u32 = 54;
+const SAMPLES: u32 = 16;
+const SEED: u64 = 7;
+
+/// The largest root mean square error between a render and its reference, with the channels
+/// in `[0, 1]`.
+const TOLERANCE: Float = 0.01;
+
+fn scene_file(name: &str) -> Scene {
+    let path = format!("tests/scenes/{}.toml", name);
+    SceneFile::load(&path)
+        .and_then(|file| {
+            file.into_scene(
+                None,
+                &CameraSettings::default(),
+                WIDTH as Float / HEIGHT as Float,
+            )
+        })
+        .unwrap_or_else(|err| panic!("cannot load {}: {}", path, err))
+}
+
+fn render(scene: &Scene, spectral: bool) -> RgbImage {
+    Renderer::new(Resolution {
+        width: WIDTH,
+        height: HEIGHT,
+    })
+    .samples(SAMPLES)
+    .seed(SEED)
+    .spectral(spectral)
+    .render(scene)
+    .expect("the render settings are valid")
+    .to_image(Encoding::Srgb)
+}
+
+fn rmse(a: &RgbImage, b: &RgbImage) -> Float {
+    let sum: Float = a
+        .as_raw()
+        .iter()
+        .zip(b.as_raw())
+        .map(|(&x, &y)| ((x as Float - y as Float) / 255.).powi(2))
+        .sum();
+    (sum / a.as_raw().len() as Float).sqrt()
+}
+
+/// The absolute difference of two images, amplified so that small differences are visible.
+fn difference(a: &RgbImage, b: &RgbImage) -> RgbImage {
+    RgbImage::from_fn(a.width(), a.height(), |x, y| {
+        let (p, q) = (a.get_pixel(x, y), b.get_pixel(x, y));
+        image::Rgb(std::array::from_fn(|c| {
+            (p[c].abs_diff(q[c]) as u32 * 8).min(255) as u8
+        }))
+    })
+}
+
+fn output_dir() -> PathBuf {
+    let dir = Path::new(env!("CARGO_TARGET_TMPDIR")).join("golden");
+    fs::create_dir_all(&dir).expect("the target directory is writable");
+    dir
+}
+
+fn check(name: &str, actual: RgbImage) {
+    let reference_path = format!("tests/golden/{}.png", name);
+    if env::var_os("RTOW_BLESS").is_some() {
+        actual
+            .save(&reference_path)
+            .expect("the reference can be written");
+        return;
+    }
+
+    let reference = match image::open(&reference_path) {
+        Ok(reference) => reference.into_rgb8(),
+        Err(err) => panic!(
+            "cannot read {} ({}); run with RTOW_BLESS=1 to create it",
+            reference_path, err
+        ),
+    };
+    assert_eq!(
+        reference.dimensions(),
+        actual.dimensions(),
+        "{} has the wrong size",
+        reference_path
+    );
+
+    let error = rmse(&reference, &actual);
+    if error > TOLERANCE {
+        let dir = output_dir();
+        let actual_path = dir.join(format!("{}.png", name));
+        let diff_path = dir.join(format!("{}.diff.png", name));
+        actual
+            .save(&actual_path)
+            .expect("the render can be written");
+        difference(&reference, &actual)
+            .save(&diff_path)
+            .expect("the difference can be written");
+        panic!(
+            "{} differs from {} (RMSE {:.4} > {}); see {} and {}",
+            name,
+            reference_path,
+            error,
+            TOLERANCE,
+            actual_path.display(),
+            diff_path.display()
+        );
+    }
+}
+
+// The ground of the random scene is smaller in single precision, which changes the scene.
+#[cfg(not(feature = "f32"))]
+#[test]
+fn random_scene() {
+    let scene = Scene::random(
+        40,
+        SEED,
+        &CameraSettings::default(),
+        WIDTH as Float / HEIGHT as Float,
+    )
+    .unwrap();
+    check("random", render(&scene, false));
+}
+
+#[test]
+fn materials() {
+    check("materials", render(&scene_file("materials"), false));
+}
+
+#[test]
+fn spectral() {
+    check("spectral", render(&scene_file("spectral"), true));
+}
+
+#[test]
+fn meshes() {
+    check("meshes", render(&scene_file("meshes"), false));
+}
+
+#[test]
+fn subsurface() {
+    check("subsurface", render(&scene_file("subsurface"), false));
+}
@@ -0,0 +1,161 @@
+//! Rays spawned from hit points must not hit the surface which they leave, at any scale and
+//! however far from the origin the scene is, and must still hit surfaces which are close by.
+
+use nalgebra::{Point3, Vector3};
+use proptest::prelude::*;
+use rtow::float::{consts, Float};
+use rtow::hitable::{HitPoint, Hitable};
+use rtow::material::Material;
+use rtow::mesh::{Geometry, Mesh};
+use rtow::ray::Ray;
+use rtow::sphere::Sphere;
+use std::sync::Arc;
+
+/// How far from the exact distance a hit may be, relative to the size of the objects.
+#[cfg(not(feature = "f32"))]
+const TOLERANCE: Float = 1e-6;
+#[cfg(feature = "f32")]
+const TOLERANCE: Float = 1e-2;
+
+/// The radius of the ground of the random scene in double precision.
+const EARTH_RADIUS: Float = 6.371e6;
+
+fn material() -> Material {
+    Material::Lambertian {
+        attenuation: Vector3::new(0.5, 0.5, 0.5),
+    }
+}
+
+fn cube(size: Float, center: &Point3<Float>) -> Mesh {
+    let mut geometry =
+        Geometry::load_obj("tests/scenes/cube.obj").expect("the test cube can be loaded");
+    geometry.transform(size, &center.coords);
+    Mesh::new(Arc::new(geometry), material())
+}
+
+prop_compose! {
+    fn unit_vector()(z in -1.0..=1.0 as Float, phi in 0.0..2. * consts::PI) -> Vector3<Float> {
+        let r = (1. - z * z).sqrt();
+        Vector3::new(r * phi.cos(), r * phi.sin(), z)
+    }
+}
+
+prop_compose! {
+    /// The size of an object, from a micrometre to a planet, and its center, from next to the
+    /// origin to far away from it.
+    fn placement()(
+        size in -6..7_i32,
+        distance in -3..4_i32,
+        direction in unit_vector(),
+    ) -> (Float, Point3<Float>) {
+        let size = (10. as Float).powi(size);
+        (size, Point3::from(size * (10. as Float).powi(distance) * direction))
+    }
+}
+
+/// A ray from the outside of an object of the given `size` towards its center, slightly off.
+fn ray_towards(
+    size: Float,
+    center: &Point3<Float>,
+    from: &Vector3<Float>,
+    aim: &Vector3<Float>,
+) -> Ray {
+    let origin = center + 3. * size * from;
+    let target = center + 0.2 * size * aim;
+    Ray::new(origin, target - origin)
+}
+
+/// Checks that rays leaving the hit point outwards escape the convex `object`, and that rays
+/// leaving it inwards reach the other side.
+fn check_spawned_rays(
+    object: &dyn Hitable,
+    hitpoint: &HitPoint,
+    direction: &Vector3<Float>,
+) -> Result<(), TestCaseError> {
+    let outward = hitpoint.geometric_normal.into_inner();
+    let direction = if direction.dot(&outward) > 0. {
+        *direction
+    } else {
+        -direction
+    };
+
+    let escaping = hitpoint.spawn_ray(&direction);
+    let hit = object.hit(&escaping, 0., Float::INFINITY);
+    prop_assert!(hit.is_none(), "hit again at t = {}", hit.unwrap().t);
+
+    let entering = hitpoint.spawn_ray(&-direction);
+    let exit = object.hit(&entering, 0., Float::INFINITY);
+    prop_assert!(exit.is_some(), "the ray did not leave the object");
+    let exit = exit.unwrap();
+    prop_assert!(
+        exit.geometric_normal.dot(entering.direction()) > 0.,
+        "the ray hit the surface it entered through again"
+    );
+    Ok(())
+}
+
+proptest! {
+    #[test]
+    fn spheres_are_not_hit_again(
+        (size, center) in placement(),
+        from in unit_vector(),
+        aim in unit_vector(),
+        direction in unit_vector(),
+    ) {
+        let sphere = Sphere::new(center, size, material());
+        let ray = ray_towards(size, &center, &from, &aim);
+        let hitpoint = sphere.hit(&ray, 0., Float::INFINITY);
+        prop_assert!(hitpoint.is_some());
+        check_spawned_rays(&sphere, &hitpoint.unwrap(), &direction)?;
+    }
+
+    #[test]
+    fn meshes_are_not_hit_again(
+        (size, center) in placement(),
+        from in unit_vector(),
+        aim in unit_vector(),
+        direction in unit_vector(),
+    ) {
+        let cube = cube(size, &center);
+        let ray = ray_towards(size, &center, &from, &aim);
+        let hitpoint = cube.hit(&ray, 0., Float::INFINITY);
+        prop_assert!(hitpoint.is_some());
+        check_spawned_rays(&cube, &hitpoint.unwrap(), &direction)?;
+    }
+
+    #[test]
+    fn the_earth_is_not_hit_again(
+        height in 1e-2..1e2 as Float,
+        x in -1e3..1e3 as Float,
+        z in -1e3..1e3 as Float,
+        direction in unit_vector(),
+    ) {
+        // A camera standing on an Earth-sized ground, as in the random scene, sees it at grazing
+        // angles, where the hit points are millions of units away from its center; a fixed
+        // epsilon there leaves shadow acne.
+        let earth = Sphere::new(Point3::new(0., -EARTH_RADIUS, 0.), EARTH_RADIUS, material());
+        let ray = Ray::new(Point3::new(0., height, 0.), Vector3::new(x, -1. - height, z));
+        let hitpoint = earth.hit(&ray, 0., Float::INFINITY);
+        prop_assert!(hitpoint.is_some());
+        check_spawned_rays(&earth, &hitpoint.unwrap(), &direction)?;
+    }
+
+    #[test]
+    fn nearby_surfaces_are_hit(
+        (size, center) in placement(),
+        gap in 1e-3..1.0 as Float,
+        from in unit_vector(),
+    ) {
+        // Two spheres a fraction of their size apart; a ray leaving one towards the other must
+        // hit it, even when the gap is far smaller than any fixed epsilon.
+        let first = Sphere::new(center, size, material());
+        let second = Sphere::new(center + (2. + gap) * size * from, size, material());
+        let ray = Ray::new(center + 3. * size * from, -from);
+        let hitpoint = first.hit(&ray, 0., Float::INFINITY);
+        prop_assert!(hitpoint.is_some());
+        let spawned = hitpoint.unwrap().spawn_ray(&from);
+        let hit = second.hit(&spawned, 0., Float::INFINITY);
+        prop_assert!(hit.is_some(), "the neighbour was missed");
+        prop_assert!((hit.unwrap().t - gap * size).abs() <= TOLERANCE * size);
+    }
+}
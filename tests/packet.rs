@@ -0,0 +1,124 @@
+//! Packets of rays must find exactly the hit points which the rays find one by one, and renders
+//! with and without packets must be identical.
+
+use nalgebra::{Point3, Vector3};
+use proptest::prelude::*;
+use rtow::camera::CameraSettings;
+use rtow::float::{consts, Float};
+use rtow::hitable::Hitable;
+use rtow::material::Material;
+use rtow::mesh::{Geometry, Mesh};
+use rtow::packet::{RayPacket, WIDTH};
+use rtow::ray::Ray;
+use rtow::sphere::Sphere;
+use rtow::{Renderer, Resolution, Scene};
+use std::sync::Arc;
+
+fn material() -> Material {
+    Material::Lambertian {
+        attenuation: Vector3::new(0.5, 0.5, 0.5),
+    }
+}
+
+prop_compose! {
+    fn unit_vector()(z in -1.0..=1.0 as Float, phi in 0.0..2. * consts::PI) -> Vector3<Float> {
+        let r = (1. - z * z).sqrt();
+        Vector3::new(r * phi.cos(), r * phi.sin(), z)
+    }
+}
+
+prop_compose! {
+    /// A ray from somewhere around a unit object at the origin towards a point near it, so that
+    /// it may or may not hit it.
+    fn ray()(
+        from in unit_vector(),
+        distance in 1.5..10.0 as Float,
+        aim in unit_vector(),
+        offset in 0.0..1.5 as Float,
+    ) -> Ray {
+        let origin = Point3::from(distance * from);
+        Ray::new(origin, offset * aim - origin.coords)
+    }
+}
+
+/// A packet of the rays, with the lanes of the `None`s inactive.
+fn packet(rays: &[Option<Ray>]) -> RayPacket {
+    RayPacket::new(&rays.to_vec().try_into().expect("one ray per lane"))
+}
+
+/// Checks that `object` gives the same hit point to each ray of the packet as to the ray alone.
+fn check_packet(
+    object: &dyn Hitable,
+    rays: &[Option<Ray>],
+    t_max: Float,
+) -> Result<(), TestCaseError> {
+    let hits = object.hit_packet(&packet(rays), 0., &[t_max; WIDTH]);
+    for (lane, ray) in rays.iter().enumerate().take(WIDTH) {
+        let expected = ray.as_ref().and_then(|ray| object.hit(ray, 0., t_max));
+        match (expected, hits[lane]) {
+            (None, None) => {}
+            (Some(expected), Some(hit)) => {
+                prop_assert_eq!(expected.t, hit.t);
+                prop_assert_eq!(expected.p, hit.p);
+                prop_assert_eq!(expected.normal, hit.normal);
+            }
+            (expected, hit) => prop_assert!(
+                false,
+                "lane {}: expected a hit at {:?}, got {:?}",
+                lane,
+                expected.map(|hitpoint| hitpoint.t),
+                hit.map(|hitpoint| hitpoint.t)
+            ),
+        }
+    }
+    Ok(())
+}
+
+fn rays() -> impl Strategy<Value = Vec<Option<Ray>>> {
+    prop::collection::vec(prop::option::weighted(0.8, ray()), WIDTH)
+}
+
+proptest! {
+    #[test]
+    fn spheres_hit_packets_like_single_rays(rays in rays(), t_max in 1.0..20.0 as Float) {
+        let sphere = Sphere::new(Point3::origin(), 1., material());
+        check_packet(&sphere, &rays, t_max)?;
+    }
+
+    #[test]
+    fn meshes_hit_packets_like_single_rays(rays in rays(), t_max in 1.0..20.0 as Float) {
+        let geometry =
+            Geometry::load_obj("tests/scenes/cube.obj").expect("the test cube can be loaded");
+        let cube = Mesh::new(Arc::new(geometry), material());
+        check_packet(&cube, &rays, t_max)?;
+    }
+
+    #[test]
+    fn lists_hit_packets_like_single_rays(
+        rays in rays(),
+        centers in prop::collection::vec(unit_vector(), 1..8),
+    ) {
+        let spheres: Vec<Sphere> = centers
+            .iter()
+            .map(|center| Sphere::new(Point3::from(*center), 0.5, material()))
+            .collect();
+        check_packet(&spheres, &rays, Float::INFINITY)?;
+    }
+}
+
+#[test]
+fn renders_with_and_without_packets_are_identical() {
+    let scene = Scene::random(50, 1, &CameraSettings::default(), 2.).unwrap();
+    let render = |packets: bool| {
+        Renderer::new(Resolution {
+            width: 64,
+            height: 32,
+        })
+        .samples(3)
+        .seed(1)
+        .packets(packets)
+        .render(&scene)
+        .unwrap()
+    };
+    assert_eq!(render(true).pixels(), render(false).pixels());
+}
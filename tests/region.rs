@@ -0,0 +1,83 @@
+//! Regions of an image, rendered separately and stitched together, give the same image as a full
+//! render, without seams: the samples just outside a region still reach the pixels at its edges.
+
+use rtow::camera::CameraSettings;
+use rtow::filter::{Filter, FilterKind};
+use rtow::float::Float;
+use rtow::{Framebuffer, Region, Renderer, Resolution, Scene};
+
+const WIDTH: u32 = 40;
+const HEIGHT: u32 = 24;
+
+/// The difference allowed between the stitched and the full image, since the splats are summed
+/// up in another order.
+#[cfg(not(feature = "f32"))]
+const TOLERANCE: Float = 1e-9;
+#[cfg(feature = "f32")]
+const TOLERANCE: Float = 1e-4;
+
+fn render(scene: &Scene, filter: Filter, region: Region) -> Framebuffer {
+    Renderer::new(Resolution {
+        width: WIDTH,
+        height: HEIGHT,
+    })
+    .samples(2)
+    .seed(3)
+    .filter(filter)
+    .region(region)
+    .render(scene)
+    .unwrap()
+}
+
+#[test]
+fn stitched_regions_equal_the_full_render() {
+    let scene = Scene::random(
+        20,
+        5,
+        &CameraSettings::default(),
+        WIDTH as Float / HEIGHT as Float,
+    )
+    .unwrap();
+    // Splits which do not line up with the tiles of the renderer.
+    let columns = [(0, 13), (13, 20), (33, 7)];
+    let rows = [(0, 9), (9, 15)];
+
+    for kind in [FilterKind::Box, FilterKind::Mitchell, FilterKind::Lanczos] {
+        let filter = Filter::new(kind);
+        let full = render(
+            &scene,
+            filter,
+            Region {
+                x: 0,
+                y: 0,
+                width: WIDTH,
+                height: HEIGHT,
+            },
+        );
+        for (x, width) in columns {
+            for (y, height) in rows {
+                let region = Region {
+                    x,
+                    y,
+                    width,
+                    height,
+                };
+                let part = render(&scene, filter, region);
+                for dy in 0..height {
+                    for dx in 0..width {
+                        let difference = (part.pixel(dx, dy) - full.pixel(x + dx, y + dy)).amax();
+                        assert!(
+                            difference <= TOLERANCE,
+                            "{:?} filter: pixel ({}, {}) of {:?} differs by {}",
+                            kind,
+                            x + dx,
+                            y + dy,
+                            region,
+                            difference
+                        );
+                    }
+                }
+            }
+        }
+    }
+}
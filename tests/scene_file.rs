@@ -0,0 +1,42 @@
+//! The assets of a scene file (meshes, images and aperture masks) are found next to it, whatever
+//! the working directory.
+
+use rtow::SceneFile;
+use std::fs;
+use std::path::Path;
+
+#[test]
+fn assets_are_relative_to_the_scene_file() {
+    let directory = Path::new(env!("CARGO_TARGET_TMPDIR")).join("scene_file");
+    fs::create_dir_all(&directory).expect("the target directory is writable");
+    fs::copy("tests/scenes/cube.obj", directory.join("cube.obj")).expect("the cube exists");
+    for name in ["label.png", "mask.png"] {
+        image::RgbImage::from_pixel(2, 2, image::Rgb([200, 100, 50]))
+            .save(directory.join(name))
+            .expect("the target directory is writable");
+    }
+    let scene = directory.join("scene.toml");
+    fs::write(
+        &scene,
+        r#"
+        [camera]
+        aperture = 0.1
+        aperture_mask = "mask.png"
+
+        [[spheres]]
+        center = [0, 1, -4]
+        radius = 1
+        material = { type = "textured", path = "label.png", base = { type = "lambertian", attenuation = [1, 1, 1] } }
+
+        [[meshes]]
+        path = "cube.obj"
+        material = { type = "lambertian", attenuation = [0.5, 0.5, 0.5] }
+        "#,
+    )
+    .expect("the target directory is writable");
+
+    let file = SceneFile::load(&scene).unwrap_or_else(|err| panic!("{}", err));
+    assert_eq!(file.meshes[0].path, directory.join("cube.obj"));
+    assert_eq!(file.camera.aperture_mask, Some(directory.join("mask.png")));
+    assert!(file.into_scene(None, &Default::default(), 1.).is_ok());
+}
@@ -0,0 +1,201 @@
+//! Properties of the intersections of rays with spheres, and of spheres with each other.
+
+use nalgebra::{Point3, Vector3};
+use proptest::prelude::*;
+use rtow::float::{consts, Float};
+use rtow::hitable::Hitable;
+use rtow::material::Material;
+use rtow::ray::Ray;
+use rtow::sphere::Sphere;
+
+/// The relative error of a few arithmetic operations.
+#[cfg(not(feature = "f32"))]
+const ROUNDING: Float = 1e-12;
+#[cfg(feature = "f32")]
+const ROUNDING: Float = 1e-5;
+/// The relative error of longer computations, with square roots and cancellations.
+#[cfg(not(feature = "f32"))]
+const ACCURACY: Float = 1e-9;
+#[cfg(feature = "f32")]
+const ACCURACY: Float = 1e-3;
+/// The relative distance from a hit beyond which an interval certainly excludes it, despite the
+/// error bounds of the distances.
+#[cfg(not(feature = "f32"))]
+const MARGIN: Float = 1e-3;
+#[cfg(feature = "f32")]
+const MARGIN: Float = 1e-1;
+
+fn sphere(center: Point3<Float>, radius: Float) -> Sphere {
+    Sphere::new(
+        center,
+        radius,
+        Material::Lambertian {
+            attenuation: Vector3::new(0.5, 0.5, 0.5),
+        },
+    )
+}
+
+prop_compose! {
+    fn unit_vector()(z in -1.0..=1.0 as Float, phi in 0.0..2. * consts::PI) -> Vector3<Float> {
+        let r = (1. - z * z).sqrt();
+        Vector3::new(r * phi.cos(), r * phi.sin(), z)
+    }
+}
+
+prop_compose! {
+    fn point()(x in -100.0..100.0 as Float, y in -100.0..100.0 as Float, z in -100.0..100.0 as Float) -> Point3<Float> {
+        Point3::new(x, y, z)
+    }
+}
+
+prop_compose! {
+    /// A sphere of any reasonable size, somewhere near the origin.
+    fn any_sphere()(center in point(), radius in 1e-2..1e2 as Float) -> Sphere {
+        sphere(center, radius)
+    }
+}
+
+prop_compose! {
+    /// A sphere and a ray which starts outside of it, at up to a thousand radii away, and goes
+    /// towards a point near the sphere, so that it may or may not hit it.
+    fn ray_near_sphere()(
+        sphere in any_sphere(),
+        from in unit_vector(),
+        distance in 1.01..1e3 as Float,
+        aim in unit_vector(),
+        offset in 0.0..1.5 as Float,
+        speed in 1e-2..1e2 as Float,
+    ) -> (Sphere, Ray) {
+        let radius = sphere.radius();
+        let origin = sphere.center() + distance * radius * from;
+        let target = sphere.center() + offset * radius * aim;
+        let ray = Ray::new(origin, speed * (target - origin).normalize());
+        (sphere, ray)
+    }
+}
+
+/// How far from the surface a computed hit point may be, relative to the size of the scene.
+fn tolerance(sphere: &Sphere, ray: &Ray) -> Float {
+    ACCURACY * (sphere.radius() + (ray.origin() - sphere.center()).norm())
+}
+
+proptest! {
+    #[test]
+    fn hit_points_lie_on_the_surface((sphere, ray) in ray_near_sphere()) {
+        if let Some(hitpoint) = sphere.hit(&ray, 0., Float::INFINITY) {
+            let distance = (hitpoint.p - sphere.center()).norm();
+            prop_assert!((distance - sphere.radius()).abs() <= tolerance(&sphere, &ray));
+            // The reported error bound covers the distance from the surface.
+            prop_assert!((distance - sphere.radius()).abs() <= hitpoint.p_error.norm());
+            // The hit point is the point of the ray at `t` projected onto the sphere, so it lies
+            // off the ray by the distance of that point from the surface (due to the error of
+            // `t`), and otherwise only by its own error bound.
+            let on_ray = ray.point_at_parameter(hitpoint.t);
+            let off_surface = ((on_ray - sphere.center()).norm() - sphere.radius()).abs();
+            prop_assert!(off_surface <= tolerance(&sphere, &ray));
+            prop_assert!((hitpoint.p - on_ray).norm() <= off_surface + hitpoint.p_error.norm());
+        }
+    }
+
+    #[test]
+    fn normals_are_unit_and_point_outwards((sphere, ray) in ray_near_sphere()) {
+        if let Some(hitpoint) = sphere.hit(&ray, 0., Float::INFINITY) {
+            let outward = (hitpoint.p - sphere.center()).normalize();
+            prop_assert!((hitpoint.normal.norm() - 1.).abs() <= ROUNDING);
+            prop_assert!((hitpoint.normal.into_inner() - outward).norm() <= ACCURACY);
+            // Coming from outside, the ray hits the side which faces it.
+            prop_assert!(hitpoint.normal.dot(ray.direction()) <= 0.);
+        }
+    }
+
+    #[test]
+    fn rays_towards_the_center_hit((sphere, ray) in ray_near_sphere()) {
+        let towards_center = Ray::new(*ray.origin(), sphere.center() - ray.origin());
+        let hitpoint = sphere.hit(&towards_center, 0., Float::INFINITY);
+        prop_assert!(hitpoint.is_some());
+        // The direction is as long as the distance to the center.
+        let distance = (sphere.center() - ray.origin()).norm();
+        let expected = 1. - sphere.radius() / distance;
+        prop_assert!((hitpoint.unwrap().t - expected).abs() <= ACCURACY);
+    }
+
+    #[test]
+    fn rays_away_from_the_sphere_miss((sphere, ray) in ray_near_sphere()) {
+        let away = Ray::new(*ray.origin(), ray.origin() - sphere.center());
+        prop_assert!(sphere.hit(&away, 0., Float::INFINITY).is_none());
+    }
+
+    #[test]
+    fn hits_respect_the_interval(
+        (sphere, ray) in ray_near_sphere(),
+        a in 0.0..1e4 as Float,
+        b in 0.0..1e4 as Float,
+    ) {
+        let (t_min, t_max) = if a < b { (a, b) } else { (b, a) };
+        if let Some(hitpoint) = sphere.hit(&ray, t_min, t_max) {
+            prop_assert!(t_min <= hitpoint.t && hitpoint.t <= t_max);
+        }
+    }
+
+    #[test]
+    fn the_closest_hit_in_the_interval_is_chosen((sphere, ray) in ray_near_sphere()) {
+        let near = match sphere.hit(&ray, 0., Float::INFINITY) {
+            Some(hitpoint) => hitpoint.t,
+            None => return Ok(()),
+        };
+        // Nothing is hit before the sphere.
+        prop_assert!(sphere.hit(&ray, 0., (1. - MARGIN) * near).is_none());
+        // The near side is found in any interval around it.
+        let around = sphere.hit(&ray, (1. - MARGIN) * near, (1. + MARGIN) * near);
+        prop_assert!(around.is_some_and(|hitpoint| hitpoint.t == near));
+        // Past the near side, the ray leaves the sphere through the far side.
+        if let Some(far) = sphere.hit(&ray, (1. + MARGIN) * near, Float::INFINITY) {
+            prop_assert!(far.t > near);
+            prop_assert!(far.normal.dot(ray.direction()) >= 0.);
+            prop_assert!(sphere.hit(&ray, far.t * (1. + MARGIN), Float::INFINITY).is_none());
+        }
+    }
+
+    #[test]
+    fn rays_from_inside_hit_once(
+        sphere in any_sphere(),
+        offset in unit_vector(),
+        depth in 0.0..0.99 as Float,
+        direction in unit_vector(),
+    ) {
+        let origin = sphere.center() + depth * sphere.radius() * offset;
+        let ray = Ray::new(origin, direction);
+        let hitpoint = sphere.hit(&ray, 0., Float::INFINITY);
+        prop_assert!(hitpoint.is_some());
+        let hitpoint = hitpoint.unwrap();
+        prop_assert!(hitpoint.normal.dot(ray.direction()) >= 0.);
+        prop_assert!(sphere.hit(&ray, hitpoint.t * (1. + MARGIN), Float::INFINITY).is_none());
+    }
+
+    #[test]
+    fn intersects_is_symmetric(a in any_sphere(), b in any_sphere()) {
+        prop_assert_eq!(a.intersects(&b), b.intersects(&a));
+    }
+
+    #[test]
+    fn spheres_intersect_themselves(a in any_sphere()) {
+        prop_assert!(a.intersects(&a));
+    }
+
+    #[test]
+    fn intersects_compares_the_distance_with_the_radii(
+        a in any_sphere(),
+        direction in unit_vector(),
+        radius in 1e-2..1e2 as Float,
+        gap in -0.99..1.0 as Float,
+    ) {
+        // The second sphere is `gap` times the sum of the radii apart from the first one.
+        let sum = a.radius() + radius;
+        let b = sphere(a.center() + (1. + gap) * sum * direction, radius);
+        if gap < -ACCURACY {
+            prop_assert!(a.intersects(&b));
+        } else if gap > ACCURACY {
+            prop_assert!(!a.intersects(&b));
+        }
+    }
+}
@@ -0,0 +1,93 @@
+//! Images which hold colors are decoded into the working color space of the render; images
+//! which hold data (normal vectors, heights, opacities) are taken as stored.
+
+use nalgebra::{Point3, Unit, Vector2, Vector3};
+use rtow::color::{ColorSpace, Encoding};
+use rtow::float::Float;
+use rtow::hitable::HitPoint;
+use rtow::material::{Bsdf, Material};
+use rtow::spectrum::Wavelengths;
+use rtow::texture::ImageTexture;
+use std::path::Path;
+
+const TEXEL: [u8; 3] = [128, 64, 255];
+
+fn image() -> ImageTexture {
+    let path = Path::new(env!("CARGO_TARGET_TMPDIR")).join("texel.png");
+    image::RgbImage::from_pixel(1, 1, image::Rgb(TEXEL))
+        .save(&path)
+        .expect("the target directory is writable");
+    ImageTexture::load(&path).expect("the image was just written")
+}
+
+fn assert_close(actual: &Vector3<Float>, expected: &Vector3<Float>) {
+    assert!(
+        (actual - expected).amax() < 1e-4,
+        "{} is not close to {}",
+        actual,
+        expected
+    );
+}
+
+#[test]
+fn data_textures_are_not_decoded() {
+    let expected = Vector3::new(128., 64., 255.) / 255.;
+    assert_close(&image().texel(&Vector2::new(0.5, 0.5)), &expected);
+}
+
+#[test]
+fn color_textures_are_decoded_into_the_working_space() {
+    let uv = Vector2::new(0.5, 0.5);
+    let linear_srgb = image().color(&uv, ColorSpace::LinearSrgb, Encoding::Srgb);
+    assert_close(&linear_srgb, &Vector3::new(0.21586, 0.05127, 1.));
+
+    let acescg = image().color(&uv, ColorSpace::Acescg, Encoding::Srgb);
+    assert_close(
+        &acescg,
+        &ColorSpace::Acescg.decode_texel(TEXEL, Encoding::Srgb),
+    );
+    assert!((acescg - linear_srgb).amax() > 0.05);
+
+    let rec709 = image().color(&uv, ColorSpace::LinearSrgb, Encoding::Rec709);
+    assert!((rec709 - linear_srgb).amax() > 0.01);
+}
+
+#[test]
+fn textured_materials_tint_their_base_in_the_working_space() {
+    let base = Material::Lambertian {
+        attenuation: Vector3::new(1., 1., 1.),
+    };
+    let textured = Material::Textured {
+        base: Box::new(base.clone()),
+        image: image(),
+        encoding: Encoding::Srgb,
+    };
+    let hitpoint = |material| HitPoint {
+        t: 1.,
+        p: Point3::origin(),
+        p_error: Vector3::zeros(),
+        normal: Unit::new_unchecked(Vector3::z()),
+        geometric_normal: Unit::new_unchecked(Vector3::z()),
+        uv: Vector2::new(0.3, 0.6),
+        dpdu: Vector3::x(),
+        dpdv: Vector3::y(),
+        material,
+    };
+    let wo = Vector3::new(0.3, 0., 1.).normalize();
+    let wi = Vector3::new(-0.2, 0.4, 1.).normalize();
+
+    for space in [ColorSpace::LinearSrgb, ColorSpace::Acescg] {
+        let wavelengths = Wavelengths::Rgb { space };
+        let plain = base.eval(&wo, &wi, &hitpoint(&base), &wavelengths);
+        let tinted = textured.eval(&wo, &wi, &hitpoint(&textured), &wavelengths);
+        let color = space.decode_texel(TEXEL, Encoding::Srgb);
+        for i in 0..3 {
+            assert!(
+                (tinted[i] - plain[i] * color[i]).abs() < 1e-6,
+                "{:?}: channel {}",
+                space,
+                i
+            );
+        }
+    }
+}
@@ -0,0 +1,150 @@
+//! Properties of the reflection and refraction of rays and of Schlick's approximation.
+
+use nalgebra::Vector3;
+use proptest::prelude::*;
+use rtow::float::{consts, Float};
+use rtow::util;
+
+/// The relative error of a few arithmetic operations.
+#[cfg(not(feature = "f32"))]
+const ROUNDING: Float = 1e-12;
+#[cfg(feature = "f32")]
+const ROUNDING: Float = 1e-5;
+/// The relative error of longer computations, with square roots and cancellations.
+#[cfg(not(feature = "f32"))]
+const ACCURACY: Float = 1e-9;
+#[cfg(feature = "f32")]
+const ACCURACY: Float = 1e-3;
+/// The error of round trips through refraction, which amplifies errors near the critical angle.
+#[cfg(not(feature = "f32"))]
+const ROUND_TRIP: Float = 1e-6;
+#[cfg(feature = "f32")]
+const ROUND_TRIP: Float = 1e-2;
+
+prop_compose! {
+    /// A uniformly distributed unit vector.
+    fn unit_vector()(z in -1.0..=1.0 as Float, phi in 0.0..2. * consts::PI) -> Vector3<Float> {
+        let r = (1. - z * z).sqrt();
+        Vector3::new(r * phi.cos(), r * phi.sin(), z)
+    }
+}
+
+prop_compose! {
+    /// A vector of any reasonable length, pointing anywhere.
+    fn vector()(direction in unit_vector(), length in 1e-3..1e3 as Float) -> Vector3<Float> {
+        length * direction
+    }
+}
+
+prop_compose! {
+    /// A unit normal vector and a unit vector which goes into the surface, against the normal.
+    fn incident()(normal in unit_vector(), v in unit_vector()) -> (Vector3<Float>, Vector3<Float>) {
+        let v = if v.dot(&normal) > 0. { -v } else { v };
+        (normal, v)
+    }
+}
+
+/// The sine of the angle between the unit vector `v` and the line of the unit `normal`.
+fn sine(v: &Vector3<Float>, normal: &Vector3<Float>) -> Float {
+    v.cross(normal).norm()
+}
+
+proptest! {
+    #[test]
+    fn reflection_preserves_length(v in vector(), normal in unit_vector()) {
+        let reflected = util::reflection(&v, &normal);
+        prop_assert!((reflected.norm() - v.norm()).abs() <= ROUNDING * v.norm());
+    }
+
+    #[test]
+    fn reflection_mirrors_the_normal_component(v in vector(), normal in unit_vector()) {
+        let reflected = util::reflection(&v, &normal);
+        let tolerance = ROUNDING * v.norm();
+        prop_assert!((reflected.dot(&normal) + v.dot(&normal)).abs() <= tolerance);
+        prop_assert!((reflected - v).cross(&normal).norm() <= tolerance);
+    }
+
+    #[test]
+    fn reflection_is_an_involution(v in vector(), normal in unit_vector()) {
+        let twice = util::reflection(&util::reflection(&v, &normal), &normal);
+        prop_assert!((twice - v).norm() <= ROUNDING * v.norm());
+    }
+
+    #[test]
+    fn refraction_obeys_snells_law(
+        (normal, v) in incident(),
+        length in 1e-3..1e3 as Float,
+        ratio in 0.2..5.0 as Float,
+    ) {
+        match util::refraction(&(length * v), &normal, ratio) {
+            Some(refracted) => {
+                prop_assert!((refracted.norm() - 1.).abs() <= ACCURACY);
+                prop_assert!(refracted.dot(&normal) <= 0., "the ray must go through the surface");
+                prop_assert!((sine(&refracted, &normal) - ratio * sine(&v, &normal)).abs() <= ACCURACY);
+                // The incident ray, the refracted ray and the normal lie in the same plane.
+                prop_assert!(v.cross(&normal).dot(&refracted).abs() <= ACCURACY);
+            }
+            None => prop_assert!(ratio * sine(&v, &normal) >= 1. - ACCURACY),
+        }
+    }
+
+    #[test]
+    fn refraction_beyond_the_critical_angle_is_total_internal_reflection(
+        normal in unit_vector(),
+        ratio in 1.01..5.0 as Float,
+        beyond in ACCURACY..1.0 as Float,
+        phi in 0.0..2. * consts::PI,
+    ) {
+        // The sine of the angle of incidence, between the critical one and grazing. It is
+        // generated rather than filtered from random incident vectors, about half of which
+        // would be rejected.
+        let sine = 1. / ratio + beyond * (1. - 1. / ratio);
+        let (tangent, bitangent) = util::orthonormal_basis(&normal);
+        let along = phi.cos() * tangent + phi.sin() * bitangent;
+        let v = sine * along - (1. - sine * sine).sqrt() * normal;
+        prop_assert!(util::refraction(&v, &normal, ratio).is_none());
+    }
+
+    #[test]
+    fn refraction_without_a_change_of_index_goes_straight((normal, v) in incident()) {
+        let refracted = util::refraction(&v, &normal, 1.);
+        prop_assume!(refracted.is_some());
+        prop_assert!((refracted.unwrap() - v).norm() <= ACCURACY);
+    }
+
+    #[test]
+    fn refraction_is_reversible((normal, v) in incident(), ratio in 0.2..5.0 as Float) {
+        if let Some(refracted) = util::refraction(&v, &normal, ratio) {
+            // Going back along the refracted ray leads back along the incident ray.
+            let back = util::refraction(&-refracted, &-normal, 1. / ratio);
+            prop_assume!(back.is_some());
+            prop_assert!((back.unwrap() + v).norm() <= ROUND_TRIP);
+        }
+    }
+
+    #[test]
+    fn schlick_is_a_reflectance(cosine in 0.0..=1.0 as Float, refractive_index in 1.0..3.0 as Float) {
+        let r0 = ((1. - refractive_index) / (1. + refractive_index)).powi(2);
+        let reflectance = util::schlick(cosine, refractive_index);
+        prop_assert!(r0 - ROUNDING <= reflectance && reflectance <= 1.);
+    }
+
+    #[test]
+    fn schlick_decreases_towards_normal_incidence(
+        a in 0.0..=1.0 as Float,
+        b in 0.0..=1.0 as Float,
+        refractive_index in 1.0..3.0 as Float,
+    ) {
+        let (low, high) = if a < b { (a, b) } else { (b, a) };
+        prop_assert!(
+            util::schlick(low, refractive_index) >= util::schlick(high, refractive_index)
+        );
+    }
+}
+
+#[test]
+fn schlick_at_the_extremes() {
+    // Glass reflects 4% of the light at normal incidence, and all of it at grazing angles.
+    assert!((util::schlick(1., 1.5) - 0.04).abs() < ROUNDING);
+    assert_eq!(util::schlick(0., 1.5), 1.);
+}
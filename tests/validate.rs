@@ -0,0 +1,227 @@
+//! Scene validation finds the mistakes of a scene, names the objects they are about, and keeps
+//! quiet about the scenes which are fine.
+
+use nalgebra::{Point3, Vector3};
+use rtow::camera::{Camera, CameraSettings, Lens, Orientation};
+use rtow::float::Float;
+use rtow::material::Material;
+use rtow::mesh::{Geometry, Mesh};
+use rtow::sphere::Sphere;
+use rtow::validate::{validate, Issue, Object, Severity};
+use rtow::{Scene, SceneFile};
+use std::sync::Arc;
+
+fn camera(look_from: Point3<Float>) -> Camera {
+    Camera::new(
+        Orientation {
+            look_from,
+            look_at: Point3::new(0., 0., -4.),
+            upwards: Vector3::y(),
+        },
+        Lens {
+            aperture: 0.,
+            diaphragm: Default::default(),
+            focal_length: 1.,
+            vertical_fov: 60.,
+            aspect_ratio: 2.,
+        },
+    )
+}
+
+fn gray() -> Material {
+    Material::Lambertian {
+        attenuation: Vector3::new(0.5, 0.5, 0.5),
+    }
+}
+
+fn sphere(x: Float, radius: Float, material: Material) -> Sphere {
+    Sphere::new(Point3::new(x, 0., -4.), radius, material)
+}
+
+fn issue(severity: Severity, object: Object, message: &str) -> Issue {
+    Issue {
+        severity,
+        object,
+        message: String::from(message),
+    }
+}
+
+#[test]
+fn random_scenes_have_no_issues() {
+    for seed in 0..3 {
+        let scene = Scene::random(500, seed, &CameraSettings::default(), 2.).unwrap();
+        assert_eq!(validate(&scene), vec![], "seed {}", seed);
+    }
+}
+
+#[test]
+fn touching_spheres_do_not_overlap() {
+    let scene = Scene::new(camera(Point3::origin()))
+        .with_object(sphere(0., 1., gray()))
+        .with_object(sphere(1.5, 0.5, gray()));
+    assert_eq!(validate(&scene), vec![]);
+}
+
+#[test]
+fn overlapping_spheres_are_reported() {
+    let scene = Scene::new(camera(Point3::origin()))
+        .with_object(sphere(-0.25, 0.5, gray()))
+        .with_object(sphere(0., 1., gray()))
+        .with_object(sphere(1.5, 1., gray()));
+    assert_eq!(
+        validate(&scene),
+        vec![
+            issue(
+                Severity::Warning,
+                Object::Sphere(0),
+                "lies inside sphere #1"
+            ),
+            issue(
+                Severity::Warning,
+                Object::Sphere(1),
+                "sinks 0.5 deep into sphere #2"
+            ),
+        ]
+    );
+}
+
+#[test]
+fn degenerate_spheres_are_reported() {
+    let scene = Scene::new(camera(Point3::origin()))
+        .with_object(sphere(0., Float::NAN, gray()))
+        .with_object(sphere(3., -0.5, gray()))
+        .with_object(sphere(6., 0., gray()));
+    assert_eq!(
+        validate(&scene),
+        vec![
+            issue(
+                Severity::Error,
+                Object::Sphere(0),
+                "the radius NaN is not a finite number"
+            ),
+            issue(
+                Severity::Warning,
+                Object::Sphere(1),
+                "the radius -0.5 is negative, which turns the sphere inside out"
+            ),
+            issue(
+                Severity::Warning,
+                Object::Sphere(2),
+                "the radius is 0, so the sphere cannot be seen"
+            ),
+        ]
+    );
+}
+
+#[test]
+fn materials_are_checked_down_to_their_layers() {
+    let bright = Material::Metal {
+        attenuation: Vector3::new(1.2, 0.5, 0.5),
+        fuzziness: 0.,
+        conductor: None,
+    };
+    let scene = Scene::new(camera(Point3::origin()))
+        .with_object(sphere(0., 1., bright))
+        .with_object(sphere(
+            3.,
+            1.,
+            Material::ClearCoat {
+                base: Box::new(Material::Dielectric {
+                    refractive_index: 0.8,
+                    abbe_number: None,
+                    thin_film: None,
+                }),
+                refractive_index: 1.5,
+            },
+        ))
+        .with_object(sphere(
+            6.,
+            1.,
+            Material::Subsurface {
+                refractive_index: 0.,
+                mean_free_path: Vector3::new(0.1, 0.1, 0.1),
+                albedo: Vector3::new(0.5, -0.5, 0.5),
+                anisotropy: 0.,
+            },
+        ));
+    assert_eq!(
+        validate(&scene),
+        vec![
+            issue(
+                Severity::Warning,
+                Object::Sphere(0),
+                "`material.attenuation` [1.2, 0.5, 0.5] is above 1, so it reflects more light \
+                 than it receives"
+            ),
+            issue(
+                Severity::Warning,
+                Object::Sphere(1),
+                "`material.base.refractive_index` 0.8 is below 1, the refractive index of vacuum"
+            ),
+            issue(
+                Severity::Error,
+                Object::Sphere(2),
+                "`material.albedo` [0.5, -0.5, 0.5] is negative or not a number"
+            ),
+            issue(
+                Severity::Error,
+                Object::Sphere(2),
+                "`material.refractive_index` 0 is not positive"
+            ),
+        ]
+    );
+}
+
+#[test]
+fn cameras_inside_geometry_are_reported() {
+    let cube = || Geometry::load_obj("tests/scenes/cube.obj").expect("the test cube can be loaded");
+    let mut scene = Scene::new(camera(Point3::origin()))
+        .with_object(sphere(0., 10., gray()))
+        .with_object(sphere(0., -5., gray()));
+    scene.add_mesh(Mesh::new(Arc::new(cube()), gray()));
+    let mut outside = cube();
+    outside.transform(1., &Vector3::new(0., 0., -4.));
+    scene.add_mesh(Mesh::new(Arc::new(outside), gray()));
+
+    let camera_issues: Vec<Issue> = validate(&scene)
+        .into_iter()
+        .filter(|issue| issue.message.contains("camera"))
+        .collect();
+    assert_eq!(
+        camera_issues,
+        vec![
+            issue(
+                Severity::Warning,
+                Object::Sphere(0),
+                "the camera is inside it"
+            ),
+            issue(
+                Severity::Warning,
+                Object::Mesh(0),
+                "the camera is inside it"
+            ),
+        ]
+    );
+}
+
+#[test]
+fn scene_files_name_the_objects() {
+    let file: SceneFile = toml::from_str(
+        r#"
+        [[spheres]]
+        name = "ball"
+        center = [0, 1, -4]
+        radius = 1
+        material = { type = "lambertian", attenuation = [0.5, 0.5, 0.5] }
+
+        [[spheres]]
+        center = [0, 3, -4]
+        radius = 1
+        material = { type = "lambertian", attenuation = [0.5, 0.5, 0.5] }
+        "#,
+    )
+    .unwrap();
+    assert_eq!(file.describe(&Object::Sphere(0)), "sphere #0 (\"ball\")");
+    assert_eq!(file.describe(&Object::Sphere(1)), "sphere #1");
+    assert_eq!(file.describe(&Object::Camera), "camera");
+}